@@ -26,7 +26,7 @@ fn example_1_simple_calculation() {
     println!("Example 1: Simple Calculation");
     println!("------------------------------");
 
-    let mut engine = Engine::new();
+    let engine = Engine::new();
     let formula = Formula::new("calc", "return (5 + 3) * 2 - 1");
 
     engine.execute(vec![formula]).unwrap();
@@ -42,7 +42,7 @@ fn example_2_variables() {
     println!("Example 2: Using Variables");
     println!("--------------------------");
 
-    let mut engine = Engine::new();
+    let engine = Engine::new();
 
     // Set variables
     engine.set_variable("price".to_string(), Value::Number(100.0));
@@ -103,7 +103,7 @@ fn example_4_dependencies() {
     println!("Example 4: Formula Dependencies");
     println!("-------------------------------");
 
-    let mut engine = Engine::new();
+    let engine = Engine::new();
 
     // Create formulas with dependencies
     let base = Formula::new("base_amount", "return 1000");
@@ -141,7 +141,7 @@ fn example_5_builtin_functions() {
     println!("Example 5: Built-in Functions");
     println!("-----------------------------");
 
-    let mut engine = Engine::new();
+    let engine = Engine::new();
 
     let formulas = vec![
         Formula::new("max_test", "return max(10, 25)"),
@@ -185,7 +185,7 @@ fn example_6_strings() {
     println!("Example 6: String Operations");
     println!("----------------------------");
 
-    let mut engine = Engine::new();
+    let engine = Engine::new();
 
     engine.set_variable("first_name".to_string(), Value::String("John".to_string()));
     engine.set_variable("last_name".to_string(), Value::String("Doe".to_string()));
@@ -1,5 +1,4 @@
-use formcalc::{Engine, Formula};
-use std::time::Instant;
+use formcalc::{Engine, ExecutionReport, Formula};
 
 fn main() {
     println!("=== Parallel Execution Benchmark ===\n");
@@ -14,11 +13,37 @@ fn main() {
     benchmark_complex_formulas();
 }
 
+/// Builds an engine that records per-layer and per-formula timings.
+fn timed_engine() -> Engine {
+    Engine::builder().collect_run_stats(true).build().unwrap()
+}
+
+/// Prints the layer breakdown and the slowest formulas of a run.
+fn print_stats(report: &ExecutionReport) {
+    let stats = report.stats().expect("run stats are enabled");
+    for (index, layer) in stats.layers().iter().enumerate() {
+        println!(
+            "  Layer {}: {} formulas in {:?}",
+            index, layer.size, layer.duration
+        );
+    }
+    println!("  Slowest formulas:");
+    for (name, timing) in stats.slowest(3) {
+        println!(
+            "    {}: {:?} (parse {:?}, eval {:?})",
+            name,
+            timing.total(),
+            timing.parse,
+            timing.eval
+        );
+    }
+}
+
 fn benchmark_independent_formulas() {
     println!("Test 1: Independent Formulas");
     println!("-----------------------------");
 
-    let mut engine = Engine::new();
+    let engine = timed_engine();
 
     // Create 100 independent formulas
     let formulas: Vec<Formula> = (0..100)
@@ -30,11 +55,13 @@ fn benchmark_independent_formulas() {
         })
         .collect();
 
-    let start = Instant::now();
-    engine.execute(formulas).unwrap();
-    let duration = start.elapsed();
+    let report = engine.execute(formulas).unwrap();
 
-    println!("Executed 100 independent formulas in {:?}", duration);
+    println!(
+        "Executed 100 independent formulas in {:?}",
+        report.duration()
+    );
+    print_stats(&report);
     println!("All formulas executed in parallel (single layer)\n");
 }
 
@@ -42,7 +69,7 @@ fn benchmark_layered_dependencies() {
     println!("Test 2: Layered Dependencies");
     println!("-----------------------------");
 
-    let mut engine = Engine::new();
+    let engine = timed_engine();
 
     // Create a dependency tree:
     // Layer 0: 20 base formulas
@@ -76,11 +103,13 @@ fn benchmark_layered_dependencies() {
         ));
     }
 
-    let start = Instant::now();
-    engine.execute(formulas).unwrap();
-    let duration = start.elapsed();
+    let report = engine.execute(formulas).unwrap();
 
-    println!("Executed 60 formulas in 3 layers in {:?}", duration);
+    println!(
+        "Executed 60 formulas in 3 layers in {:?}",
+        report.duration()
+    );
+    print_stats(&report);
     println!("20 formulas per layer executed in parallel\n");
 }
 
@@ -88,7 +117,7 @@ fn benchmark_complex_formulas() {
     println!("Test 3: Complex Formulas");
     println!("------------------------");
 
-    let mut engine = Engine::new();
+    let engine = timed_engine();
 
     // Create formulas with more complex calculations
     let formulas: Vec<Formula> = (0..50)
@@ -103,10 +132,9 @@ fn benchmark_complex_formulas() {
         })
         .collect();
 
-    let start = Instant::now();
-    engine.execute(formulas).unwrap();
-    let duration = start.elapsed();
+    let report = engine.execute(formulas).unwrap();
 
-    println!("Executed 50 complex formulas in {:?}", duration);
+    println!("Executed 50 complex formulas in {:?}", report.duration());
+    print_stats(&report);
     println!("All formulas executed in parallel with conditional logic\n");
 }
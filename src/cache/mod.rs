@@ -1,18 +1,86 @@
-use crate::function::Function;
+use crate::function::{AsyncFunction, Function};
 use crate::value::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 
+/// Hit and miss counts recorded by a result cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Fraction of lookups that were hits, or `0.0` before any lookup
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+/// Lock-free hit/miss counters shared by every clone of a cache
+#[derive(Debug, Default)]
+struct CacheCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CacheCounters {
+    fn record(&self, value: Option<Value>) -> Option<Value> {
+        let counter = if value.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        value
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    fn reset(&self) {
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+    }
+}
+
 /// Cache for storing variables
+///
+/// A cache created by [`VariableCache::with_overrides`] is a layered view: its
+/// overrides shadow the shared entries on lookup, and writes go to the shared entries.
 #[derive(Debug, Clone, Default)]
 pub struct VariableCache {
     cache: Arc<RwLock<HashMap<String, Value>>>,
+    overrides: Arc<HashMap<String, Value>>,
 }
 
 impl VariableCache {
     pub fn new() -> Self {
         Self {
             cache: Arc::new(RwLock::new(HashMap::new())),
+            overrides: Arc::new(HashMap::new()),
+        }
+    }
+
+    /// Returns a view of this cache in which `overrides` shadow its entries.
+    ///
+    /// The overrides are private to the view; the cache itself is left unchanged.
+    pub fn with_overrides(&self, overrides: HashMap<String, Value>) -> Self {
+        let mut layered = self.overrides.as_ref().clone();
+        layered.extend(overrides);
+        Self {
+            cache: self.cache.clone(),
+            overrides: Arc::new(layered),
         }
     }
 
@@ -20,25 +88,48 @@ impl VariableCache {
         self.cache.write().unwrap().insert(key, value);
     }
 
+    /// Inserts every entry of `entries` under a single write lock, so readers see
+    /// either none or all of them.
+    pub fn set_many(&self, entries: HashMap<String, Value>) {
+        self.cache.write().unwrap().extend(entries);
+    }
+
     pub fn get(&self, key: &str) -> Option<Value> {
-        self.cache.read().unwrap().get(key).cloned()
+        match self.overrides.get(key) {
+            Some(value) => Some(value.clone()),
+            None => self.cache.read().unwrap().get(key).cloned(),
+        }
     }
 
     pub fn clear(&self) {
         self.cache.write().unwrap().clear();
     }
+
+    /// Returns a copy of every cached entry, with any overrides applied.
+    pub fn entries(&self) -> HashMap<String, Value> {
+        let mut entries = self.cache.read().unwrap().clone();
+        entries.extend(self.overrides.iter().map(|(k, v)| (k.clone(), v.clone())));
+        entries
+    }
+
+    /// Replaces all cached entries with `entries`.
+    pub fn replace(&self, entries: HashMap<String, Value>) {
+        *self.cache.write().unwrap() = entries;
+    }
 }
 
 /// Cache for storing formula results
 #[derive(Debug, Clone, Default)]
 pub struct FormulaResultCache {
     cache: Arc<RwLock<HashMap<String, Value>>>,
+    counters: Arc<CacheCounters>,
 }
 
 impl FormulaResultCache {
     pub fn new() -> Self {
         Self {
             cache: Arc::new(RwLock::new(HashMap::new())),
+            counters: Arc::new(CacheCounters::default()),
         }
     }
 
@@ -47,11 +138,97 @@ impl FormulaResultCache {
     }
 
     pub fn get(&self, formula_name: &str) -> Option<Value> {
+        self.counters.record(self.peek(formula_name))
+    }
+
+    /// Returns the cached value without recording a hit or miss.
+    pub fn peek(&self, formula_name: &str) -> Option<Value> {
         self.cache.read().unwrap().get(formula_name).cloned()
     }
 
+    pub fn remove(&self, formula_name: &str) -> Option<Value> {
+        self.cache.write().unwrap().remove(formula_name)
+    }
+
+    /// Removes every entry and resets the hit/miss counters.
     pub fn clear(&self) {
         self.cache.write().unwrap().clear();
+        self.counters.reset();
+    }
+
+    /// Returns a copy of every cached entry.
+    pub fn entries(&self) -> HashMap<String, Value> {
+        self.cache.read().unwrap().clone()
+    }
+
+    /// Replaces all cached entries with `entries`.
+    pub fn replace(&self, entries: HashMap<String, Value>) {
+        *self.cache.write().unwrap() = entries;
+    }
+
+    /// Returns the hit/miss counts recorded by [`FormulaResultCache::get`].
+    pub fn stats(&self) -> CacheStats {
+        self.counters.stats()
+    }
+}
+
+/// Cache for storing formula errors and unresolved dependencies
+#[derive(Debug, Clone, Default)]
+pub struct ErrorCache {
+    errors: Arc<RwLock<HashMap<String, String>>>,
+    /// Formulas left out of the execution order, with the dependencies they were missing
+    unresolved: Arc<RwLock<HashMap<String, Vec<String>>>>,
+}
+
+impl ErrorCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, formula_name: String, message: String) {
+        self.errors.write().unwrap().insert(formula_name, message);
+    }
+
+    pub fn contains(&self, formula_name: &str) -> bool {
+        self.errors.read().unwrap().contains_key(formula_name)
+    }
+
+    pub fn remove(&self, formula_name: &str) -> Option<String> {
+        self.errors.write().unwrap().remove(formula_name)
+    }
+
+    pub fn set_unresolved(&self, formula_name: String, missing: Vec<String>) {
+        self.unresolved
+            .write()
+            .unwrap()
+            .insert(formula_name, missing);
+    }
+
+    pub fn remove_unresolved(&self, formula_name: &str) -> Option<Vec<String>> {
+        self.unresolved.write().unwrap().remove(formula_name)
+    }
+
+    /// Returns a copy of every recorded error.
+    pub fn errors(&self) -> HashMap<String, String> {
+        self.errors.read().unwrap().clone()
+    }
+
+    /// Returns a copy of every recorded set of unresolved dependencies.
+    pub fn unresolved(&self) -> HashMap<String, Vec<String>> {
+        self.unresolved.read().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.errors.write().unwrap().clear();
+        self.unresolved.write().unwrap().clear();
+    }
+
+    /// Returns a cache with the same entries that no longer shares state with this one.
+    pub fn fork(&self) -> Self {
+        Self {
+            errors: Arc::new(RwLock::new(self.errors())),
+            unresolved: Arc::new(RwLock::new(self.unresolved())),
+        }
     }
 }
 
@@ -76,34 +253,218 @@ impl FunctionCache {
         self.cache.read().unwrap().get(function_id).cloned()
     }
 
+    /// Removes a registration, returning `true` if one existed.
+    pub fn remove(&self, function_id: &str) -> bool {
+        self.cache.write().unwrap().remove(function_id).is_some()
+    }
+
+    /// Returns a cache with the same registrations that no longer shares state with this one.
+    pub fn fork(&self) -> Self {
+        Self {
+            cache: Arc::new(RwLock::new(self.cache.read().unwrap().clone())),
+        }
+    }
+
+    pub fn clear(&self) {
+        self.cache.write().unwrap().clear();
+    }
+}
+
+/// Cache for storing async functions by their ID (name_numargs)
+#[derive(Clone, Default)]
+pub struct AsyncFunctionCache {
+    cache: Arc<RwLock<HashMap<String, Arc<dyn AsyncFunction>>>>,
+}
+
+impl AsyncFunctionCache {
+    pub fn new() -> Self {
+        Self {
+            cache: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    pub fn set(&self, function_id: String, function: Arc<dyn AsyncFunction>) {
+        self.cache.write().unwrap().insert(function_id, function);
+    }
+
+    pub fn get(&self, function_id: &str) -> Option<Arc<dyn AsyncFunction>> {
+        self.cache.read().unwrap().get(function_id).cloned()
+    }
+
+    /// Removes a registration, returning `true` if one existed.
+    pub fn remove(&self, function_id: &str) -> bool {
+        self.cache.write().unwrap().remove(function_id).is_some()
+    }
+
+    /// Returns a cache with the same registrations that no longer shares state with this one.
+    pub fn fork(&self) -> Self {
+        Self {
+            cache: Arc::new(RwLock::new(self.cache.read().unwrap().clone())),
+        }
+    }
+
     pub fn clear(&self) {
         self.cache.write().unwrap().clear();
     }
 }
 
+/// Entries of a [`FunctionResultCache`] with their least-recently-used ordering
+#[derive(Debug, Default)]
+struct LruEntries {
+    /// Each value with the tick of its last access
+    entries: HashMap<String, (Value, u64)>,
+    /// Keys ordered by last access, oldest first
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+    capacity: Option<usize>,
+}
+
+impl LruEntries {
+    fn touch(&mut self, key: &str) -> Option<Value> {
+        let (value, last_used) = self.entries.get_mut(key)?;
+        self.tick += 1;
+        let key = self.recency.remove(last_used)?;
+        *last_used = self.tick;
+        self.recency.insert(self.tick, key);
+        Some(value.clone())
+    }
+
+    fn insert(&mut self, key: String, value: Value) {
+        if self.capacity == Some(0) {
+            return;
+        }
+
+        self.tick += 1;
+        if let Some((_, last_used)) = self.entries.insert(key.clone(), (value, self.tick)) {
+            self.recency.remove(&last_used);
+        }
+        self.recency.insert(self.tick, key);
+
+        if let Some(capacity) = self.capacity {
+            while self.entries.len() > capacity {
+                if let Some((_, oldest)) = self.recency.pop_first() {
+                    self.entries.remove(&oldest);
+                }
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some((_, last_used)) = self.entries.remove(key) {
+            self.recency.remove(&last_used);
+        }
+    }
+
+    fn retain(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        let recency = &mut self.recency;
+        self.entries.retain(|key, (_, last_used)| {
+            let kept = keep(key);
+            if !kept {
+                recency.remove(last_used);
+            }
+            kept
+        });
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+}
+
 /// Cache for storing function results
+///
+/// Results are keyed by function id and argument values, see [`FunctionResultCache::key`].
+/// Unbounded by default; [`FunctionResultCache::with_capacity`] limits the number of
+/// entries, evicting the least recently used one when full.
 #[derive(Debug, Clone, Default)]
 pub struct FunctionResultCache {
-    cache: Arc<RwLock<HashMap<String, Value>>>,
+    cache: Arc<RwLock<LruEntries>>,
+    counters: Arc<CacheCounters>,
 }
 
 impl FunctionResultCache {
     pub fn new() -> Self {
         Self {
-            cache: Arc::new(RwLock::new(HashMap::new())),
+            cache: Arc::new(RwLock::new(LruEntries::default())),
+            counters: Arc::new(CacheCounters::default()),
         }
     }
 
+    /// Builds the key under which the result of calling `function_id` with `args` is stored.
+    pub fn key(function_id: &str, args: &[Value]) -> String {
+        let args: Vec<String> = args.iter().map(|arg| format!("{:?}", arg)).collect();
+        format!("{}({})", function_id, args.join(", "))
+    }
+
+    /// Creates a cache holding at most `capacity` entries.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let cache = Self::new();
+        cache.cache.write().unwrap().capacity = Some(capacity);
+        cache
+    }
+
+    /// Returns the maximum number of entries, or `None` if unbounded.
+    pub fn capacity(&self) -> Option<usize> {
+        self.cache.read().unwrap().capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.read().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn set(&self, key: String, value: Value) {
         self.cache.write().unwrap().insert(key, value);
     }
 
+    /// Returns the cached value, marking it as most recently used.
     pub fn get(&self, key: &str) -> Option<Value> {
-        self.cache.read().unwrap().get(key).cloned()
+        let value = {
+            let cache = self.cache.read().unwrap();
+            if cache.capacity.is_none() {
+                // Recency only matters for eviction, so unbounded lookups stay shared
+                cache.entries.get(key).map(|(value, _)| value.clone())
+            } else {
+                drop(cache);
+                self.cache.write().unwrap().touch(key)
+            }
+        };
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            function = key,
+            hit = value.is_some(),
+            "function result lookup"
+        );
+        self.counters.record(value)
     }
 
+    /// Removes the entry for `key`, if any, leaving the hit/miss counters untouched.
+    pub fn remove(&self, key: &str) {
+        self.cache.write().unwrap().remove(key);
+    }
+
+    /// Removes the results of `function_id` for every argument set.
+    pub fn remove_function(&self, function_id: &str) {
+        let prefix = format!("{}(", function_id);
+        self.cache
+            .write()
+            .unwrap()
+            .retain(|key| !key.starts_with(&prefix));
+    }
+
+    /// Removes every entry and resets the hit/miss counters.
     pub fn clear(&self) {
         self.cache.write().unwrap().clear();
+        self.counters.reset();
+    }
+
+    /// Returns the hit/miss counts recorded by [`FunctionResultCache::get`].
+    pub fn stats(&self) -> CacheStats {
+        self.counters.stats()
     }
 }
 
@@ -119,10 +480,34 @@ mod tests {
         assert_eq!(cache.get("x"), Some(Value::from(42.0)));
         assert_eq!(cache.get("y"), None);
 
+        cache.set_many(HashMap::from([
+            ("x".to_string(), Value::from(1.0)),
+            ("y".to_string(), Value::from(2.0)),
+        ]));
+        assert_eq!(cache.get("x"), Some(Value::from(1.0)));
+        assert_eq!(cache.get("y"), Some(Value::from(2.0)));
+
         cache.clear();
         assert_eq!(cache.get("x"), None);
     }
 
+    #[test]
+    fn test_variable_cache_overrides() {
+        let cache = VariableCache::new();
+        cache.set("x".to_string(), Value::from(1.0));
+        cache.set("y".to_string(), Value::from(2.0));
+
+        let layered = cache.with_overrides(HashMap::from([("x".to_string(), Value::from(10.0))]));
+        assert_eq!(layered.get("x"), Some(Value::from(10.0)));
+        assert_eq!(layered.get("y"), Some(Value::from(2.0)));
+        assert_eq!(layered.entries()["x"], Value::from(10.0));
+
+        // The shared entries are unaffected, and later writes show through
+        assert_eq!(cache.get("x"), Some(Value::from(1.0)));
+        cache.set("y".to_string(), Value::from(3.0));
+        assert_eq!(layered.get("y"), Some(Value::from(3.0)));
+    }
+
     #[test]
     fn test_formula_result_cache() {
         let cache = FormulaResultCache::new();
@@ -131,4 +516,94 @@ mod tests {
         assert_eq!(cache.get("formula1"), Some(Value::from("result")));
         assert_eq!(cache.get("formula2"), None);
     }
+
+    #[test]
+    fn test_result_cache_stats() {
+        let cache = FunctionResultCache::new();
+        cache.set("square_1".to_string(), Value::from(1.0));
+
+        cache.get("square_1");
+        cache.get("square_1");
+        cache.get("square_2");
+        assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 1 });
+
+        // Clones share their counters, like they share entries
+        let formulas = FormulaResultCache::new();
+        formulas.set("a".to_string(), Value::from(1.0));
+        formulas.clone().get("a");
+        formulas.peek("a");
+        assert_eq!(formulas.stats(), CacheStats { hits: 1, misses: 0 });
+
+        cache.clear();
+        assert_eq!(cache.stats(), CacheStats::default());
+    }
+
+    #[test]
+    fn test_function_result_cache_evicts_least_recently_used() {
+        let cache = FunctionResultCache::with_capacity(2);
+        cache.set("a".to_string(), Value::from(1.0));
+        cache.set("b".to_string(), Value::from(2.0));
+
+        // Touching "a" leaves "b" as the oldest untouched entry
+        assert_eq!(cache.get("a"), Some(Value::from(1.0)));
+        cache.set("c".to_string(), Value::from(3.0));
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("a"), Some(Value::from(1.0)));
+        assert_eq!(cache.get("c"), Some(Value::from(3.0)));
+
+        // Overwriting an entry refreshes it without growing the cache
+        cache.set("a".to_string(), Value::from(10.0));
+        cache.set("d".to_string(), Value::from(4.0));
+        assert_eq!(cache.get("c"), None);
+        assert_eq!(cache.get("a"), Some(Value::from(10.0)));
+        assert_eq!(cache.len(), 2);
+
+        // A removed entry frees its slot without evicting the others
+        cache.remove("a");
+        cache.set("e".to_string(), Value::from(5.0));
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.get("d"), Some(Value::from(4.0)));
+        assert_eq!(cache.get("e"), Some(Value::from(5.0)));
+    }
+
+    #[test]
+    fn test_function_result_cache_keys_by_arguments() {
+        let cache = FunctionResultCache::with_capacity(2);
+        let one = FunctionResultCache::key("f_1", &[Value::from(1.0)]);
+        let two = FunctionResultCache::key("f_1", &[Value::from(2.0)]);
+        let three = FunctionResultCache::key("f_1", &[Value::from(3.0)]);
+        assert_ne!(one, two);
+
+        cache.set(one.clone(), Value::from(10.0));
+        cache.set(two.clone(), Value::from(20.0));
+        assert_eq!(cache.get(&one), Some(Value::from(10.0)));
+        assert_eq!(cache.get(&two), Some(Value::from(20.0)));
+
+        // Distinct arguments compete for the same slots
+        assert_eq!(cache.get(&one), Some(Value::from(10.0)));
+        cache.set(three.clone(), Value::from(30.0));
+        assert_eq!(cache.get(&two), None);
+        assert_eq!(cache.get(&one), Some(Value::from(10.0)));
+        assert_eq!(cache.get(&three), Some(Value::from(30.0)));
+
+        // Removing a function drops its results for every argument set only
+        let other = FunctionResultCache::key("f_10", &[Value::from(1.0)]);
+        let unbounded = FunctionResultCache::new();
+        unbounded.set(one.clone(), Value::from(10.0));
+        unbounded.set(three.clone(), Value::from(30.0));
+        unbounded.set(other.clone(), Value::from(1.0));
+        unbounded.remove_function("f_1");
+        assert_eq!(unbounded.len(), 1);
+        assert_eq!(unbounded.get(&other), Some(Value::from(1.0)));
+    }
+
+    #[test]
+    fn test_function_result_cache_zero_capacity() {
+        let cache = FunctionResultCache::with_capacity(0);
+        cache.set("a".to_string(), Value::from(1.0));
+        assert!(cache.is_empty());
+        assert_eq!(FunctionResultCache::new().capacity(), None);
+    }
 }
@@ -1,13 +1,244 @@
-use crate::cache::{FormulaResultCache, FunctionCache, FunctionResultCache, VariableCache};
+use crate::cache::{
+    AsyncFunctionCache, CacheStats, ErrorCache, FormulaResultCache, FunctionCache,
+    FunctionResultCache, VariableCache,
+};
+use crate::cancellation::CancellationToken;
 use crate::error::{CalculatorError, Result};
-use crate::formula::{Formula, FormulaT};
-use crate::function::{build_function_id, Function};
+use crate::formula::{
+    compile, compile_expression, qualified_name, CompiledFormula, Formula, FormulaT,
+};
+use crate::function::{build_function_id, to_snake_case, AsyncFunction, AsyncRunner, Function};
 use crate::graph::DAGraph;
-use crate::parser::{Evaluator, Parser};
+use crate::options::EngineOptions;
+use crate::parser::lexer::is_keyword;
+use crate::parser::{Evaluator, Program, References};
 use crate::value::Value;
+use chrono::NaiveDate;
 use rayon::prelude::*;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
+use std::time::Duration;
+
+/// A point-in-time copy of an [`Engine`]'s variables and formula results.
+///
+/// Created by [`Engine::snapshot`] and applied with [`Engine::restore`]. Registered
+/// functions and recorded errors are not part of a snapshot. Snapshots are plain
+/// values, so several can be kept to explore alternative scenarios.
+///
+/// With the `serde` feature, snapshots implement `Serialize` and `Deserialize`, so
+/// results can be persisted and restored into an engine in a later process. Functions
+/// cannot be serialized and must be registered again on the new engine.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EngineSnapshot {
+    variables: HashMap<String, Value>,
+    results: HashMap<String, Value>,
+}
+
+/// The name [`Engine::export_state`] and [`Engine::import_state`] use for an
+/// [`EngineSnapshot`].
+pub type EngineState = EngineSnapshot;
+
+impl EngineSnapshot {
+    /// Returns the captured variables.
+    pub fn variables(&self) -> &HashMap<String, Value> {
+        &self.variables
+    }
+
+    /// Returns the captured formula results.
+    pub fn results(&self) -> &HashMap<String, Value> {
+        &self.results
+    }
+}
+
+/// Outcome of a single call to [`Engine::execute`] or one of its variants.
+///
+/// Only formulas handled by that call appear in the report; [`Engine::get_errors`]
+/// and [`Engine::get_result`] still reflect every execution.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ExecutionReport {
+    results: HashMap<String, Value>,
+    errors: HashMap<String, String>,
+    succeeded: Vec<String>,
+    failed: Vec<String>,
+    detached: Vec<String>,
+    skipped: Vec<String>,
+    cancelled: bool,
+    duration: Duration,
+    stats: Option<RunStats>,
+}
+
+impl ExecutionReport {
+    /// Records the call's total time, in the statistics too if collected.
+    fn finish(&mut self, duration: Duration) {
+        self.duration = duration;
+        if let Some(stats) = self.stats.as_mut() {
+            stats.duration = duration;
+        }
+    }
+
+    /// Returns the formulas that produced a result, in execution order.
+    pub fn succeeded(&self) -> &[String] {
+        &self.succeeded
+    }
+
+    /// Returns the formulas that failed or were skipped because a dependency failed,
+    /// in execution order.
+    pub fn failed(&self) -> &[String] {
+        &self.failed
+    }
+
+    /// Returns the formulas left out of the execution order because a dependency
+    /// could not be resolved.
+    pub fn detached(&self) -> &[String] {
+        &self.detached
+    }
+
+    /// Returns the formulas that were not run because the execution was cancelled
+    /// first, or stopped early by [`EngineOptions::fail_fast`].
+    pub fn skipped(&self) -> &[String] {
+        &self.skipped
+    }
+
+    /// Returns `true` if the execution was cancelled before it completed.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    /// Returns the wall-clock time the call took. Always zero on `wasm32`, which has
+    /// no monotonic clock.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Returns per-layer and per-formula timings, when
+    /// [`EngineOptions::collect_run_stats`] is enabled.
+    pub fn stats(&self) -> Option<&RunStats> {
+        self.stats.as_ref()
+    }
+
+    /// Returns `true` if every formula produced a result.
+    pub fn is_success(&self) -> bool {
+        self.errors.is_empty() && !self.cancelled
+    }
+
+    /// Returns the value of each formula that executed successfully.
+    pub fn results(&self) -> &HashMap<String, Value> {
+        &self.results
+    }
+
+    /// Returns the error message of each formula that failed, as in [`Engine::get_errors`].
+    pub fn errors(&self) -> &HashMap<String, String> {
+        &self.errors
+    }
+
+    /// Returns the value of `formula_name`, if it executed successfully.
+    pub fn get_result(&self, formula_name: &str) -> Option<Value> {
+        self.results.get(formula_name).cloned()
+    }
+}
+
+/// Timings of one execution, broken down by layer and formula.
+///
+/// Included in the [`ExecutionReport`] when [`EngineOptions::collect_run_stats`] is
+/// enabled. Durations are always zero on `wasm32`, which has no monotonic clock.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RunStats {
+    duration: Duration,
+    layers: Vec<LayerStats>,
+    formulas: HashMap<String, FormulaTiming>,
+}
+
+impl RunStats {
+    /// Returns the wall-clock time of the whole execution.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Returns the executed dependency layers in execution order.
+    pub fn layers(&self) -> &[LayerStats] {
+        &self.layers
+    }
+
+    /// Returns the timing of each executed formula.
+    pub fn formulas(&self) -> &HashMap<String, FormulaTiming> {
+        &self.formulas
+    }
+
+    /// Returns up to `n` formulas with the longest total time, slowest first.
+    pub fn slowest(&self, n: usize) -> Vec<(&str, FormulaTiming)> {
+        let mut formulas: Vec<(&str, FormulaTiming)> = self
+            .formulas
+            .iter()
+            .map(|(name, timing)| (name.as_str(), *timing))
+            .collect();
+        formulas.sort_by(|a, b| b.1.total().cmp(&a.1.total()).then(a.0.cmp(b.0)));
+        formulas.truncate(n);
+        formulas
+    }
+}
+
+/// Size and wall-clock time of one dependency layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LayerStats {
+    /// Formulas in the layer, including any skipped because a dependency failed.
+    pub size: usize,
+    pub duration: Duration,
+}
+
+/// Time one formula spent being parsed and being evaluated.
+///
+/// Precompiled formulas have a zero parse time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FormulaTiming {
+    pub parse: Duration,
+    pub eval: Duration,
+}
+
+impl FormulaTiming {
+    /// Returns the parse and evaluation time combined.
+    pub fn total(&self) -> Duration {
+        self.parse + self.eval
+    }
+}
+
+/// An event reported to the callback of [`Engine::execute_with_progress`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgressEvent {
+    /// A dependency layer of `size` formulas is about to run. Layers are numbered
+    /// from zero in execution order.
+    LayerStarted { index: usize, size: usize },
+    /// A formula finished, successfully or not, after `duration`.
+    FormulaCompleted {
+        name: String,
+        result: Result<Value>,
+        duration: Duration,
+    },
+    /// Every formula of the layer has completed.
+    LayerFinished { index: usize },
+}
+
+/// Hit/miss statistics for an [`Engine`]'s result caches.
+///
+/// Returned by [`Engine::cache_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EngineCacheStats {
+    /// Lookups of memoized custom function calls.
+    pub function_results: CacheStats,
+    /// Lookups of formula results through `get_output_from`.
+    pub formula_results: CacheStats,
+}
+
+/// Callback registered with [`Engine::set_on_result`]
+type ResultCallback = dyn Fn(&str, &Result<Value>) + Send + Sync;
+
+/// Callback passed to [`Engine::execute_with_progress`]
+type ProgressCallback = dyn Fn(ProgressEvent) + Send + Sync;
+
+/// Produces the program a formula executes, parsing it if needed
+type Prepare<F> = for<'a> fn(&'a F) -> Result<Cow<'a, Program>>;
 
 /// Main engine for parsing and executing formulas with dependency resolution.
 ///
@@ -15,12 +246,19 @@ use std::sync::Arc;
 /// resolves dependencies between formulas to execute them in the correct order.
 /// Formulas in the same dependency layer are executed in parallel for optimal performance.
 ///
+/// `Engine` is `Send` and `Sync`. Setting variables and executing formulas only need
+/// `&self`, so one engine behind an `Arc` can serve concurrent requests without a
+/// `Mutex`; configuration, function registration, and the registered-formula graph
+/// still need `&mut self`. Results and errors are stored per formula name, so when
+/// concurrent executions compute the same name, the last one to finish it wins. Use
+/// distinct names, or [`Engine::execute_with_variables`], to keep them apart.
+///
 /// # Examples
 ///
 /// ```
 /// use formcalc::{Engine, Formula, Value};
 ///
-/// let mut engine = Engine::new();
+/// let engine = Engine::new();
 /// engine.set_variable("x".to_string(), Value::Number(10.0));
 ///
 /// let formula = Formula::new("doubled", "return x * 2");
@@ -34,7 +272,20 @@ pub struct Engine {
     formula_result_cache: FormulaResultCache,
     function_cache: FunctionCache,
     function_result_cache: FunctionResultCache,
-    errors: HashMap<String, String>,
+    async_function_cache: AsyncFunctionCache,
+    /// Set only on the engine driving an `execute_async` call
+    async_runner: Option<AsyncRunner>,
+    errors: ErrorCache,
+    options: EngineOptions,
+    /// Dedicated pool built from `options.parallelism`; `None` uses rayon's global pool
+    thread_pool: Option<Arc<rayon::ThreadPool>>,
+    graph: DAGraph<String, CompiledFormula>,
+    dirty: HashSet<String>,
+    on_result: Option<Arc<ResultCallback>>,
+    /// Set only on the engine driving an `execute_cancellable` call
+    cancellation: Option<CancellationToken>,
+    /// Set only on the engine driving an `execute_with_progress` call
+    progress: Option<Arc<ProgressCallback>>,
 }
 
 impl Engine {
@@ -53,10 +304,71 @@ impl Engine {
             formula_result_cache: FormulaResultCache::new(),
             function_cache: FunctionCache::new(),
             function_result_cache: FunctionResultCache::new(),
-            errors: HashMap::new(),
+            async_function_cache: AsyncFunctionCache::new(),
+            async_runner: None,
+            errors: ErrorCache::new(),
+            options: EngineOptions::default(),
+            thread_pool: None,
+            graph: DAGraph::new(),
+            dirty: HashSet::new(),
+            on_result: None,
+            cancellation: None,
+            progress: None,
         }
     }
 
+    /// Returns an [`EngineBuilder`] for configuring an engine option by option.
+    ///
+    /// [`Engine::new`] remains the shortcut for an engine with every default.
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::default()
+    }
+
+    /// Creates a new `Engine` configured by `options`.
+    ///
+    /// When `options.parallelism` is set, the engine builds its own thread pool of
+    /// that size instead of sharing rayon's global pool. A parallelism of zero is
+    /// an [`CalculatorError::InvalidArgument`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, EngineOptions, Formula, Value};
+    ///
+    /// let engine = Engine::with_options(EngineOptions {
+    ///     parallelism: Some(1),
+    ///     ..EngineOptions::default()
+    /// })
+    /// .unwrap();
+    ///
+    /// engine.execute(vec![Formula::new("answer", "return 6 * 7")]).unwrap();
+    /// assert_eq!(engine.get_result("answer"), Some(Value::Number(42.0)));
+    /// ```
+    pub fn with_options(options: EngineOptions) -> Result<Self> {
+        let thread_pool = match options.parallelism {
+            Some(0) => {
+                return Err(CalculatorError::InvalidArgument(
+                    "parallelism must be at least 1".to_string(),
+                ))
+            }
+            Some(threads) => Some(Arc::new(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .map_err(|e| {
+                        CalculatorError::EvalError(format!("failed to build thread pool: {}", e))
+                    })?,
+            )),
+            None => None,
+        };
+
+        Ok(Self {
+            options,
+            thread_pool,
+            ..Self::new()
+        })
+    }
+
     /// Sets a variable that can be referenced in formulas.
     ///
     /// Variables can be used directly in formula expressions by name.
@@ -71,65 +383,85 @@ impl Engine {
     /// ```
     /// use formcalc::{Engine, Value};
     ///
-    /// let mut engine = Engine::new();
+    /// let engine = Engine::new();
     /// engine.set_variable("pi".to_string(), Value::Number(3.14159));
     /// ```
-    pub fn set_variable(&mut self, name: String, value: Value) {
+    pub fn set_variable(&self, name: String, value: Value) {
         self.variable_cache.set(name, value);
     }
 
-    /// Registers a custom function that can be called from formulas.
+    /// Sets several variables at once.
     ///
-    /// Functions are identified by their name and number of arguments.
-    /// You can register multiple functions with the same name but different arities.
+    /// All values are inserted under a single lock, so a formula evaluated
+    /// concurrently never observes a partial update. Existing variables not named in
+    /// `variables` are left untouched.
     ///
-    /// # Arguments
+    /// # Examples
     ///
-    /// * `function` - An `Arc` containing a type implementing the [`Function`] trait
+    /// ```
+    /// use formcalc::{Engine, Formula, Value};
+    /// use std::collections::HashMap;
     ///
-    /// # Examples
+    /// let engine = Engine::new();
+    /// engine.set_variables_batch(HashMap::from([
+    ///     ("price".to_string(), Value::Number(20.0)),
+    ///     ("quantity".to_string(), Value::Number(3.0)),
+    /// ]));
     ///
+    /// engine
+    ///     .execute(vec![Formula::new("total", "return price * quantity")])
+    ///     .unwrap();
+    /// assert_eq!(engine.get_result("total"), Some(Value::Number(60.0)));
     /// ```
-    /// use formcalc::{Engine, Function, Value, Result, CalculatorError};
-    /// use std::sync::Arc;
+    pub fn set_variables_batch(&self, variables: HashMap<String, Value>) {
+        self.variable_cache.set_many(variables);
+    }
+
+    /// Returns `true` if a variable with this name has been set.
     ///
-    /// struct SquareFunction;
+    /// # Examples
     ///
-    /// impl Function for SquareFunction {
-    ///     fn name(&self) -> &str { "square" }
-    ///     fn num_args(&self) -> usize { 1 }
-    ///     fn execute(&self, params: &[Value]) -> Result<Value> {
-    ///         match params[0] {
-    ///             Value::Number(n) => Ok(Value::Number(n * n)),
-    ///             _ => Err(CalculatorError::TypeError("Expected number".to_string())),
-    ///         }
-    ///     }
-    /// }
+    /// ```
+    /// use formcalc::{Engine, Value};
     ///
-    /// let mut engine = Engine::new();
-    /// engine.register_function(Arc::new(SquareFunction));
+    /// let engine = Engine::new();
+    /// engine.set_variable("x".to_string(), Value::Number(1.0));
+    ///
+    /// assert!(engine.has_variable("x"));
+    /// assert!(!engine.has_variable("y"));
     /// ```
-    pub fn register_function(&mut self, function: Arc<dyn Function>) {
-        let function_id = build_function_id(function.name(), function.num_args());
-        self.function_cache.set(function_id, function);
+    pub fn has_variable(&self, name: &str) -> bool {
+        self.variable_cache.get(name).is_some()
     }
 
-    /// Executes multiple formulas with automatic dependency resolution.
+    /// Enables or disables rejection of non-finite results.
     ///
-    /// The engine analyzes dependencies between formulas (via `get_output_from` calls),
-    /// builds a dependency graph, and executes formulas in topological order.
-    /// Formulas in the same dependency layer are executed in parallel for performance.
+    /// When enabled, a formula that evaluates to `NaN` or an infinite number is
+    /// recorded in [`Engine::get_errors`] instead of being stored as a result.
     ///
-    /// # Arguments
+    /// # Examples
     ///
-    /// * `formulas` - A vector of [`Formula`] instances to execute
+    /// ```
+    /// use formcalc::{Engine, Formula};
     ///
-    /// # Returns
+    /// let mut engine = Engine::new();
+    /// engine.set_reject_nonfinite(true);
+    ///
+    /// let formula = Formula::new("overflow", "return 10 ^ 400");
+    /// engine.execute(vec![formula]).unwrap();
     ///
-    /// Returns `Ok(())` if dependency resolution succeeds, or an error if there are
-    /// circular dependencies or invalid graph structures.
+    /// assert_eq!(engine.get_result("overflow"), None);
+    /// assert!(engine.get_errors().contains_key("overflow"));
+    /// ```
+    pub fn set_reject_nonfinite(&mut self, reject: bool) {
+        self.options.reject_nonfinite = reject;
+    }
+
+    /// Enables or disables strict math.
     ///
-    /// Individual formula execution errors are captured and available via [`Engine::get_errors`].
+    /// When enabled, an operation that produces `NaN` or an infinite number fails
+    /// with [`CalculatorError::EvalError`] at the point it happens, so the error names
+    /// the offending operation rather than just the formula's final result.
     ///
     /// # Examples
     ///
@@ -137,221 +469,3186 @@ impl Engine {
     /// use formcalc::{Engine, Formula, Value};
     ///
     /// let mut engine = Engine::new();
+    /// engine.set_strict_math(true);
+    /// engine.set_variable("x".to_string(), Value::Number(1000.0));
     ///
-    /// let f1 = Formula::new("a", "return 10");
-    /// let f2 = Formula::new("b", "return get_output_from('a') * 2");
-    /// let f3 = Formula::new("c", "return get_output_from('b') + 5");
-    ///
-    /// engine.execute(vec![f1, f2, f3]).unwrap();
+    /// let formula = Formula::new("overflow", "return exp(x) - exp(x)");
+    /// engine.execute(vec![formula]).unwrap();
     ///
-    /// assert_eq!(engine.get_result("c"), Some(Value::Number(25.0)));
+    /// let error = &engine.get_errors()["overflow"];
+    /// assert!(error.contains("Exp of (1000.0)"), "{}", error);
     /// ```
-    pub fn execute(&mut self, formulas: Vec<Formula>) -> Result<()> {
-        let mut graph = DAGraph::new();
-
-        // Build dependency graph
-        for formula in &formulas {
-            graph
-                .add_node(
-                    formula.name().to_string(),
-                    formula.clone(),
-                    formula.depends_on().to_vec(),
-                )
-                .map_err(CalculatorError::DependencyError)?;
-        }
-
-        // Topological sort to get execution order
-        let (layers, detached) = graph.topological_sort();
-
-        // Handle detached (unresolvable) formulas
-        for formula_name in detached {
-            let error_msg = format!(
-                "Could not resolve dependency path for formula: '{}'",
-                formula_name
-            );
-            self.errors.insert(formula_name, error_msg);
-        }
-
-        // Execute formulas layer by layer
-        // Formulas in the same layer can be executed in parallel
-        for layer in layers {
-            self.execute_layer_parallel(&graph, layer);
-        }
-
-        Ok(())
+    pub fn set_strict_math(&mut self, strict: bool) {
+        self.options.strict_math = strict;
     }
 
-    /// Execute all formulas in a layer in parallel
-    fn execute_layer_parallel(&mut self, graph: &DAGraph<String, Formula>, layer: Vec<String>) {
-        // Execute formulas in parallel
-        let results: Vec<(String, Result<Value>)> = layer
-            .par_iter()
-            .filter_map(|formula_name| {
-                graph.get(formula_name).map(|formula| {
-                    let result = self.try_execute_formula(formula);
-                    (formula_name.clone(), result)
-                })
-            })
-            .collect();
-
-        // Process results sequentially to update caches and collect errors
-        for (formula_name, result) in results {
-            match result {
-                Ok(value) => {
-                    self.formula_result_cache.set(formula_name, value);
-                }
-                Err(e) => {
-                    let error_msg = format!("Error executing formula '{}': {}", formula_name, e);
-                    self.errors.insert(formula_name, error_msg);
-                }
-            }
-        }
+    /// Limits how many expression nodes each formula evaluation may visit.
+    ///
+    /// A formula that exceeds the budget fails with
+    /// [`CalculatorError::LimitExceeded`]; `None` removes the limit.
+    pub fn set_max_eval_steps(&mut self, max_steps: Option<usize>) {
+        self.options.max_eval_steps = max_steps;
     }
 
-    fn try_execute_formula(&self, formula: &Formula) -> Result<Value> {
-        let mut parser = Parser::new(formula.body())?;
-        let program = parser.parse()?;
-
-        let evaluator = Evaluator::new(
-            self.variable_cache.clone(),
-            self.formula_result_cache.clone(),
-            self.function_cache.clone(),
-            self.function_result_cache.clone(),
-        );
+    /// Enables or disables stopping an execution after the first failing layer; see
+    /// [`EngineOptions::fail_fast`].
+    pub fn set_fail_fast(&mut self, enabled: bool) {
+        self.options.fail_fast = enabled;
+    }
 
-        evaluator.evaluate(&program)
+    /// Enables or disables [`RunStats`] in execution reports; see
+    /// [`EngineOptions::collect_run_stats`].
+    pub fn set_collect_run_stats(&mut self, enabled: bool) {
+        self.options.collect_run_stats = enabled;
     }
 
-    /// Retrieves the result of a previously executed formula.
-    ///
-    /// # Arguments
-    ///
-    /// * `formula_name` - The name of the formula whose result to retrieve
-    ///
-    /// # Returns
+    /// Registers a callback invoked with each formula's name and result as it completes.
     ///
-    /// Returns `Some(Value)` if the formula executed successfully, or `None` if the
-    /// formula hasn't been executed or failed with an error.
+    /// Formulas in a layer run in parallel, but the callback is called sequentially
+    /// as their results are collected, so it needs no synchronization of its own.
+    /// Formulas skipped because a dependency failed are reported with their
+    /// dependency error. Replaces any previously registered callback.
     ///
     /// # Examples
     ///
     /// ```
-    /// use formcalc::{Engine, Formula, Value};
+    /// use formcalc::{Engine, Formula};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let finished = Arc::new(Mutex::new(Vec::new()));
+    /// let log = finished.clone();
     ///
     /// let mut engine = Engine::new();
-    /// let formula = Formula::new("test", "return 42");
-    /// engine.execute(vec![formula]).unwrap();
+    /// engine.set_on_result(Box::new(move |name, result| {
+    ///     log.lock().unwrap().push(format!("{}: {}", name, result.is_ok()));
+    /// }));
     ///
-    /// assert_eq!(engine.get_result("test"), Some(Value::Number(42.0)));
-    /// assert_eq!(engine.get_result("nonexistent"), None);
+    /// engine.execute(vec![Formula::new("total", "return 1 + 1")]).unwrap();
+    /// assert_eq!(*finished.lock().unwrap(), vec!["total: true".to_string()]);
     /// ```
-    pub fn get_result(&self, formula_name: &str) -> Option<Value> {
-        self.formula_result_cache.get(formula_name)
+    pub fn set_on_result(&mut self, callback: Box<ResultCallback>) {
+        self.on_result = Some(Arc::from(callback));
     }
 
-    /// Returns a map of all errors that occurred during the last execution.
+    /// Enables or disables skipping formulas whose dependencies failed.
     ///
-    /// The map keys are formula names and values are error messages.
+    /// See [`Engine::execute_best_effort`], which always behaves as if this were enabled.
+    pub fn set_skip_on_dependency_error(&mut self, skip: bool) {
+        self.options.skip_on_dependency_error = skip;
+    }
+
+    /// Enables or disables evaluating unknown identifiers as their own name.
+    ///
+    /// When enabled, a bareword with no variable set, such as `pending`, evaluates to
+    /// the string `'pending'` instead of failing with
+    /// [`CalculatorError::VariableNotFound`]. Disabled by default.
     ///
     /// # Examples
     ///
     /// ```
-    /// use formcalc::{Engine, Formula};
+    /// use formcalc::{Engine, Formula, Value};
     ///
     /// let mut engine = Engine::new();
-    /// let formula = Formula::new("bad", "return 1 / 0");
-    /// engine.execute(vec![formula]).unwrap();
+    /// engine.set_unknown_identifier_as_string(true);
     ///
-    /// assert!(!engine.get_errors().is_empty());
+    /// engine.execute(vec![Formula::new("status", "return pending")]).unwrap();
+    /// assert_eq!(engine.get_result("status"), Some(Value::String("pending".to_string())));
     /// ```
-    pub fn get_errors(&self) -> &HashMap<String, String> {
-        &self.errors
+    pub fn set_unknown_identifier_as_string(&mut self, enabled: bool) {
+        self.options.unknown_identifier_as_string = enabled;
     }
 
-    /// Clears all variables, formula results, function result caches, and errors.
+    /// Sets the holiday calendar shared by every `business_days_between` call.
     ///
-    /// Note: Registered custom functions are preserved.
+    /// Holidays passed explicitly to the function are excluded in addition to these.
     ///
     /// # Examples
     ///
     /// ```
+    /// use chrono::NaiveDate;
     /// use formcalc::{Engine, Formula, Value};
     ///
     /// let mut engine = Engine::new();
-    /// engine.set_variable("x".to_string(), Value::Number(10.0));
-    /// let formula = Formula::new("test", "return x");
-    /// engine.execute(vec![formula]).unwrap();
+    /// engine.set_holidays(vec![NaiveDate::from_ymd_opt(2024, 1, 8).unwrap()]);
     ///
-    /// engine.clear();
+    /// let formula = Formula::new("days", "return business_days_between('2024-01-05', '2024-01-09')");
+    /// engine.execute(vec![formula]).unwrap();
     ///
-    /// assert_eq!(engine.get_result("test"), None);
+    /// assert_eq!(engine.get_result("days"), Some(Value::Number(1.0)));
     /// ```
-    pub fn clear(&mut self) {
-        self.variable_cache.clear();
-        self.formula_result_cache.clear();
-        self.function_result_cache.clear();
-        self.errors.clear();
+    pub fn set_holidays(&mut self, holidays: Vec<NaiveDate>) {
+        self.options.holidays = holidays;
     }
-}
 
-impl Default for Engine {
-    fn default() -> Self {
-        Self::new()
+    /// Limits the custom function result cache to `capacity` entries.
+    ///
+    /// Once full, the least recently used result is evicted. The cache is unbounded
+    /// by default; changing the capacity discards any cached results.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::Engine;
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.set_function_cache_capacity(1024);
+    /// ```
+    pub fn set_function_cache_capacity(&mut self, capacity: usize) {
+        self.function_result_cache = FunctionResultCache::with_capacity(capacity);
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
-    #[test]
-    fn test_simple_formula() {
-        let mut engine = Engine::new();
-        let formula = Formula::new("test", "return 2 + 2");
-
-        engine.execute(vec![formula]).unwrap();
-
-        let result = engine.get_result("test").unwrap();
+    /// Registers a custom function that can be called from formulas.
+    ///
+    /// Functions are identified by their name and number of arguments.
+    /// You can register multiple functions with the same name but different arities.
+    ///
+    /// # Arguments
+    ///
+    /// * `function` - An `Arc` containing a type implementing the [`Function`] trait
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CalculatorError::FunctionAlreadyRegistered`] if a synchronous or async
+    /// function with the same name and arity exists, leaving the engine unchanged; use
+    /// [`Engine::register_function_overwrite`] when replacing it is intended. Returns
+    /// [`CalculatorError::InvalidArgument`] if the snake_cased name is a keyword or
+    /// built-in such as `max`, since formulas could never call it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Function, Value, Result, CalculatorError};
+    /// use std::sync::Arc;
+    ///
+    /// struct SquaredFunction;
+    ///
+    /// impl Function for SquaredFunction {
+    ///     fn name(&self) -> &str { "squared" }
+    ///     fn num_args(&self) -> usize { 1 }
+    ///     fn execute(&self, params: &[Value]) -> Result<Value> {
+    ///         match params[0] {
+    ///             Value::Number(n) => Ok(Value::Number(n * n)),
+    ///             _ => Err(CalculatorError::TypeError("Expected number".to_string())),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.register_function(Arc::new(SquaredFunction)).unwrap();
+    /// assert_eq!(
+    ///     engine.register_function(Arc::new(SquaredFunction)),
+    ///     Err(CalculatorError::FunctionAlreadyRegistered("squared_1".to_string()))
+    /// );
+    /// ```
+    pub fn register_function(&mut self, function: Arc<dyn Function>) -> Result<()> {
+        let function_id = callable_function_id(function.name(), function.num_args())?;
+        if self.function_cache.get(&function_id).is_some()
+            || self.async_function_cache.get(&function_id).is_some()
+        {
+            return Err(CalculatorError::FunctionAlreadyRegistered(function_id));
+        }
+
+        self.function_cache.set(function_id, function);
+        Ok(())
+    }
+
+    /// Registers a custom function, replacing any function with the same name and arity.
+    ///
+    /// Any cached result of the replaced function is discarded. Names that collide
+    /// with a keyword or built-in are still rejected, as in [`Engine::register_function`].
+    pub fn register_function_overwrite(&mut self, function: Arc<dyn Function>) -> Result<()> {
+        let function_id = callable_function_id(function.name(), function.num_args())?;
+        self.async_function_cache.remove(&function_id);
+        self.function_result_cache.remove_function(&function_id);
+        self.function_cache.set(function_id, function);
+        Ok(())
+    }
+
+    /// Registers a custom function, failing if one with the same name and arity exists.
+    #[deprecated(note = "register_function now rejects duplicates; use it instead")]
+    pub fn try_register_function(&mut self, function: Arc<dyn Function>) -> Result<()> {
+        self.register_function(function)
+    }
+
+    /// Registers a custom async function that can be called from formulas.
+    ///
+    /// Formulas calling it must be run with `Engine::execute_async` (requires the
+    /// `tokio` feature); other execution methods record an error for them.
+    ///
+    /// Fails like [`Engine::register_function`] if a synchronous or async function
+    /// with the same name and arity exists, or if the name is a keyword or built-in.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::function::BoxFuture;
+    /// use formcalc::{AsyncFunction, Engine, Formula, Result, Value};
+    /// use std::sync::Arc;
+    ///
+    /// struct FetchRate;
+    ///
+    /// impl AsyncFunction for FetchRate {
+    ///     fn name(&self) -> &str { "fetch_rate" }
+    ///     fn num_args(&self) -> usize { 1 }
+    ///     fn execute<'a>(&'a self, _params: &'a [Value]) -> BoxFuture<'a, Result<Value>> {
+    ///         Box::pin(async { Ok(Value::Number(1.0)) })
+    ///     }
+    /// }
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.register_async_function(Arc::new(FetchRate)).unwrap();
+    ///
+    /// engine.execute(vec![Formula::new("value", "return fetch_rate('key')")]).unwrap();
+    /// assert!(engine.get_errors()["value"].contains("Async function requires async execution"));
+    /// ```
+    pub fn register_async_function(&mut self, function: Arc<dyn AsyncFunction>) -> Result<()> {
+        let function_id = callable_function_id(function.name(), function.num_args())?;
+        if self.function_cache.get(&function_id).is_some()
+            || self.async_function_cache.get(&function_id).is_some()
+        {
+            return Err(CalculatorError::FunctionAlreadyRegistered(function_id));
+        }
+
+        self.async_function_cache.set(function_id, function);
+        Ok(())
+    }
+
+    /// Removes the custom function registered under `name` with `num_args` arguments.
+    ///
+    /// Both synchronous and async registrations are removed, along with any cached
+    /// result of the function, so formulas calling it afterwards fail with
+    /// [`CalculatorError::FunctionNotFound`] until another function is registered.
+    /// Returns `true` if a function was registered.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Function, Result, Value};
+    /// use std::sync::Arc;
+    ///
+    /// struct Zero;
+    ///
+    /// impl Function for Zero {
+    ///     fn name(&self) -> &str { "zero" }
+    ///     fn num_args(&self) -> usize { 0 }
+    ///     fn execute(&self, _params: &[Value]) -> Result<Value> { Ok(Value::Number(0.0)) }
+    /// }
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.register_function(Arc::new(Zero)).unwrap();
+    ///
+    /// assert!(engine.unregister_function("zero", 0));
+    /// assert!(!engine.unregister_function("zero", 0));
+    /// ```
+    pub fn unregister_function(&mut self, name: &str, num_args: usize) -> bool {
+        let function_id = build_function_id(name, num_args);
+        let removed_sync = self.function_cache.remove(&function_id);
+        let removed_async = self.async_function_cache.remove(&function_id);
+        self.function_result_cache.remove_function(&function_id);
+        removed_sync || removed_async
+    }
+
+    /// Removes every registered custom function, sync and async, and their cached results.
+    pub fn clear_functions(&mut self) {
+        self.function_cache.clear();
+        self.async_function_cache.clear();
+        self.function_result_cache.clear();
+    }
+
+    /// Executes multiple formulas with automatic dependency resolution.
+    ///
+    /// The engine analyzes dependencies between formulas (via `get_output_from` calls),
+    /// builds a dependency graph, and executes formulas in topological order.
+    /// Formulas in the same dependency layer are executed in parallel for performance.
+    ///
+    /// # Arguments
+    ///
+    /// * `formulas` - A vector of [`Formula`] instances to execute
+    ///
+    /// # Returns
+    ///
+    /// Returns an [`ExecutionReport`] listing which formulas succeeded, failed, or
+    /// could not be placed in the execution order, or an error if the dependency
+    /// graph cannot be built (for example, two formulas share a name).
+    ///
+    /// Individual formula execution errors are captured in the report and in
+    /// [`Engine::get_errors`]. This includes syntax errors: each formula is parsed on
+    /// its own, so a malformed formula is reported without stopping the rest of the batch.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula, Value};
+    ///
+    /// let engine = Engine::new();
+    ///
+    /// let f1 = Formula::new("a", "return 10");
+    /// let f2 = Formula::new("b", "return get_output_from('a') * 2");
+    /// let f3 = Formula::new("c", "return get_output_from('b') + 5");
+    ///
+    /// let report = engine.execute(vec![f1, f2, f3]).unwrap();
+    ///
+    /// assert!(report.is_success());
+    /// assert_eq!(report.succeeded(), ["a", "b", "c"]);
+    /// assert_eq!(engine.get_result("c"), Some(Value::Number(25.0)));
+    /// ```
+    pub fn execute(&self, formulas: Vec<Formula>) -> Result<ExecutionReport> {
+        let skip = self.options.skip_on_dependency_error;
+        self.execute_graph(formulas, compile_formula, skip)
+    }
+
+    /// Executes formulas of any [`FormulaT`] implementation, like [`Engine::execute`].
+    ///
+    /// Use this to mix custom formula types, such as records loaded from a database,
+    /// in one batch. Each formula's body is parsed when it is executed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula, FormulaT, Value};
+    ///
+    /// struct StoredFormula {
+    ///     name: String,
+    ///     body: String,
+    /// }
+    ///
+    /// impl FormulaT for StoredFormula {
+    ///     fn name(&self) -> &str { &self.name }
+    ///     fn body(&self) -> &str { &self.body }
+    ///     fn depends_on(&self) -> &[String] { &[] }
+    /// }
+    ///
+    /// let formulas: Vec<Box<dyn FormulaT + Send + Sync>> = vec![
+    ///     Box::new(StoredFormula { name: "base".into(), body: "return 20".into() }),
+    ///     Box::new(Formula::new("total", "return get_output_from('base') + 1")),
+    /// ];
+    ///
+    /// let engine = Engine::new();
+    /// engine.execute_dyn(formulas).unwrap();
+    /// assert_eq!(engine.get_result("total"), Some(Value::Number(21.0)));
+    /// ```
+    pub fn execute_dyn(
+        &self,
+        formulas: Vec<Box<dyn FormulaT + Send + Sync>>,
+    ) -> Result<ExecutionReport> {
+        let skip = self.options.skip_on_dependency_error;
+        self.execute_graph(formulas, compile_formula, skip)
+    }
+
+    /// Evaluates a single formula and returns its result or error directly.
+    ///
+    /// The formula sees the current variables, registered functions, and the results
+    /// of previously executed formulas, but its own result is not stored and nothing
+    /// is recorded in [`Engine::get_errors`]. Use [`Engine::execute_one_cached`] to keep
+    /// the result for later `get_output_from` calls.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula, Value};
+    ///
+    /// let engine = Engine::new();
+    /// engine.set_variable("x".to_string(), Value::Number(4.0));
+    ///
+    /// let value = engine.execute_one(&Formula::new("preview", "return x * 2")).unwrap();
+    /// assert_eq!(value, Value::Number(8.0));
+    /// assert_eq!(engine.get_result("preview"), None);
+    ///
+    /// assert!(engine.execute_one(&Formula::new("broken", "return x +")).is_err());
+    /// ```
+    pub fn execute_one(&self, formula: &Formula) -> Result<Value> {
+        self.try_execute_formula(formula)
+    }
+
+    /// Evaluates a bare expression such as `price * (1 + tax_rate)`.
+    ///
+    /// The expression is evaluated as if it were the body `return {expression}`,
+    /// against the current variables, functions, and formula results. Nothing is
+    /// stored and no error is recorded; the value or error is returned directly.
+    /// A leading `return`, `if`, or `error` is a parse error, since those start a
+    /// full formula body (see [`Formula::validate`]).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Value};
+    ///
+    /// let engine = Engine::new();
+    /// engine.set_variable("price".to_string(), Value::Number(100.0));
+    /// engine.set_variable("tax_rate".to_string(), Value::Number(0.2));
+    ///
+    /// assert_eq!(engine.evaluate("price * (1 + tax_rate)"), Ok(Value::Number(120.0)));
+    /// assert!(engine.evaluate("price *").is_err());
+    /// ```
+    pub fn evaluate(&self, expression: &str) -> Result<Value> {
+        compile_expression(expression)
+            .and_then(|program| self.evaluate_program(&program))
+            .map_err(|e| e.with_expression(expression))
+    }
+
+    /// Evaluates a single formula like [`Engine::execute_one`], storing its result on success.
+    pub fn execute_one_cached(&self, formula: &Formula) -> Result<Value> {
+        let value = self.try_execute_formula(formula)?;
+        self.errors.remove(formula.name());
+        self.formula_result_cache
+            .set(formula.name().to_string(), value.clone());
+        Ok(value)
+    }
+
+    /// Executes only the formulas carrying at least one of `tags`, plus the formulas they
+    /// depend on.
+    ///
+    /// Dependencies are included transitively whatever their tags, so every selected
+    /// formula can compute. Formulas that are neither tagged nor needed are not run
+    /// and keep any previous result.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula, Value};
+    ///
+    /// let pricing = vec!["pricing".to_string()];
+    /// let engine = Engine::new();
+    /// engine
+    ///     .execute_tagged(
+    ///         vec![
+    ///             Formula::new("base", "return 100"),
+    ///             Formula::new("price", "return get_output_from('base') * 2").with_tags(pricing),
+    ///             Formula::new("check", "return 1"),
+    ///         ],
+    ///         &["pricing"],
+    ///     )
+    ///     .unwrap();
+    ///
+    /// assert_eq!(engine.get_result("price"), Some(Value::Number(200.0)));
+    /// assert_eq!(engine.get_result("check"), None);
+    /// ```
+    pub fn execute_tagged(&self, formulas: Vec<Formula>, tags: &[&str]) -> Result<ExecutionReport> {
+        let depends_on: HashMap<&str, &[String]> = formulas
+            .iter()
+            .map(|formula| (formula.name(), formula.depends_on()))
+            .collect();
+
+        let mut selected = HashSet::new();
+        let mut pending: Vec<&str> = formulas
+            .iter()
+            .filter(|formula| {
+                formula
+                    .tags()
+                    .iter()
+                    .any(|tag| tags.contains(&tag.as_str()))
+            })
+            .map(|formula| formula.name())
+            .collect();
+        while let Some(name) = pending.pop() {
+            if selected.insert(name.to_string()) {
+                if let Some(dependencies) = depends_on.get(name) {
+                    pending.extend(dependencies.iter().map(String::as_str));
+                }
+            }
+        }
+
+        let formulas = formulas
+            .into_iter()
+            .filter(|formula| selected.contains(formula.name()))
+            .collect();
+        self.execute(formulas)
+    }
+
+    /// Executes formulas like [`Engine::execute`] without blocking the async runtime.
+    ///
+    /// The work runs on tokio's blocking thread pool, where formulas in the same
+    /// dependency layer are still executed in parallel. This is the only execution
+    /// method that can call functions registered with [`Engine::register_async_function`];
+    /// each call is awaited on the current tokio runtime. Results are written to the
+    /// engine's shared caches as they complete, so they are kept even if the
+    /// returned future is dropped early.
+    ///
+    /// Requires the `tokio` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula, Value};
+    ///
+    /// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+    /// let engine = Engine::new();
+    /// engine
+    ///     .execute_async(vec![Formula::new("answer", "return 6 * 7")])
+    ///     .await
+    ///     .unwrap();
+    ///
+    /// assert_eq!(engine.get_result("answer"), Some(Value::Number(42.0)));
+    /// # });
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub async fn execute_async(&self, formulas: Vec<Formula>) -> Result<ExecutionReport> {
+        let handle = tokio::runtime::Handle::current();
+        let mut engine = self.share_caches();
+        engine.async_runner = Some(Arc::new(
+            move |future: crate::function::BoxFuture<'_, Result<Value>>| handle.block_on(future),
+        ));
+
+        tokio::task::spawn_blocking(move || engine.execute(formulas))
+            .await
+            .map_err(|e| CalculatorError::EvalError(format!("execution task failed: {}", e)))?
+    }
+
+    /// Executes formulas like [`Engine::execute`], stopping early once `token` is cancelled.
+    ///
+    /// The token is checked before each dependency layer and periodically while a
+    /// formula evaluates. Formulas that completed keep their results; a formula
+    /// interrupted mid-evaluation fails with [`CalculatorError::Cancelled`], and
+    /// formulas not yet started are listed in [`ExecutionReport::skipped`]. A call
+    /// into a custom function is not interrupted, so cancellation takes effect once
+    /// the function returns.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{CancellationToken, Engine, Formula};
+    ///
+    /// let engine = Engine::new();
+    /// let token = CancellationToken::new();
+    /// token.cancel();
+    ///
+    /// let report = engine
+    ///     .execute_cancellable(vec![Formula::new("a", "return 1")], &token)
+    ///     .unwrap();
+    ///
+    /// assert!(report.is_cancelled());
+    /// assert_eq!(report.skipped(), ["a"]);
+    /// ```
+    pub fn execute_cancellable(
+        &self,
+        formulas: Vec<Formula>,
+        token: &CancellationToken,
+    ) -> Result<ExecutionReport> {
+        let mut engine = self.share_caches();
+        engine.cancellation = Some(token.clone());
+        engine.execute(formulas)
+    }
+
+    /// Executes formulas like [`Engine::execute`], reporting progress to `callback`.
+    ///
+    /// The callback receives a [`ProgressEvent::LayerStarted`] before each dependency
+    /// layer runs, a [`ProgressEvent::FormulaCompleted`] as each of its formulas
+    /// finishes, and a [`ProgressEvent::LayerFinished`] once they all have. Formulas
+    /// of a layer run in parallel, so completions arrive from worker threads in no
+    /// particular order.
+    ///
+    /// A panic in the callback does not abort the run. A panic while handling a
+    /// completion is recorded as that formula's error instead of its result; a panic
+    /// while handling a layer event is discarded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula, ProgressEvent};
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// let engine = Engine::new();
+    /// let completed = Arc::new(AtomicUsize::new(0));
+    /// let counter = completed.clone();
+    ///
+    /// engine
+    ///     .execute_with_progress(
+    ///         vec![
+    ///             Formula::new("a", "return 1"),
+    ///             Formula::new("b", "return get_output_from('a') + 1"),
+    ///         ],
+    ///         move |event| {
+    ///             if let ProgressEvent::FormulaCompleted { .. } = event {
+    ///                 counter.fetch_add(1, Ordering::Relaxed);
+    ///             }
+    ///         },
+    ///     )
+    ///     .unwrap();
+    ///
+    /// assert_eq!(completed.load(Ordering::Relaxed), 2);
+    /// ```
+    pub fn execute_with_progress(
+        &self,
+        formulas: Vec<Formula>,
+        callback: impl Fn(ProgressEvent) + Send + Sync + 'static,
+    ) -> Result<ExecutionReport> {
+        let mut engine = self.share_caches();
+        engine.progress = Some(Arc::new(callback));
+        engine.execute(formulas)
+    }
+
+    /// Creates an independent copy of this engine.
+    ///
+    /// The fork starts with the same variables, registered functions, formula
+    /// results, registered formulas, errors, and options, but in fresh caches: changes
+    /// made to either engine afterwards are not visible to the other. Memoized
+    /// function results and cache statistics start empty. This suits building a base
+    /// engine once and forking it per request.
+    ///
+    /// The registered `Arc<dyn Function>` handles themselves are shared rather than
+    /// copied, since functions are immutable; only the registry holding them is new.
+    /// Use [`Engine::clone_detached`] to copy an engine without its results.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Value};
+    ///
+    /// let base = Engine::new();
+    /// base.set_variable("rate".to_string(), Value::Number(0.2));
+    ///
+    /// let fork = base.fork();
+    /// fork.set_variable("rate".to_string(), Value::Number(0.5));
+    ///
+    /// assert!(base.has_variable("rate"));
+    /// assert_eq!(base.snapshot().variables()["rate"], Value::Number(0.2));
+    /// ```
+    pub fn fork(&self) -> Engine {
+        self.clone_detached(true)
+    }
+
+    /// Creates an independent copy of this engine, like [`Engine::fork`], copying
+    /// formula results and errors only when `include_results` is `true`.
+    ///
+    /// Without results the copy keeps the variables, functions, registered formulas,
+    /// and options, and [`Engine::recompute`] reruns every registered formula. This
+    /// suits a template engine stamped out per request.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula, Value};
+    ///
+    /// let template = Engine::new();
+    /// template.set_variable("rate".to_string(), Value::Number(0.2));
+    /// template
+    ///     .execute(vec![Formula::new("interest", "return 100 * rate")])
+    ///     .unwrap();
+    ///
+    /// let request = template.clone_detached(false);
+    /// assert!(request.has_variable("rate"));
+    /// assert_eq!(request.get_result("interest"), None);
+    /// assert_eq!(template.get_result("interest"), Some(Value::Number(20.0)));
+    /// ```
+    pub fn clone_detached(&self, include_results: bool) -> Engine {
+        let variable_cache = VariableCache::new();
+        variable_cache.replace(self.variable_cache.entries());
+        let formula_result_cache = FormulaResultCache::new();
+        let mut dirty = self.dirty.clone();
+        let errors = if include_results {
+            formula_result_cache.replace(self.formula_result_cache.entries());
+            self.errors.fork()
+        } else {
+            dirty.extend(self.graph.keys().cloned());
+            ErrorCache::new()
+        };
+        Engine {
+            variable_cache,
+            formula_result_cache,
+            function_cache: self.function_cache.fork(),
+            function_result_cache: self.empty_function_result_cache(),
+            async_function_cache: self.async_function_cache.fork(),
+            async_runner: None,
+            errors,
+            options: self.options.clone(),
+            thread_pool: self.thread_pool.clone(),
+            graph: self.graph.clone(),
+            dirty,
+            on_result: self.on_result.clone(),
+            cancellation: None,
+            progress: None,
+        }
+    }
+
+    /// Creates an empty function result cache with the same capacity as this engine's.
+    fn empty_function_result_cache(&self) -> FunctionResultCache {
+        match self.function_result_cache.capacity() {
+            Some(capacity) => FunctionResultCache::with_capacity(capacity),
+            None => FunctionResultCache::new(),
+        }
+    }
+
+    /// Creates an engine backed by the same caches, for running work with
+    /// per-call settings such as an async runner or a cancellation token.
+    fn share_caches(&self) -> Engine {
+        Engine {
+            variable_cache: self.variable_cache.clone(),
+            formula_result_cache: self.formula_result_cache.clone(),
+            function_cache: self.function_cache.clone(),
+            function_result_cache: self.function_result_cache.clone(),
+            async_function_cache: self.async_function_cache.clone(),
+            async_runner: None,
+            errors: self.errors.clone(),
+            options: self.options.clone(),
+            thread_pool: self.thread_pool.clone(),
+            graph: DAGraph::new(),
+            dirty: HashSet::new(),
+            on_result: self.on_result.clone(),
+            cancellation: None,
+            progress: None,
+        }
+    }
+
+    /// Executes formulas like [`Engine::execute`], isolating failures to their dependents.
+    ///
+    /// Every formula whose dependencies all succeeded is executed. A formula with a
+    /// failed dependency is not run; instead a dependency error naming the failed
+    /// upstream formula is recorded in [`Engine::get_errors`]. This keeps any stale
+    /// result of an upstream formula from leaking into downstream results.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula, Value};
+    ///
+    /// let engine = Engine::new();
+    /// engine
+    ///     .execute_best_effort(vec![
+    ///         Formula::new("ok", "return 1"),
+    ///         Formula::new("bad", "return 1 / 0"),
+    ///         Formula::new("uses_ok", "return get_output_from('ok') + 1"),
+    ///         Formula::new("uses_bad", "return get_output_from('bad') + 1"),
+    ///     ])
+    ///     .unwrap();
+    ///
+    /// assert_eq!(engine.get_result("uses_ok"), Some(Value::Number(2.0)));
+    /// assert!(engine.get_errors()["uses_bad"].contains("'bad' failed"));
+    /// ```
+    pub fn execute_best_effort(&self, formulas: Vec<Formula>) -> Result<ExecutionReport> {
+        self.execute_graph(formulas, compile_formula, true)
+    }
+
+    /// Executes formulas against the engine's variables with `overrides` layered on top.
+    ///
+    /// Overrides shadow variables of the same name for this call only; the engine's
+    /// variables, results, errors, and memoized function results are left untouched.
+    /// Results and errors are only returned in the [`ExecutionReport`], so calls for
+    /// different records can run concurrently on a shared engine. Formulas may still
+    /// read results already stored in the engine through `get_output_from`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula, Value};
+    /// use std::collections::HashMap;
+    ///
+    /// let engine = Engine::new();
+    /// engine.set_variable("rate".to_string(), Value::Number(0.1));
+    /// engine.set_variable("amount".to_string(), Value::Number(100.0));
+    ///
+    /// let formulas = vec![Formula::new("fee", "return amount * rate")];
+    /// let overrides = HashMap::from([("amount".to_string(), Value::Number(250.0))]);
+    /// let report = engine.execute_with_variables(formulas, overrides).unwrap();
+    ///
+    /// assert_eq!(report.get_result("fee"), Some(Value::Number(25.0)));
+    /// assert_eq!(engine.get_result("fee"), None);
+    /// ```
+    pub fn execute_with_variables(
+        &self,
+        formulas: Vec<Formula>,
+        overrides: HashMap<String, Value>,
+    ) -> Result<ExecutionReport> {
+        let formula_result_cache = FormulaResultCache::new();
+        formula_result_cache.replace(self.formula_result_cache.entries());
+        let scoped = Engine {
+            variable_cache: self.variable_cache.with_overrides(overrides),
+            formula_result_cache,
+            function_cache: self.function_cache.clone(),
+            function_result_cache: self.empty_function_result_cache(),
+            async_function_cache: self.async_function_cache.clone(),
+            async_runner: None,
+            errors: ErrorCache::new(),
+            options: self.options.clone(),
+            thread_pool: self.thread_pool.clone(),
+            graph: DAGraph::new(),
+            dirty: HashSet::new(),
+            on_result: self.on_result.clone(),
+            cancellation: None,
+            progress: None,
+        };
+
+        scoped.execute(formulas)
+    }
+
+    /// Checks formulas for problems without executing them.
+    ///
+    /// Every formula is parsed, and the variables, custom functions, and
+    /// `get_output_from` targets it references are checked against the engine's
+    /// variables, the registered functions, and the names in `formulas`. Branches
+    /// are checked whether or not they would be taken at runtime.
+    ///
+    /// # Returns
+    ///
+    /// Returns the errors found for each formula name. Formulas without problems
+    /// are omitted, so an empty map means every formula is valid.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{CalculatorError, Engine, Formula, Value};
+    ///
+    /// let engine = Engine::new();
+    /// engine.set_variable("price".to_string(), Value::Number(100.0));
+    ///
+    /// let errors = engine.validate_all(&[
+    ///     Formula::new("net", "return price * (1 - discount)"),
+    ///     Formula::new("gross", "return get_output_from('net') * 1.2"),
+    /// ]);
+    ///
+    /// assert_eq!(
+    ///     errors["net"],
+    ///     vec![CalculatorError::VariableNotFound("discount".to_string())]
+    /// );
+    /// assert!(!errors.contains_key("gross"));
+    /// ```
+    pub fn validate_all(&self, formulas: &[Formula]) -> HashMap<String, Vec<CalculatorError>> {
+        let names: HashSet<&str> = formulas.iter().map(|formula| formula.name()).collect();
+        let mut errors = HashMap::new();
+
+        for formula in formulas {
+            let formula_errors = match compile(formula.body()).and_then(References::collect) {
+                Ok(references) => self.check_references(&references, &names),
+                Err(e) => vec![e],
+            };
+
+            if !formula_errors.is_empty() {
+                errors.insert(formula.name().to_string(), formula_errors);
+            }
+        }
+
+        errors
+    }
+
+    /// Returns the names of variables set on the engine that no formula in `formulas`
+    /// refers to, in sorted order.
+    ///
+    /// References are found by parsing each body, including untaken branches, as in
+    /// [`Engine::validate_all`]. Formulas that fail to parse are ignored, and names
+    /// only built at runtime, as by `lookup`, are not seen.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula, Value};
+    ///
+    /// let engine = Engine::new();
+    /// engine.set_variable("price".to_string(), Value::Number(100.0));
+    /// engine.set_variable("legacy_rate".to_string(), Value::Number(0.1));
+    ///
+    /// let unused = engine.unused_variables(&[Formula::new("net", "return price * 0.9")]);
+    /// assert_eq!(unused, ["legacy_rate"]);
+    /// ```
+    pub fn unused_variables(&self, formulas: &[Formula]) -> Vec<String> {
+        let referenced: HashSet<String> = formulas
+            .iter()
+            .filter_map(|formula| formula.ast().and_then(References::collect).ok())
+            .flat_map(|references| references.variables)
+            .collect();
+
+        let mut unused: Vec<String> = self
+            .variable_cache
+            .entries()
+            .into_keys()
+            .filter(|name| !referenced.contains(name))
+            .collect();
+        unused.sort();
+        unused
+    }
+
+    fn check_references(
+        &self,
+        references: &References,
+        formula_names: &HashSet<&str>,
+    ) -> Vec<CalculatorError> {
+        let mut errors = Vec::new();
+
+        for variable in &references.variables {
+            if !self.has_variable(variable) && !self.options.unknown_identifier_as_string {
+                errors.push(CalculatorError::VariableNotFound(variable.clone()));
+            }
+        }
+
+        for formula in &references.formulas {
+            if !formula_names.contains(formula.as_str()) {
+                errors.push(CalculatorError::FormulaNotFound(formula.clone()));
+            }
+        }
+
+        for (name, num_args) in &references.functions {
+            let function_id = build_function_id(name, *num_args);
+            if self.function_cache.get(&function_id).is_none()
+                && self.async_function_cache.get(&function_id).is_none()
+            {
+                errors.push(CalculatorError::FunctionNotFound(function_id));
+            }
+        }
+
+        errors
+    }
+
+    /// Parses and constant-folds formulas ahead of execution.
+    ///
+    /// The returned [`CompiledFormula`]s can be passed to [`Engine::execute_compiled`]
+    /// any number of times without re-parsing their bodies.
+    ///
+    /// # Returns
+    ///
+    /// Returns the compiled formulas in input order, or every parse error keyed by
+    /// formula name when any formula fails to compile.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula, Value};
+    ///
+    /// let engine = Engine::new();
+    /// let compiled = engine
+    ///     .precompile(&[Formula::new("doubled", "return x * 2")])
+    ///     .unwrap();
+    ///
+    /// for x in [1.0, 2.0] {
+    ///     engine.set_variable("x".to_string(), Value::Number(x));
+    ///     engine.execute_compiled(compiled.clone()).unwrap();
+    ///     assert_eq!(engine.get_result("doubled"), Some(Value::Number(x * 2.0)));
+    /// }
+    /// ```
+    pub fn precompile(
+        &self,
+        formulas: &[Formula],
+    ) -> std::result::Result<Vec<CompiledFormula>, HashMap<String, CalculatorError>> {
+        let mut compiled = Vec::with_capacity(formulas.len());
+        let mut errors = HashMap::new();
+        for formula in formulas {
+            match CompiledFormula::new(formula.clone()) {
+                Ok(formula) => compiled.push(formula),
+                Err(e) => {
+                    errors.insert(formula.name().to_string(), e);
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(compiled)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Executes precompiled formulas with automatic dependency resolution.
+    ///
+    /// Behaves like [`Engine::execute`] but skips parsing, since each
+    /// [`CompiledFormula`] already carries its program.
+    pub fn execute_compiled(&self, formulas: Vec<CompiledFormula>) -> Result<ExecutionReport> {
+        let skip = self.options.skip_on_dependency_error;
+        self.execute_graph(formulas, compiled_program, skip)
+    }
+
+    /// Registers a formula with the engine without executing it.
+    ///
+    /// The formula is parsed immediately and kept in the engine's dependency graph,
+    /// replacing any registered formula with the same name. It runs on the next
+    /// call to [`Engine::recompute`], together with every formula depending on it.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if the formula body fails to parse, or a
+    /// [`CalculatorError::DependencyError`] if its dependencies would form a cycle.
+    /// On error any registered formula with the same name is kept unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula, Value};
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.add_formula(Formula::new("price", "return 100")).unwrap();
+    /// engine.add_formula(Formula::new("tax", "return get_output_from('price') * 0.1")).unwrap();
+    /// assert_eq!(engine.get_result("tax"), None);
+    ///
+    /// engine.recompute().unwrap();
+    /// assert_eq!(engine.get_result("tax"), Some(Value::Number(10.0)));
+    /// ```
+    pub fn add_formula(&mut self, formula: Formula) -> Result<()> {
+        let compiled = CompiledFormula::new(formula)?;
+        let name = compiled.name().to_string();
+        let depends_on = compiled.depends_on().to_vec();
+
+        // Depending on the formula itself or on anything downstream of it is a cycle
+        let mut downstream = HashSet::new();
+        let mut pending = vec![name.clone()];
+        while let Some(node) = pending.pop() {
+            if downstream.insert(node.clone()) {
+                pending.extend(self.graph.dependents(&node));
+            }
+        }
+        if let Some(dependency) = depends_on.iter().find(|d| downstream.contains(*d)) {
+            return Err(CalculatorError::DependencyError(format!(
+                "formula '{}' would form a cycle through '{}'",
+                name, dependency
+            )));
+        }
+
+        let previous = self.graph.remove_node(&name);
+        if let Err(e) = self.graph.add_node(name.clone(), compiled, depends_on) {
+            if let Some(previous) = previous {
+                let depends_on = previous.depends_on().to_vec();
+                // The key was just freed, so restoring the old node cannot fail
+                let _ = self.graph.add_node(name, previous, depends_on);
+            }
+            return Err(CalculatorError::DependencyError(e));
+        }
+        self.dirty.insert(name);
+        Ok(())
+    }
+
+    /// Removes a formula previously registered with [`Engine::add_formula`].
+    ///
+    /// Its result and error are evicted, and formulas depending on it are marked
+    /// dirty so the next [`Engine::recompute`] reports their missing dependency.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if a formula with this name was registered.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula};
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.add_formula(Formula::new("a", "return 1")).unwrap();
+    /// engine.add_formula(Formula::new("b", "return get_output_from('a') + 1")).unwrap();
+    /// engine.recompute().unwrap();
+    ///
+    /// assert!(engine.remove_formula("a"));
+    /// engine.recompute().unwrap();
+    ///
+    /// assert_eq!(engine.get_result("a"), None);
+    /// assert_eq!(engine.get_result("b"), None);
+    /// assert!(engine.get_errors().contains_key("b"));
+    /// ```
+    pub fn remove_formula(&mut self, name: &str) -> bool {
+        let key = name.to_string();
+        if self.graph.remove_node(&key).is_none() {
+            return false;
+        }
+
+        self.formula_result_cache.remove(name);
+        self.errors.remove(name);
+        self.dirty.remove(name);
+        self.dirty.extend(self.graph.dependents(&key));
+        true
+    }
+
+    /// Renames a formula registered with [`Engine::add_formula`].
+    ///
+    /// The formula keeps its place in the dependency graph: edges from and to it are
+    /// rewritten, and its result, error, and dirty state move to the new name.
+    /// Bodies are not rewritten, so each dependent still calling
+    /// `get_output_from('old_name')` gets an entry in [`Engine::get_errors`] asking
+    /// for its body to be updated.
+    ///
+    /// # Returns
+    ///
+    /// Returns [`CalculatorError::FormulaNotFound`] if `old_name` is not registered, or
+    /// [`CalculatorError::DependencyError`] if `new_name` already is.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula, Value};
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.add_formula(Formula::new("price", "return 100")).unwrap();
+    /// engine.add_formula(Formula::new("tax", "return get_output_from('price') * 0.1")).unwrap();
+    /// engine.recompute().unwrap();
+    ///
+    /// engine.rename_formula("price", "base_price").unwrap();
+    ///
+    /// assert_eq!(engine.get_result("base_price"), Some(Value::Number(100.0)));
+    /// assert!(engine.get_errors()["tax"].contains("'price'"));
+    /// ```
+    pub fn rename_formula(&mut self, old_name: &str, new_name: &str) -> Result<()> {
+        let old_key = old_name.to_string();
+        if !self.graph.contains(&old_key) {
+            return Err(CalculatorError::FormulaNotFound(old_key));
+        }
+
+        self.graph
+            .rename_node(&old_key, new_name.to_string())
+            .map_err(CalculatorError::DependencyError)?;
+        if let Some(compiled) = self.graph.get_mut(&new_name.to_string()) {
+            compiled.set_name(new_name.to_string());
+        }
+
+        if let Some(value) = self.formula_result_cache.remove(old_name) {
+            self.formula_result_cache.set(new_name.to_string(), value);
+        }
+        if let Some(error) = self.errors.remove(old_name) {
+            self.errors.set(new_name.to_string(), error);
+        }
+        if self.dirty.remove(old_name) {
+            self.dirty.insert(new_name.to_string());
+        }
+
+        for dependent in self.graph.dependents(&new_name.to_string()) {
+            let references_old_name = self
+                .graph
+                .get(&dependent)
+                .is_some_and(|compiled| compiled.depends_on().contains(&old_key));
+
+            if references_old_name {
+                let error_msg = format!(
+                    "Formula '{}' still references renamed formula '{}'; update it to get_output_from('{}')",
+                    dependent, old_name, new_name
+                );
+                self.errors.set(dependent, error_msg);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Executes the dirty registered formulas and everything downstream of them.
+    ///
+    /// A formula is dirty after being added with [`Engine::add_formula`] or when a
+    /// dependency was removed. Formulas run in topological order, with each layer
+    /// executed in parallel like [`Engine::execute`]; clean formulas keep their
+    /// results. Variable changes are not tracked, so re-add a formula to force it
+    /// to run again.
+    pub fn recompute(&mut self) -> Result<ExecutionReport> {
+        let mut pending: Vec<String> = self.dirty.drain().collect();
+        let mut stale: HashSet<String> = HashSet::new();
+        while let Some(name) = pending.pop() {
+            if stale.insert(name.clone()) {
+                pending.extend(self.graph.dependents(&name));
+            }
+        }
+
+        for name in &stale {
+            self.formula_result_cache.remove(name);
+            self.errors.remove(name);
+            self.errors.remove_unresolved(name);
+        }
+
+        let timer = Timer::start();
+        let mut report = self.new_report();
+        let graph = std::mem::take(&mut self.graph);
+        let (layers, detached) = graph.topological_sort();
+
+        self.record_detached(&graph, detached, |name| stale.contains(name), &mut report);
+
+        let skip = self.options.skip_on_dependency_error;
+        let mut failed = HashSet::new();
+        for layer in layers {
+            let layer: Vec<String> = layer
+                .into_iter()
+                .filter(|name| stale.contains(name))
+                .collect();
+            if self.options.fail_fast && !report.failed.is_empty() {
+                report.skipped.extend(layer);
+            } else if !layer.is_empty() {
+                self.execute_layer_parallel(
+                    &graph,
+                    layer,
+                    compiled_program,
+                    skip.then_some(&mut failed),
+                    &mut report,
+                );
+            }
+        }
+
+        self.graph = graph;
+        report.finish(timer.elapsed());
+        Ok(report)
+    }
+
+    fn execute_graph<F>(
+        &self,
+        formulas: Vec<F>,
+        prepare: Prepare<F>,
+        skip_failed_dependencies: bool,
+    ) -> Result<ExecutionReport>
+    where
+        F: FormulaT + Sync,
+    {
+        let timer = Timer::start();
+        let mut report = self.new_report();
+        let mut graph = DAGraph::new();
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(
+            "execute",
+            formulas = formulas.len(),
+            layers = tracing::field::Empty
+        )
+        .entered();
+
+        // Build dependency graph
+        for formula in formulas {
+            let name = formula.name().to_string();
+            let depends_on = formula.depends_on().to_vec();
+            graph
+                .add_node(name, formula, depends_on)
+                .map_err(CalculatorError::DependencyError)?;
+        }
+
+        // Topological sort to get execution order
+        let (layers, detached) = graph.topological_sort();
+        #[cfg(feature = "tracing")]
+        span.record("layers", layers.len());
+
+        // Handle detached (unresolvable) formulas
+        self.record_detached(&graph, detached, |_| true, &mut report);
+
+        // Execute formulas layer by layer
+        // Formulas in the same layer can be executed in parallel
+        let mut failed = HashSet::new();
+        let mut layers = layers.into_iter().enumerate();
+        while let Some((index, layer)) = layers.next() {
+            if self.is_cancelled() {
+                report.cancelled = true;
+                report
+                    .skipped
+                    .extend(layer.into_iter().chain(layers.flat_map(|(_, layer)| layer)));
+                break;
+            }
+            if self.options.fail_fast && !report.failed.is_empty() {
+                report
+                    .skipped
+                    .extend(layer.into_iter().chain(layers.flat_map(|(_, layer)| layer)));
+                break;
+            }
+            self.notify_progress(ProgressEvent::LayerStarted {
+                index,
+                size: layer.len(),
+            });
+            #[cfg(feature = "tracing")]
+            let (layer_timer, layer_span) = (
+                Timer::start(),
+                tracing::info_span!(
+                    "layer",
+                    index,
+                    size = layer.len(),
+                    duration_us = tracing::field::Empty
+                )
+                .entered(),
+            );
+            self.execute_layer_parallel(
+                &graph,
+                layer,
+                prepare,
+                skip_failed_dependencies.then_some(&mut failed),
+                &mut report,
+            );
+            #[cfg(feature = "tracing")]
+            layer_span.record("duration_us", layer_timer.elapsed().as_micros() as u64);
+            self.notify_progress(ProgressEvent::LayerFinished { index });
+        }
+
+        report.finish(timer.elapsed());
+        Ok(report)
+    }
+
+    /// Records an error and the unresolved dependencies of each detached formula
+    /// accepted by `record`.
+    ///
+    /// A dependency is unresolved when it is not in the graph or is itself detached,
+    /// e.g. because it is part of a cycle.
+    fn record_detached<F: FormulaT>(
+        &self,
+        graph: &DAGraph<String, F>,
+        detached: Vec<String>,
+        record: impl Fn(&str) -> bool,
+        report: &mut ExecutionReport,
+    ) {
+        let detached_names: HashSet<&str> = detached.iter().map(String::as_str).collect();
+
+        for formula_name in detached.iter().filter(|name| record(name)) {
+            let missing = graph
+                .get(formula_name)
+                .map(|formula| {
+                    formula
+                        .depends_on()
+                        .iter()
+                        .filter(|dependency| {
+                            graph.get(dependency).is_none()
+                                || detached_names.contains(dependency.as_str())
+                        })
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            let error_msg = format!(
+                "Could not resolve dependency path for formula: '{}'",
+                formula_name
+            );
+            report.detached.push(formula_name.clone());
+            report
+                .errors
+                .insert(formula_name.clone(), error_msg.clone());
+            self.errors.set(formula_name.clone(), error_msg);
+            self.errors.set_unresolved(formula_name.clone(), missing);
+        }
+    }
+
+    /// Execute all formulas in a layer in parallel
+    ///
+    /// When `failed` is given, formulas depending on a formula in it are skipped with
+    /// a dependency error, and every formula failing in this layer is added to it.
+    fn execute_layer_parallel<F>(
+        &self,
+        graph: &DAGraph<String, F>,
+        mut layer: Vec<String>,
+        prepare: Prepare<F>,
+        mut failed: Option<&mut HashSet<String>>,
+        report: &mut ExecutionReport,
+    ) where
+        F: FormulaT + Sync,
+    {
+        let layer_timer = report.stats.is_some().then(Timer::start);
+        let layer_size = layer.len();
+        if let Some(failed) = failed.as_deref_mut() {
+            layer.retain(|formula_name| {
+                let failed_dependency = graph.get(formula_name).and_then(|formula| {
+                    formula
+                        .depends_on()
+                        .iter()
+                        .find(|dependency| failed.contains(*dependency))
+                });
+
+                match failed_dependency {
+                    Some(dependency) => {
+                        let error = CalculatorError::DependencyError(format!(
+                            "upstream formula '{}' failed",
+                            dependency
+                        ))
+                        .with_context(formula_name);
+                        // A completion report never turns an error into a value
+                        let error = self
+                            .report_completion(formula_name, Err(error), Duration::ZERO)
+                            .unwrap_err();
+                        let error_msg =
+                            format!("Error executing formula '{}': {}", formula_name, error);
+                        if let Some(on_result) = &self.on_result {
+                            on_result(formula_name, &Err(error));
+                        }
+                        self.record_failure(report, formula_name.clone(), error_msg);
+                        failed.insert(formula_name.clone());
+                        false
+                    }
+                    None => true,
+                }
+            });
+        }
+
+        // Execute formulas in parallel
+        let engine = self;
+        // Worker threads do not inherit a scoped subscriber, so hand it over
+        #[cfg(feature = "tracing")]
+        let (dispatch, layer_span) = (
+            tracing::dispatcher::get_default(Clone::clone),
+            tracing::Span::current(),
+        );
+        let timed = report.stats.is_some() || self.progress.is_some();
+        let execute = || -> Vec<(String, Result<Value>, Option<FormulaTiming>)> {
+            layer
+                .par_iter()
+                .filter_map(|formula_name| {
+                    graph.get(formula_name).map(|formula| {
+                        #[cfg(feature = "tracing")]
+                        let (result, timing) = tracing::dispatcher::with_default(&dispatch, || {
+                            let span = tracing::info_span!(
+                                parent: &layer_span,
+                                "formula",
+                                name = %formula_name,
+                                outcome = tracing::field::Empty
+                            );
+                            let _entered = span.enter();
+                            let (result, timing) = engine.run_formula(formula, prepare, timed);
+                            let outcome = if result.is_ok() { "ok" } else { "error" };
+                            span.record("outcome", tracing::field::display(outcome));
+                            (result, timing)
+                        });
+                        #[cfg(not(feature = "tracing"))]
+                        let (result, timing) = engine.run_formula(formula, prepare, timed);
+                        let duration = timing.map_or(Duration::ZERO, |timing| timing.total());
+                        let result = engine.report_completion(formula_name, result, duration);
+                        (formula_name.clone(), result, timing)
+                    })
+                })
+                .collect()
+        };
+        let results = match &self.thread_pool {
+            Some(pool) => pool.install(execute),
+            None => execute(),
+        };
+
+        // Process results sequentially to update caches and collect errors
+        for (formula_name, result, timing) in results {
+            if let (Some(stats), Some(timing)) = (report.stats.as_mut(), timing) {
+                stats.formulas.insert(formula_name.clone(), timing);
+            }
+            if let Some(on_result) = &self.on_result {
+                on_result(&formula_name, &result);
+            }
+            match result {
+                Ok(value) => {
+                    report.succeeded.push(formula_name.clone());
+                    report.results.insert(formula_name.clone(), value.clone());
+                    self.formula_result_cache.set(formula_name, value);
+                }
+                Err(e) => {
+                    if e.root() == &CalculatorError::Cancelled {
+                        report.cancelled = true;
+                    }
+                    let error_msg = format!("Error executing formula '{}': {}", formula_name, e);
+                    if let Some(failed) = failed.as_deref_mut() {
+                        failed.insert(formula_name.clone());
+                    }
+                    self.record_failure(report, formula_name, error_msg);
+                }
+            }
+        }
+
+        if let (Some(stats), Some(timer)) = (report.stats.as_mut(), layer_timer) {
+            stats.layers.push(LayerStats {
+                size: layer_size,
+                duration: timer.elapsed(),
+            });
+        }
+    }
+
+    /// Parses and evaluates one formula, timing each phase when `timed`.
+    fn run_formula<F: FormulaT>(
+        &self,
+        formula: &F,
+        prepare: Prepare<F>,
+        timed: bool,
+    ) -> (Result<Value>, Option<FormulaTiming>) {
+        let timer = timed.then(Timer::start);
+        let program = prepare(formula);
+        let parse = timer.as_ref().map(|timer| match program {
+            Ok(Cow::Borrowed(_)) => Duration::ZERO,
+            _ => timer.elapsed(),
+        });
+        let result = program
+            .and_then(|program| self.evaluate_program(&program))
+            .map_err(|e| e.with_context(formula.name()));
+        let timing = timer.zip(parse).map(|(timer, parse)| FormulaTiming {
+            parse,
+            eval: timer.elapsed().saturating_sub(parse),
+        });
+        (result, timing)
+    }
+
+    /// Starts an empty report, collecting statistics if the options ask for them.
+    fn new_report(&self) -> ExecutionReport {
+        ExecutionReport {
+            stats: self.options.collect_run_stats.then(RunStats::default),
+            ..ExecutionReport::default()
+        }
+    }
+
+    /// Sends `event` to the progress callback, if any, returning the panic message
+    /// if the callback panicked.
+    fn notify_progress(&self, event: ProgressEvent) -> Option<String> {
+        let progress = self.progress.as_ref()?;
+        std::panic::catch_unwind(AssertUnwindSafe(|| progress(event)))
+            .err()
+            .map(|payload| {
+                payload
+                    .downcast_ref::<&str>()
+                    .map(|msg| msg.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_default()
+            })
+    }
+
+    /// Reports a completed formula to the progress callback, turning a panic in the
+    /// callback into the formula's error.
+    fn report_completion(
+        &self,
+        formula_name: &str,
+        result: Result<Value>,
+        duration: Duration,
+    ) -> Result<Value> {
+        if self.progress.is_none() {
+            return result;
+        }
+        let event = ProgressEvent::FormulaCompleted {
+            name: formula_name.to_string(),
+            result: result.clone(),
+            duration,
+        };
+        match self.notify_progress(event) {
+            Some(msg) => Err(CalculatorError::EvalError(format!(
+                "progress callback panicked: {}",
+                msg
+            ))
+            .with_context(formula_name)),
+            None => result,
+        }
+    }
+
+    /// Returns `true` if this engine is driving a cancelled `execute_cancellable` call.
+    fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+    }
+
+    /// Records a failed formula's error in the engine and in `report`.
+    fn record_failure(
+        &self,
+        report: &mut ExecutionReport,
+        formula_name: String,
+        error_msg: String,
+    ) {
+        report.failed.push(formula_name.clone());
+        report
+            .errors
+            .insert(formula_name.clone(), error_msg.clone());
+        self.errors.set(formula_name, error_msg);
+    }
+
+    fn try_execute_formula<F: FormulaT + ?Sized>(&self, formula: &F) -> Result<Value> {
+        compile(formula.body())
+            .and_then(|program| self.evaluate_program(&program))
+            .map_err(|e| e.with_context(formula.name()))
+    }
+
+    fn evaluate_program(&self, program: &Program) -> Result<Value> {
+        let evaluator = Evaluator::new(
+            self.variable_cache.clone(),
+            self.formula_result_cache.clone(),
+            self.function_cache.clone(),
+            self.function_result_cache.clone(),
+        )
+        .with_async_functions(self.async_function_cache.clone(), self.async_runner.clone())
+        .with_options(self.options.clone())
+        .with_cancellation(self.cancellation.clone());
+
+        let value = evaluator.evaluate(program)?;
+
+        match value {
+            Value::Number(n) if self.options.reject_nonfinite && !n.is_finite() => {
+                Err(CalculatorError::EvalError("non-finite result".to_string()))
+            }
+            _ => Ok(value),
+        }
+    }
+
+    /// Retrieves the result of a previously executed formula.
+    ///
+    /// # Arguments
+    ///
+    /// * `formula_name` - The name of the formula whose result to retrieve
+    ///
+    /// # Returns
+    ///
+    /// Returns `Some(Value)` if the formula executed successfully, or `None` if the
+    /// formula hasn't been executed or failed with an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula, Value};
+    ///
+    /// let engine = Engine::new();
+    /// let formula = Formula::new("test", "return 42");
+    /// engine.execute(vec![formula]).unwrap();
+    ///
+    /// assert_eq!(engine.get_result("test"), Some(Value::Number(42.0)));
+    /// assert_eq!(engine.get_result("nonexistent"), None);
+    /// ```
+    pub fn get_result(&self, formula_name: &str) -> Option<Value> {
+        self.formula_result_cache.peek(formula_name)
+    }
+
+    /// Retrieves the result of a formula created with [`Formula::new_namespaced`].
+    ///
+    /// Equivalent to `get_result("namespace.name")`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula, Value};
+    ///
+    /// let engine = Engine::new();
+    /// engine
+    ///     .execute(vec![Formula::new_namespaced("pricing", "base", "return 100")])
+    ///     .unwrap();
+    ///
+    /// assert_eq!(engine.get_result_ns("pricing", "base"), Some(Value::Number(100.0)));
+    /// ```
+    pub fn get_result_ns(&self, namespace: &str, formula_name: &str) -> Option<Value> {
+        self.get_result(&qualified_name(namespace, formula_name))
+    }
+
+    /// Retrieves the result of a formula, or `default` if there is none.
+    ///
+    /// The default is returned both when the formula hasn't been executed and when
+    /// it failed with an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula, Value};
+    ///
+    /// let engine = Engine::new();
+    /// engine.execute(vec![Formula::new("bad", "return 1 / 0")]).unwrap();
+    ///
+    /// assert_eq!(engine.get_result_or("bad", Value::Number(0.0)), Value::Number(0.0));
+    /// ```
+    pub fn get_result_or(&self, formula_name: &str, default: Value) -> Value {
+        self.get_result(formula_name).unwrap_or(default)
+    }
+
+    /// Returns a map of all errors that occurred during the last execution.
+    ///
+    /// The map keys are formula names and values are error messages.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula};
+    ///
+    /// let engine = Engine::new();
+    /// let formula = Formula::new("bad", "return 1 / 0");
+    /// engine.execute(vec![formula]).unwrap();
+    ///
+    /// assert!(!engine.get_errors().is_empty());
+    /// ```
+    pub fn get_errors(&self) -> HashMap<String, String> {
+        self.errors.errors()
+    }
+
+    /// Returns the formulas that could not be placed in the execution order, mapped to
+    /// the dependencies they could not resolve.
+    ///
+    /// A dependency is unresolved when no formula with that name was supplied, or when
+    /// it is unresolved itself (for example, because it is part of a cycle). These
+    /// formulas also have an entry in [`Engine::get_errors`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula};
+    ///
+    /// let engine = Engine::new();
+    /// engine
+    ///     .execute(vec![Formula::new("total", "return get_output_from('subtotal') * 2")])
+    ///     .unwrap();
+    ///
+    /// assert_eq!(engine.get_unresolved_dependencies()["total"], vec!["subtotal".to_string()]);
+    /// ```
+    pub fn get_unresolved_dependencies(&self) -> HashMap<String, Vec<String>> {
+        self.errors.unresolved()
+    }
+
+    /// Returns hit/miss counts for the function and formula result caches.
+    ///
+    /// Counts accumulate across executions until [`Engine::clear`]. Reading results
+    /// with [`Engine::get_result`] is not counted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula};
+    ///
+    /// let engine = Engine::new();
+    /// engine
+    ///     .execute(vec![
+    ///         Formula::new("a", "return 2"),
+    ///         Formula::new("b", "return get_output_from('a') * get_output_from('a')"),
+    ///     ])
+    ///     .unwrap();
+    ///
+    /// assert_eq!(engine.cache_stats().formula_results.hits, 2);
+    /// ```
+    pub fn cache_stats(&self) -> EngineCacheStats {
+        EngineCacheStats {
+            function_results: self.function_result_cache.stats(),
+            formula_results: self.formula_result_cache.stats(),
+        }
+    }
+
+    /// Captures the current variables and formula results.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula, Value};
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.set_variable("x".to_string(), Value::Number(1.0));
+    /// let snapshot = engine.snapshot();
+    ///
+    /// engine.set_variable("x".to_string(), Value::Number(2.0));
+    /// engine.restore(snapshot);
+    ///
+    /// engine.execute(vec![Formula::new("x_value", "return x")]).unwrap();
+    /// assert_eq!(engine.get_result("x_value"), Some(Value::Number(1.0)));
+    /// ```
+    pub fn snapshot(&self) -> EngineSnapshot {
+        EngineSnapshot {
+            variables: self.variable_cache.entries(),
+            results: self.formula_result_cache.entries(),
+        }
+    }
+
+    /// Replaces the current variables and formula results with those of `snapshot`.
+    ///
+    /// Registered functions and recorded errors are left untouched.
+    pub fn restore(&mut self, snapshot: EngineSnapshot) {
+        self.variable_cache.replace(snapshot.variables);
+        self.formula_result_cache.replace(snapshot.results);
+    }
+
+    /// Captures the current variables and formula results; same as [`Engine::snapshot`].
+    ///
+    /// Functions are not part of the state and must be registered again on the
+    /// engine that imports it.
+    pub fn export_state(&self) -> EngineState {
+        self.snapshot()
+    }
+
+    /// Replaces the current variables and formula results with those of `state`;
+    /// same as [`Engine::restore`].
+    pub fn import_state(&mut self, state: EngineState) {
+        self.restore(state);
+    }
+
+    /// Clears all variables, formula results, function result caches, and errors.
+    ///
+    /// Note: Registered custom functions and formulas are preserved; formulas added
+    /// with [`Engine::add_formula`] run again on the next [`Engine::recompute`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Engine, Formula, Value};
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.set_variable("x".to_string(), Value::Number(10.0));
+    /// let formula = Formula::new("test", "return x");
+    /// engine.execute(vec![formula]).unwrap();
+    ///
+    /// engine.clear();
+    ///
+    /// assert_eq!(engine.get_result("test"), None);
+    /// ```
+    pub fn clear(&mut self) {
+        self.variable_cache.clear();
+        self.formula_result_cache.clear();
+        self.function_result_cache.clear();
+        self.errors.clear();
+        self.dirty.extend(self.graph.keys().cloned());
+    }
+}
+
+/// Parses a formula's body for execution.
+fn compile_formula<F: FormulaT>(formula: &F) -> Result<Cow<'_, Program>> {
+    compile(formula.body()).map(Cow::Owned)
+}
+
+/// Returns a precompiled formula's program without parsing it again.
+fn compiled_program(compiled: &CompiledFormula) -> Result<Cow<'_, Program>> {
+    Ok(Cow::Borrowed(compiled.program()))
+}
+
+/// Measures wall-clock time where the target has a monotonic clock.
+struct Timer {
+    #[cfg(not(target_arch = "wasm32"))]
+    started: std::time::Instant,
+}
+
+impl Timer {
+    fn start() -> Self {
+        Timer {
+            #[cfg(not(target_arch = "wasm32"))]
+            started: std::time::Instant::now(),
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.started.elapsed();
+        #[cfg(target_arch = "wasm32")]
+        return Duration::ZERO;
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Fluent configuration for an [`Engine`], created by [`Engine::builder`].
+///
+/// Each option method sets the [`EngineOptions`] field of the same name. Functions
+/// and variables are added to the engine when it is built.
+///
+/// # Examples
+///
+/// ```
+/// use formcalc::{Engine, Formula, Value};
+///
+/// let engine = Engine::builder()
+///     .strict_math(true)
+///     .parallelism(2)
+///     .variable("x", Value::Number(1e200))
+///     .build()
+///     .unwrap();
+///
+/// engine.execute(vec![Formula::new("big", "return x * x")]).unwrap();
+/// assert!(engine.get_errors()["big"].contains("produced inf"));
+/// ```
+#[derive(Default)]
+pub struct EngineBuilder {
+    options: EngineOptions,
+    function_cache_capacity: Option<usize>,
+    functions: Vec<Arc<dyn Function>>,
+    async_functions: Vec<Arc<dyn AsyncFunction>>,
+    variables: HashMap<String, Value>,
+    on_result: Option<Box<ResultCallback>>,
+}
+
+impl EngineBuilder {
+    /// Replaces every option at once; later option methods adjust the result.
+    pub fn options(mut self, options: EngineOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// See [`EngineOptions::reject_nonfinite`].
+    pub fn reject_nonfinite(mut self, reject: bool) -> Self {
+        self.options.reject_nonfinite = reject;
+        self
+    }
+
+    /// See [`EngineOptions::strict_math`].
+    pub fn strict_math(mut self, strict: bool) -> Self {
+        self.options.strict_math = strict;
+        self
+    }
+
+    /// See [`EngineOptions::skip_on_dependency_error`].
+    pub fn skip_on_dependency_error(mut self, skip: bool) -> Self {
+        self.options.skip_on_dependency_error = skip;
+        self
+    }
+
+    /// See [`EngineOptions::unknown_identifier_as_string`].
+    pub fn unknown_identifier_as_string(mut self, enabled: bool) -> Self {
+        self.options.unknown_identifier_as_string = enabled;
+        self
+    }
+
+    /// See [`EngineOptions::max_eval_steps`].
+    pub fn max_eval_steps(mut self, max_steps: usize) -> Self {
+        self.options.max_eval_steps = Some(max_steps);
+        self
+    }
+
+    /// See [`EngineOptions::collect_run_stats`].
+    pub fn collect_run_stats(mut self, enabled: bool) -> Self {
+        self.options.collect_run_stats = enabled;
+        self
+    }
+
+    /// See [`EngineOptions::holidays`].
+    pub fn holidays(mut self, holidays: Vec<NaiveDate>) -> Self {
+        self.options.holidays = holidays;
+        self
+    }
+
+    /// Runs each dependency layer on a dedicated pool of `threads` threads; see
+    /// [`EngineOptions::parallelism`].
+    pub fn parallelism(mut self, threads: usize) -> Self {
+        self.options.parallelism = Some(threads);
+        self
+    }
+
+    /// Same as [`EngineBuilder::parallelism`].
+    pub fn max_threads(self, threads: usize) -> Self {
+        self.parallelism(threads)
+    }
+
+    /// See [`EngineOptions::fail_fast`].
+    pub fn fail_fast(mut self, enabled: bool) -> Self {
+        self.options.fail_fast = enabled;
+        self
+    }
+
+    /// See [`Engine::set_function_cache_capacity`].
+    pub fn function_cache_capacity(mut self, capacity: usize) -> Self {
+        self.function_cache_capacity = Some(capacity);
+        self
+    }
+
+    /// Registers `function` when the engine is built; see [`Engine::register_function`].
+    pub fn register_function(mut self, function: Arc<dyn Function>) -> Self {
+        self.functions.push(function);
+        self
+    }
+
+    /// Registers `function` when the engine is built; see
+    /// [`Engine::register_async_function`].
+    pub fn register_async_function(mut self, function: Arc<dyn AsyncFunction>) -> Self {
+        self.async_functions.push(function);
+        self
+    }
+
+    /// Sets a variable when the engine is built.
+    pub fn variable(mut self, name: impl Into<String>, value: Value) -> Self {
+        self.variables.insert(name.into(), value);
+        self
+    }
+
+    /// See [`Engine::set_on_result`].
+    pub fn on_result(mut self, callback: Box<ResultCallback>) -> Self {
+        self.on_result = Some(callback);
+        self
+    }
+
+    /// Creates the configured engine.
+    ///
+    /// Fails like [`Engine::with_options`] for a parallelism of zero, and like
+    /// [`Engine::register_function`] if a function name is reserved or registered twice.
+    pub fn build(self) -> Result<Engine> {
+        let mut engine = Engine::with_options(self.options)?;
+        if let Some(capacity) = self.function_cache_capacity {
+            engine.set_function_cache_capacity(capacity);
+        }
+        for function in self.functions {
+            engine.register_function(function)?;
+        }
+        for function in self.async_functions {
+            engine.register_async_function(function)?;
+        }
+        engine.set_variables_batch(self.variables);
+        if let Some(callback) = self.on_result {
+            engine.set_on_result(callback);
+        }
+        Ok(engine)
+    }
+}
+
+/// Builds the ID of a custom function, rejecting names formulas could never call.
+fn callable_function_id(name: &str, num_args: usize) -> Result<String> {
+    if is_keyword(&to_snake_case(name)) {
+        return Err(CalculatorError::InvalidArgument(format!(
+            "Function name '{}' is reserved by a keyword or built-in function",
+            name
+        )));
+    }
+    Ok(build_function_id(name, num_args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use static_assertions::assert_impl_all;
+
+    // Hosts share engines across threads, e.g. behind `Arc<Mutex<Engine>>`
+    assert_impl_all!(Engine: Send, Sync);
+    assert_impl_all!(EngineSnapshot: Send, Sync);
+
+    struct PoolSize;
+
+    impl Function for PoolSize {
+        fn name(&self) -> &str {
+            "pool_size"
+        }
+
+        fn num_args(&self) -> usize {
+            0
+        }
+
+        fn execute(&self, _params: &[Value]) -> Result<Value> {
+            Ok(Value::Number(rayon::current_num_threads() as f64))
+        }
+    }
+
+    #[test]
+    fn test_with_options_uses_dedicated_pool() {
+        for threads in [1, 3] {
+            let mut engine = Engine::with_options(EngineOptions {
+                parallelism: Some(threads),
+                ..EngineOptions::default()
+            })
+            .unwrap();
+            engine.register_function(Arc::new(PoolSize)).unwrap();
+
+            engine
+                .execute(vec![
+                    Formula::new("threads", "return pool_size()"),
+                    Formula::new("other", "return 1"),
+                ])
+                .unwrap();
+            assert_eq!(
+                engine.get_result("threads"),
+                Some(Value::Number(threads as f64))
+            );
+        }
+
+        assert!(matches!(
+            Engine::with_options(EngineOptions {
+                parallelism: Some(0),
+                ..EngineOptions::default()
+            }),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_builder_combines_options() {
+        let formulas = || {
+            vec![
+                Formula::new("label", "return draft"),
+                Formula::new("overflow", "return huge * huge"),
+                Formula::new("threads", "return pool_size()"),
+                Formula::new("downstream", "return get_output_from('overflow') + 1"),
+            ]
+        };
+
+        let engine = Engine::builder()
+            .register_function(Arc::new(PoolSize))
+            .variable("huge", Value::Number(1e200))
+            .build()
+            .unwrap();
+        engine.execute(formulas()).unwrap();
+        assert!(engine.get_errors()["label"].contains("Variable not found"));
+        assert_eq!(
+            engine.get_result("overflow"),
+            Some(Value::Number(f64::INFINITY))
+        );
+        assert_eq!(
+            engine.get_result("downstream"),
+            Some(Value::Number(f64::INFINITY))
+        );
+
+        let engine = Engine::builder()
+            .unknown_identifier_as_string(true)
+            .strict_math(true)
+            .skip_on_dependency_error(true)
+            .parallelism(2)
+            .register_function(Arc::new(PoolSize))
+            .variable("huge", Value::Number(1e200))
+            .build()
+            .unwrap();
+        engine.execute(formulas()).unwrap();
+        assert_eq!(engine.get_result("label"), Some(Value::from("draft")));
+        assert_eq!(engine.get_result("threads"), Some(Value::Number(2.0)));
+        let errors = engine.get_errors();
+        assert!(errors["overflow"].contains("produced inf"));
+        assert!(errors["downstream"].contains("'overflow' failed"));
+
+        assert!(matches!(
+            Engine::builder().parallelism(0).build(),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            Engine::builder()
+                .register_function(Arc::new(PoolSize))
+                .register_function(Arc::new(PoolSize))
+                .build(),
+            Err(CalculatorError::FunctionAlreadyRegistered(_))
+        ));
+    }
+
+    #[test]
+    fn test_fail_fast_stops_after_failing_layer() {
+        let formulas = || {
+            vec![
+                Formula::new("bad", "return missing * 2"),
+                Formula::new("ok", "return 1"),
+                Formula::new("next", "return get_output_from('ok') + 1"),
+            ]
+        };
+
+        let engine = Engine::builder().max_threads(2).build().unwrap();
+        let report = engine.execute(formulas()).unwrap();
+        assert_eq!(report.get_result("next"), Some(Value::Number(2.0)));
+        assert!(report.skipped().is_empty());
+
+        let engine = Engine::builder()
+            .fail_fast(true)
+            .max_threads(2)
+            .build()
+            .unwrap();
+        let report = engine.execute(formulas()).unwrap();
+        assert_eq!(report.failed(), ["bad"]);
+        assert_eq!(report.get_result("ok"), Some(Value::Number(1.0)));
+        assert_eq!(report.skipped(), ["next"]);
+        assert_eq!(engine.get_result("next"), None);
+        assert!(!report.is_success());
+
+        let mut engine = Engine::new();
+        engine.set_fail_fast(true);
+        for formula in formulas() {
+            engine.add_formula(formula).unwrap();
+        }
+        let report = engine.recompute().unwrap();
+        assert_eq!(report.skipped(), ["next"]);
+    }
+
+    #[test]
+    fn test_simple_formula() {
+        let engine = Engine::new();
+        let formula = Formula::new("test", "return 2 + 2");
+
+        engine.execute(vec![formula]).unwrap();
+
+        let result = engine.get_result("test").unwrap();
         assert_eq!(result, Value::Number(4.0));
     }
 
     #[test]
-    fn test_formula_with_variable() {
-        let mut engine = Engine::new();
-        engine.set_variable("x".to_string(), Value::Number(10.0));
+    fn test_formula_with_variable() {
+        let engine = Engine::new();
+        engine.set_variable("x".to_string(), Value::Number(10.0));
+
+        let formula = Formula::new("test", "return x * 2");
+        engine.execute(vec![formula]).unwrap();
+
+        let result = engine.get_result("test").unwrap();
+        assert_eq!(result, Value::Number(20.0));
+    }
+
+    #[test]
+    fn test_formula_dependencies() {
+        let engine = Engine::new();
+
+        let formula1 = Formula::new("first", "return 10");
+        let formula2 = Formula::new("second", "return get_output_from('first') * 2");
+
+        engine.execute(vec![formula1, formula2]).unwrap();
+
+        // Check for errors
+        if !engine.get_errors().is_empty() {
+            for (name, error) in engine.get_errors() {
+                eprintln!("Error in {}: {}", name, error);
+            }
+        }
+
+        let result = engine
+            .get_result("second")
+            .expect("second formula should have result");
+        assert_eq!(result, Value::Number(20.0));
+    }
+
+    #[test]
+    fn test_explicit_dependencies_order_dynamic_references() {
+        let engine = Engine::new();
+        engine.set_variable("quarter".to_string(), Value::String("2".to_string()));
+        engine
+            .execute(vec![
+                Formula::new_explicit(
+                    "latest",
+                    "return get_output_from('q' + quarter) * 10",
+                    vec!["q2".to_string()],
+                ),
+                Formula::new("q2", "return 4"),
+            ])
+            .unwrap();
+
+        assert!(engine.get_errors().is_empty());
+        assert_eq!(engine.get_result("latest"), Some(Value::Number(40.0)));
+    }
+
+    #[test]
+    fn test_namespaced_formulas() {
+        let engine = Engine::new();
+        engine
+            .execute(vec![
+                Formula::new_namespaced("pricing", "base", "return 100"),
+                Formula::new_namespaced("shipping", "base", "return 5"),
+                Formula::new_namespaced(
+                    "billing",
+                    "total",
+                    "return get_output_from('pricing.base') + get_output_from('shipping.base')",
+                ),
+                Formula::new("base", "return 1"),
+            ])
+            .unwrap();
+
+        assert!(engine.get_errors().is_empty());
+        assert_eq!(
+            engine.get_result_ns("billing", "total"),
+            Some(Value::Number(105.0))
+        );
+        assert_eq!(
+            engine.get_result("pricing.base"),
+            Some(Value::Number(100.0))
+        );
+        assert_eq!(engine.get_result_ns("", "base"), Some(Value::Number(1.0)));
+    }
+
+    #[test]
+    fn test_fork_is_independent() {
+        let mut base = Engine::new();
+        base.set_variable("x".to_string(), Value::Number(1.0));
+        base.register_function(Arc::new(PoolSize)).unwrap();
+        base.execute(vec![Formula::new("doubled", "return x * 2")])
+            .unwrap();
+
+        let mut fork = base.fork();
+        assert_eq!(fork.get_result("doubled"), Some(Value::Number(2.0)));
+
+        fork.set_variable("x".to_string(), Value::Number(10.0));
+        fork.set_variable("y".to_string(), Value::Number(5.0));
+        fork.execute(vec![
+            Formula::new("doubled", "return x * 2"),
+            Formula::new("threads", "return pool_size() * 0"),
+        ])
+        .unwrap();
+        assert_eq!(fork.get_result("doubled"), Some(Value::Number(20.0)));
+        assert_eq!(fork.get_result("threads"), Some(Value::Number(0.0)));
+
+        assert!(!base.has_variable("y"));
+        assert_eq!(base.snapshot().variables()["x"], Value::Number(1.0));
+        assert_eq!(base.get_result("doubled"), Some(Value::Number(2.0)));
+        assert_eq!(base.get_result("threads"), None);
+
+        struct Triple;
+        impl Function for Triple {
+            fn name(&self) -> &str {
+                "triple"
+            }
+            fn num_args(&self) -> usize {
+                1
+            }
+            fn execute(&self, params: &[Value]) -> Result<Value> {
+                match params[0] {
+                    Value::Number(n) => Ok(Value::Number(n * 3.0)),
+                    _ => Err(CalculatorError::TypeError("Expected number".to_string())),
+                }
+            }
+        }
+        fork.register_function(Arc::new(Triple)).unwrap();
+        base.execute(vec![Formula::new("tripled", "return triple(1)")])
+            .unwrap();
+        assert!(base.get_errors().contains_key("tripled"));
+    }
+
+    #[test]
+    fn test_clone_detached_without_results() {
+        let mut template = Engine::new();
+        template.set_variable("x".to_string(), Value::Number(2.0));
+        template.register_function(Arc::new(PoolSize)).unwrap();
+        template
+            .add_formula(Formula::new("doubled", "return x * 2"))
+            .unwrap();
+        template.recompute().unwrap();
+        template
+            .execute(vec![Formula::new("broken", "return 1 / 0")])
+            .unwrap();
+
+        let mut copy = template.clone_detached(false);
+        assert!(copy.has_variable("x"));
+        assert!(copy.function_cache.get("pool_size_0").is_some());
+        assert_eq!(copy.get_result("doubled"), None);
+        assert!(copy.get_errors().is_empty());
+
+        // Registered formulas are rerun from scratch
+        copy.set_variable("x".to_string(), Value::Number(5.0));
+        copy.recompute().unwrap();
+        assert_eq!(copy.get_result("doubled"), Some(Value::Number(10.0)));
+        assert_eq!(template.get_result("doubled"), Some(Value::Number(4.0)));
+        assert!(template.get_errors().contains_key("broken"));
+
+        let with_results = template.clone_detached(true);
+        assert_eq!(with_results.get_result("doubled"), Some(Value::Number(4.0)));
+    }
+
+    #[test]
+    fn test_fork_shares_function_handles_but_not_caches() {
+        let mut base = Engine::new();
+        base.register_function(Arc::new(PoolSize)).unwrap();
+        let mut fork = base.fork();
+
+        let base_function = base.function_cache.get("pool_size_0").unwrap();
+        let fork_function = fork.function_cache.get("pool_size_0").unwrap();
+        assert!(Arc::ptr_eq(&base_function, &fork_function));
+
+        // The registries holding the handles are separate
+        assert!(fork.unregister_function("pool_size", 0));
+        assert!(base.function_cache.get("pool_size_0").is_some());
+    }
+
+    #[test]
+    fn test_parse_errors_are_recorded_per_formula() {
+        let engine = Engine::new();
+        engine
+            .execute(vec![
+                Formula::new("broken", "return (1 +"),
+                Formula::new("also_broken", "return 2 $ 3"),
+                Formula::new("valid", "return 6 * 7"),
+                Formula::new("dependent", "return get_output_from('valid') + 1"),
+            ])
+            .unwrap();
+
+        let errors = engine.get_errors();
+        assert_eq!(errors.len(), 2);
+        assert!(errors["broken"].contains("Parse error"));
+        assert!(errors["also_broken"].contains("Parse error"));
+        assert_eq!(engine.get_result("valid"), Some(Value::Number(42.0)));
+        assert_eq!(engine.get_result("dependent"), Some(Value::Number(43.0)));
+    }
+
+    #[test]
+    fn test_execute_tagged() {
+        let tags = |tags: &[&str]| tags.iter().map(|tag| tag.to_string()).collect();
+        let formulas = vec![
+            Formula::new("rate", "return 0.5"),
+            Formula::new("base", "return 100 * get_output_from('rate')"),
+            Formula::new("price", "return get_output_from('base') + 1")
+                .with_tags(tags(&["pricing"])),
+            Formula::new("shipping", "return 5").with_tags(tags(&["pricing", "logistics"])),
+            Formula::new("check", "return 1").with_tags(tags(&["validation"])),
+            Formula::new("unrelated", "return 2"),
+        ];
+
+        let engine = Engine::new();
+        engine
+            .execute_tagged(formulas.clone(), &["pricing"])
+            .unwrap();
+        assert!(engine.get_errors().is_empty());
+        assert_eq!(engine.get_result("price"), Some(Value::Number(51.0)));
+        assert_eq!(engine.get_result("shipping"), Some(Value::Number(5.0)));
+        assert_eq!(engine.get_result("rate"), Some(Value::Number(0.5)));
+        assert_eq!(engine.get_result("check"), None);
+        assert_eq!(engine.get_result("unrelated"), None);
+
+        let engine = Engine::new();
+        engine
+            .execute_tagged(formulas, &["validation", "logistics"])
+            .unwrap();
+        assert_eq!(engine.get_result("check"), Some(Value::Number(1.0)));
+        assert_eq!(engine.get_result("shipping"), Some(Value::Number(5.0)));
+        assert_eq!(engine.get_result("price"), None);
+    }
+
+    #[test]
+    fn test_execute_one() {
+        let engine = Engine::new();
+        engine.set_variable("x".to_string(), Value::Number(3.0));
+        engine
+            .execute(vec![Formula::new("base", "return 10")])
+            .unwrap();
+
+        assert_eq!(
+            engine.execute_one(&Formula::new("sum", "return get_output_from('base') + x")),
+            Ok(Value::Number(13.0))
+        );
+        let error = engine
+            .execute_one(&Formula::new("bad", "return (x"))
+            .unwrap_err();
+        assert!(matches!(error.root(), CalculatorError::ParseError(_)));
+        assert_eq!(
+            error.context().unwrap().formula_name.as_deref(),
+            Some("bad")
+        );
+        let error = engine
+            .execute_one(&Formula::new("orphan", "return get_output_from('absent')"))
+            .unwrap_err();
+        assert!(matches!(error.root(), CalculatorError::FormulaNotFound(_)));
+
+        // A computed name gets a hint instead, since it is never a detected dependency
+        engine.set_variable("suffix".to_string(), Value::from("absent"));
+        let error = engine
+            .execute_one(&Formula::new(
+                "dynamic",
+                "return get_output_from('b' + suffix)",
+            ))
+            .unwrap_err();
+        match error.root() {
+            CalculatorError::DependencyError(msg) => {
+                assert!(
+                    msg.contains("'babsent'") && msg.contains("new_explicit"),
+                    "{}",
+                    msg
+                )
+            }
+            other => panic!("expected DependencyError, got {:?}", other),
+        }
+
+        // Neither results nor errors are recorded
+        assert_eq!(engine.get_result("sum"), None);
+        assert!(engine.get_errors().is_empty());
+        assert_eq!(engine.snapshot().results().len(), 1);
+
+        assert_eq!(
+            engine.execute_one_cached(&Formula::new("double", "return x * 2")),
+            Ok(Value::Number(6.0))
+        );
+        assert_eq!(engine.get_result("double"), Some(Value::Number(6.0)));
+        assert_eq!(
+            engine.execute_one(&Formula::new(
+                "chained",
+                "return get_output_from('double') + 1"
+            )),
+            Ok(Value::Number(7.0))
+        );
+    }
+
+    #[test]
+    fn test_evaluate_expression() {
+        let engine = Engine::new();
+        engine.set_variable("x".to_string(), Value::Number(2.0));
+        engine
+            .execute(vec![Formula::new("base", "return 10")])
+            .unwrap();
+
+        assert_eq!(
+            engine.evaluate("get_output_from('base') * x"),
+            Ok(Value::Number(20.0))
+        );
+        assert_eq!(
+            engine.evaluate("iif(x > 1, 'big', 'small')"),
+            Ok(Value::from("big"))
+        );
+        assert!(matches!(
+            engine.evaluate("return x").unwrap_err().root(),
+            CalculatorError::ParseError(_)
+        ));
+
+        // No `return` needed here, while a formula body still requires a block
+        assert_eq!(engine.evaluate("2 + 2"), Ok(Value::Number(4.0)));
+        assert!(matches!(
+            Formula::validate("2 + 2"),
+            Err(CalculatorError::ParseError(_))
+        ));
+        let error = engine.evaluate("y + 1").unwrap_err();
+        assert_eq!(
+            error.root(),
+            &CalculatorError::VariableNotFound("y".to_string())
+        );
+        assert_eq!(
+            error.context().unwrap().expression.as_deref(),
+            Some("y + 1")
+        );
+        assert_eq!(error.to_string(), "Variable not found: y");
+        assert!(engine.get_errors().is_empty());
+        assert_eq!(engine.snapshot().results().len(), 1);
+    }
+
+    #[test]
+    fn test_execute_dyn_with_custom_formula_type() {
+        struct Row {
+            name: String,
+            body: String,
+            depends_on: Vec<String>,
+        }
+
+        impl FormulaT for Row {
+            fn name(&self) -> &str {
+                &self.name
+            }
+            fn body(&self) -> &str {
+                &self.body
+            }
+            fn depends_on(&self) -> &[String] {
+                &self.depends_on
+            }
+        }
+
+        let formulas: Vec<Box<dyn FormulaT + Send + Sync>> = vec![
+            Box::new(Row {
+                name: "total".to_string(),
+                body: "return get_output_from('base') * 2".to_string(),
+                depends_on: vec!["base".to_string()],
+            }),
+            Box::new(Formula::new("base", "return 21")),
+            Box::new(Row {
+                name: "broken".to_string(),
+                body: "return 1 +".to_string(),
+                depends_on: vec![],
+            }),
+        ];
+
+        let engine = Engine::new();
+        engine.execute_dyn(formulas).unwrap();
+
+        assert_eq!(engine.get_result("total"), Some(Value::Number(42.0)));
+        assert!(engine.get_errors().contains_key("broken"));
+    }
+
+    #[test]
+    fn test_execution_report() {
+        let engine = Engine::new();
+        engine
+            .execute(vec![Formula::new("earlier", "return 1 / 0")])
+            .unwrap();
+
+        let report = engine
+            .execute(vec![
+                Formula::new("a", "return 1"),
+                Formula::new("b", "return get_output_from('a') + 1"),
+                Formula::new("bad", "return 1 / 0"),
+                Formula::new("orphan", "return get_output_from('absent')"),
+            ])
+            .unwrap();
+
+        assert_eq!(report.succeeded(), ["a", "b"]);
+        assert_eq!(report.failed(), ["bad"]);
+        assert_eq!(report.detached(), ["orphan"]);
+        assert!(!report.is_success());
+        assert_eq!(report.get_result("b"), Some(Value::Number(2.0)));
+        // Only this call's formulas are reported
+        assert_eq!(report.errors().len(), 2);
+        assert!(report.errors()["bad"].contains("Division by zero"));
+        assert!(engine.get_errors().contains_key("earlier"));
+
+        assert!(matches!(
+            engine.execute(vec![
+                Formula::new("dup", "return 1"),
+                Formula::new("dup", "return 2"),
+            ]),
+            Err(CalculatorError::DependencyError(_))
+        ));
+    }
+
+    #[test]
+    fn test_execute_cancellable_stops_between_layers() {
+        struct Slow;
+
+        impl Function for Slow {
+            fn name(&self) -> &str {
+                "slow"
+            }
+
+            fn num_args(&self) -> usize {
+                1
+            }
+
+            fn execute(&self, params: &[Value]) -> Result<Value> {
+                std::thread::sleep(Duration::from_millis(50));
+                Ok(params[0].clone())
+            }
+        }
+
+        // A chain of 40 formulas, one per layer, takes about two seconds uncancelled.
+        // Each step passes a new argument, so no result comes from the cache.
+        let mut engine = Engine::new();
+        engine.register_function(Arc::new(Slow)).unwrap();
+        let mut formulas = Vec::new();
+        for i in 1..=40 {
+            let input = match i {
+                1 => "1".to_string(),
+                _ => format!("get_output_from('step{}') + 1", i - 1),
+            };
+            formulas.push(Formula::new(
+                format!("step{}", i),
+                format!("return slow({})", input),
+            ));
+        }
+
+        let token = CancellationToken::new();
+        let started = std::time::Instant::now();
+        let report = std::thread::scope(|scope| {
+            let execution = scope.spawn(|| engine.execute_cancellable(formulas, &token));
+            std::thread::sleep(Duration::from_millis(200));
+            token.cancel();
+            execution.join().unwrap().unwrap()
+        });
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(report.is_cancelled());
+        assert!(!report.is_success());
+        assert!(!report.succeeded().is_empty());
+        assert!(report.failed().is_empty());
+        assert_eq!(report.succeeded().len() + report.skipped().len(), 40);
+        assert_eq!(engine.get_result("step1"), Some(Value::Number(1.0)));
+        assert_eq!(engine.get_result("step40"), None);
+    }
+
+    /// Five inputs, five formulas depending on one input each, and a total.
+    fn layered_formulas() -> Vec<Formula> {
+        let mut formulas = Vec::new();
+        for i in 1..=5 {
+            formulas.push(Formula::new(format!("a{}", i), format!("return {}", i)));
+            formulas.push(Formula::new(
+                format!("b{}", i),
+                format!("return get_output_from('a{}') * 2", i),
+            ));
+        }
+        let total = (1..=5)
+            .map(|i| format!("get_output_from('b{}')", i))
+            .collect::<Vec<_>>()
+            .join(" + ");
+        formulas.push(Formula::new("total", format!("return {}", total)));
+        formulas
+    }
+
+    #[test]
+    fn test_execute_with_progress_orders_events() {
+        let engine = Engine::new();
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let report = engine
+            .execute_with_progress(layered_formulas(), move |event| {
+                recorded.lock().unwrap().push(event)
+            })
+            .unwrap();
+        assert!(report.is_success());
+        assert_eq!(engine.get_result("total"), Some(Value::Number(30.0)));
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 11 + 2 * 3);
+        let mut current = None;
+        let mut completed = HashMap::new();
+        for event in events.iter() {
+            match event {
+                ProgressEvent::LayerStarted { index, size } => {
+                    assert_eq!(current, None);
+                    assert_eq!(*size, [5, 5, 1][*index]);
+                    current = Some(*index);
+                }
+                ProgressEvent::FormulaCompleted { name, result, .. } => {
+                    let layer = current.expect("completion outside a layer");
+                    let expected = match &name[..1] {
+                        "a" => 0,
+                        "b" => 1,
+                        _ => 2,
+                    };
+                    assert_eq!(layer, expected, "{}", name);
+                    assert!(result.is_ok());
+                    completed.insert(name.clone(), layer);
+                }
+                ProgressEvent::LayerFinished { index } => {
+                    assert_eq!(current, Some(*index));
+                    assert_eq!(
+                        completed.values().filter(|l| *l == index).count(),
+                        [5, 5, 1][*index]
+                    );
+                    current = None;
+                }
+            }
+        }
+        assert_eq!(completed.len(), 11);
+    }
+
+    #[test]
+    fn test_panicking_progress_callback_fails_only_its_formula() {
+        let engine = Engine::new();
+        let report = engine
+            .execute_with_progress(layered_formulas(), |event| match event {
+                ProgressEvent::FormulaCompleted { name, .. } if name == "b2" => {
+                    panic!("progress bar broke")
+                }
+                ProgressEvent::LayerFinished { index: 0 } => panic!("layer event"),
+                _ => {}
+            })
+            .unwrap();
+
+        assert_eq!(report.failed(), ["b2", "total"]);
+        assert!(report.errors()["b2"].contains("progress callback panicked: progress bar broke"));
+        assert_eq!(report.succeeded().len(), 9);
+        assert_eq!(engine.get_result("b1"), Some(Value::Number(2.0)));
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_tracing_spans() {
+        use std::io::Write;
+        use std::sync::Mutex;
+        use tracing_subscriber::fmt::format::FmtSpan;
+
+        struct Capture(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for Capture {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let writer = output.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || Capture(writer.clone()))
+            .with_span_events(FmtSpan::CLOSE)
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .finish();
+
+        let engine = Engine::new();
+        tracing::subscriber::with_default(subscriber, || {
+            engine
+                .execute(vec![
+                    Formula::new("a", "return 1"),
+                    Formula::new("b", "return get_output_from('a') + 1"),
+                    Formula::new("c", "return get_output_from('b') / 0"),
+                ])
+                .unwrap();
+        });
+
+        let output = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        assert!(
+            output.contains("execute{formulas=3 layers=3}"),
+            "{}",
+            output
+        );
+        for (index, name, outcome) in [(0, "a", "ok"), (1, "b", "ok"), (2, "c", "error")] {
+            let span = format!("layer{{index={} size=1 duration_us=", index);
+            assert!(output.contains(&span), "{}\n{}", span, output);
+            let formula = format!("formula{{name={} outcome={}}}: ", name, outcome);
+            assert!(output.contains(&formula), "{}\n{}", formula, output);
+        }
+    }
+
+    #[test]
+    fn test_run_stats() {
+        let engine = Engine::new();
+        let report = engine.execute(layered_formulas()).unwrap();
+        assert_eq!(report.stats(), None);
+
+        let engine = Engine::builder().collect_run_stats(true).build().unwrap();
+        let report = engine.execute(layered_formulas()).unwrap();
+        let stats = report.stats().unwrap();
+        assert_eq!(
+            stats
+                .layers()
+                .iter()
+                .map(|layer| layer.size)
+                .collect::<Vec<_>>(),
+            [5, 5, 1]
+        );
+        assert_eq!(stats.formulas().len(), 11);
+        assert_eq!(stats.duration(), report.duration());
+        assert!(
+            stats
+                .layers()
+                .iter()
+                .map(|layer| layer.duration)
+                .sum::<Duration>()
+                <= stats.duration()
+        );
+
+        let slowest = stats.slowest(3);
+        assert_eq!(slowest.len(), 3);
+        assert!(slowest[0].1.total() >= slowest[1].1.total());
+        assert!(slowest[1].1.total() >= slowest[2].1.total());
+        assert_eq!(stats.slowest(100).len(), 11);
+
+        // Precompiled formulas skip parsing
+        let compiled = engine.precompile(&layered_formulas()).unwrap();
+        let report = engine.execute_compiled(compiled).unwrap();
+        let stats = report.stats().unwrap();
+        assert!(stats
+            .formulas()
+            .values()
+            .all(|timing| timing.parse == Duration::ZERO));
+    }
+
+    #[test]
+    fn test_concurrent_execution_through_shared_reference() {
+        const THREADS: usize = 8;
+
+        let engine = Arc::new(Engine::new());
+        engine.set_variable("base".to_string(), Value::Number(100.0));
+
+        let handles: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let engine = Arc::clone(&engine);
+                std::thread::spawn(move || {
+                    for round in 0..20 {
+                        let input = format!("input_{}", t);
+                        engine.set_variable(input.clone(), Value::Number((t * round) as f64));
+                        engine
+                            .execute(vec![
+                                Formula::new(
+                                    format!("sum_{}", t),
+                                    format!("return base + {}", input),
+                                ),
+                                Formula::new(
+                                    format!("double_{}", t),
+                                    format!("return get_output_from('sum_{}') * 2", t),
+                                ),
+                                Formula::new(format!("bad_{}", t), "return 1 / 0"),
+                            ])
+                            .unwrap();
+                        assert_eq!(
+                            engine.get_result(&format!("double_{}", t)),
+                            Some(Value::Number((100 + t * round) as f64 * 2.0))
+                        );
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let errors = engine.get_errors();
+        assert_eq!(errors.len(), THREADS);
+        for t in 0..THREADS {
+            assert_eq!(
+                engine.get_result(&format!("sum_{}", t)),
+                Some(Value::Number((100 + t * 19) as f64))
+            );
+            assert!(errors.contains_key(&format!("bad_{}", t)));
+        }
+    }
+
+    #[test]
+    fn test_execute_with_variables_layers_overrides() {
+        let engine = Engine::new();
+        engine.set_variable("x".to_string(), Value::Number(1.0));
+        engine.set_variable("y".to_string(), Value::Number(2.0));
+        let formulas = || {
+            vec![
+                Formula::new("sum", "return x + y"),
+                Formula::new("double", "return get_output_from('sum') * 2"),
+                Formula::new("broken", "return missing"),
+            ]
+        };
+
+        let report = engine
+            .execute_with_variables(
+                formulas(),
+                HashMap::from([("x".to_string(), Value::Number(10.0))]),
+            )
+            .unwrap();
+        // Overridden x shadows the base value, y comes from the base variables
+        assert_eq!(report.get_result("sum"), Some(Value::Number(12.0)));
+        assert_eq!(report.get_result("double"), Some(Value::Number(24.0)));
+        assert_eq!(report.get_result("broken"), None);
+        assert!(report.errors()["broken"].contains("missing"));
+
+        // Nothing from the call persists in the engine
+        assert_eq!(engine.snapshot().variables()["x"], Value::Number(1.0));
+        assert_eq!(engine.get_result("sum"), None);
+        assert!(engine.get_errors().is_empty());
+
+        let report = engine
+            .execute_with_variables(formulas(), HashMap::new())
+            .unwrap();
+        assert_eq!(report.get_result("sum"), Some(Value::Number(3.0)));
+    }
+
+    #[test]
+    fn test_execute_with_variables_keeps_function_results_per_call() {
+        struct Twice;
+        impl Function for Twice {
+            fn name(&self) -> &str {
+                "twice"
+            }
+            fn num_args(&self) -> usize {
+                1
+            }
+            fn execute(&self, params: &[Value]) -> Result<Value> {
+                Ok(Value::Number(
+                    params[0].as_number().unwrap_or_default() * 2.0,
+                ))
+            }
+        }
+
+        let mut engine = Engine::new();
+        engine.register_function(Arc::new(Twice)).unwrap();
+        engine.set_variable("amount".to_string(), Value::Number(1.0));
+        let formulas = || vec![Formula::new("fee", "return twice(amount)")];
+
+        for amount in [10.0, 20.0] {
+            let overrides = HashMap::from([("amount".to_string(), Value::Number(amount))]);
+            let report = engine
+                .execute_with_variables(formulas(), overrides)
+                .unwrap();
+            assert_eq!(report.get_result("fee"), Some(Value::Number(amount * 2.0)));
+        }
+
+        // Scoped calls leave the engine's function result cache untouched
+        assert_eq!(engine.cache_stats().function_results, CacheStats::default());
+        engine.execute(formulas()).unwrap();
+        assert_eq!(engine.get_result("fee"), Some(Value::Number(2.0)));
+    }
+
+    #[test]
+    fn test_on_result_fires_once_per_formula() {
+        use std::sync::Mutex;
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        let mut engine = Engine::new();
+        engine.set_on_result(Box::new(move |name, result| {
+            log.lock().unwrap().push((name.to_string(), result.clone()));
+        }));
+
+        engine
+            .execute_best_effort(vec![
+                Formula::new("a", "return 1"),
+                Formula::new("b", "return get_output_from('a') + 1"),
+                Formula::new("c", "return 1 / 0"),
+                Formula::new("d", "return get_output_from('c') * 2"),
+            ])
+            .unwrap();
+
+        let mut seen = seen.lock().unwrap().clone();
+        seen.sort_by(|x, y| x.0.cmp(&y.0));
+        let names: Vec<&str> = seen.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "c", "d"]);
+        assert_eq!(seen[0].1, Ok(Value::Number(1.0)));
+        assert_eq!(seen[1].1, Ok(Value::Number(2.0)));
+        let error = seen[2].1.as_ref().unwrap_err();
+        assert_eq!(error.root(), &CalculatorError::DivisionByZero);
+        assert_eq!(error.context().unwrap().formula_name.as_deref(), Some("c"));
+        assert!(matches!(
+            seen[3].1.as_ref().unwrap_err().root(),
+            CalculatorError::DependencyError(_)
+        ));
+    }
+
+    #[test]
+    fn test_unregister_function() {
+        struct Rate(f64);
+        impl Function for Rate {
+            fn name(&self) -> &str {
+                "tenant_rate"
+            }
+            fn num_args(&self) -> usize {
+                0
+            }
+            fn execute(&self, _params: &[Value]) -> Result<Value> {
+                Ok(Value::Number(self.0))
+            }
+        }
+        let run = |engine: &mut Engine, name: &str| {
+            engine
+                .execute(vec![Formula::new(name, "return tenant_rate()")])
+                .unwrap();
+            engine.get_result(name)
+        };
+
+        let mut engine = Engine::new();
+        engine.register_function(Arc::new(Rate(1.0))).unwrap();
+        assert_eq!(run(&mut engine, "before"), Some(Value::Number(1.0)));
+
+        assert!(engine.unregister_function("tenant_rate", 0));
+        assert!(!engine.unregister_function("tenant_rate", 0));
+        assert_eq!(run(&mut engine, "removed"), None);
+        assert!(engine.get_errors()["removed"].contains("Function not found: tenant_rate_0"));
+
+        // The cached result of the removed function does not resurface
+        engine.register_function(Arc::new(Rate(2.0))).unwrap();
+        assert_eq!(run(&mut engine, "replaced"), Some(Value::Number(2.0)));
+
+        engine
+            .register_async_function(Arc::new(AsyncDouble))
+            .unwrap();
+        engine.clear_functions();
+        assert_eq!(run(&mut engine, "cleared"), None);
+        assert!(!engine.unregister_function("async_double", 1));
+    }
+
+    #[test]
+    fn test_function_results_are_cached_per_arguments() {
+        struct Area;
+        impl Function for Area {
+            fn name(&self) -> &str {
+                "area"
+            }
+            fn num_args(&self) -> usize {
+                1
+            }
+            fn execute(&self, params: &[Value]) -> Result<Value> {
+                let n = params[0].as_number().unwrap_or_default();
+                Ok(Value::Number(n * n))
+            }
+        }
 
-        let formula = Formula::new("test", "return x * 2");
-        engine.execute(vec![formula]).unwrap();
+        let mut engine = Engine::new();
+        engine.set_function_cache_capacity(2);
+        engine.register_function(Arc::new(Area)).unwrap();
+        engine
+            .execute(vec![
+                Formula::new("one", "return area(1)"),
+                Formula::new("two", "return area(2)"),
+                Formula::new("three", "return area(3)"),
+                Formula::new("again", "return area(1) + area(2)"),
+            ])
+            .unwrap();
 
-        let result = engine.get_result("test").unwrap();
-        assert_eq!(result, Value::Number(20.0));
+        assert_eq!(engine.get_result("one"), Some(Value::Number(1.0)));
+        assert_eq!(engine.get_result("two"), Some(Value::Number(4.0)));
+        assert_eq!(engine.get_result("three"), Some(Value::Number(9.0)));
+        assert_eq!(engine.get_result("again"), Some(Value::Number(5.0)));
     }
 
     #[test]
-    fn test_formula_dependencies() {
+    fn test_register_function_rejects_duplicates() {
+        struct Constant(f64);
+        impl Function for Constant {
+            fn name(&self) -> &str {
+                "pool_size"
+            }
+            fn num_args(&self) -> usize {
+                0
+            }
+            fn execute(&self, _params: &[Value]) -> Result<Value> {
+                Ok(Value::Number(self.0))
+            }
+        }
+
         let mut engine = Engine::new();
+        engine.register_function(Arc::new(Constant(1.0))).unwrap();
+        let result = engine.register_function(Arc::new(PoolSize));
+        assert_eq!(
+            result,
+            Err(CalculatorError::FunctionAlreadyRegistered(
+                "pool_size_0".to_string()
+            ))
+        );
 
-        let formula1 = Formula::new("first", "return 10");
-        let formula2 = Formula::new("second", "return get_output_from('first') * 2");
+        // The original registration is kept
+        engine
+            .execute(vec![Formula::new("value", "return pool_size()")])
+            .unwrap();
+        assert_eq!(engine.get_result("value"), Some(Value::Number(1.0)));
 
-        engine.execute(vec![formula1, formula2]).unwrap();
+        // Overwriting replaces it and discards its cached result
+        engine
+            .register_function_overwrite(Arc::new(Constant(2.0)))
+            .unwrap();
+        engine
+            .execute(vec![Formula::new("value", "return pool_size()")])
+            .unwrap();
+        assert_eq!(engine.get_result("value"), Some(Value::Number(2.0)));
 
-        // Check for errors
-        if !engine.get_errors().is_empty() {
-            for (name, error) in engine.get_errors() {
-                eprintln!("Error in {}: {}", name, error);
+        let mut engine = Engine::new();
+        engine
+            .register_async_function(Arc::new(AsyncDouble))
+            .unwrap();
+        struct SyncDouble;
+        impl Function for SyncDouble {
+            fn name(&self) -> &str {
+                "async_double"
+            }
+            fn num_args(&self) -> usize {
+                1
+            }
+            fn execute(&self, params: &[Value]) -> Result<Value> {
+                Ok(params[0].clone())
             }
         }
+        assert_eq!(
+            engine.register_function(Arc::new(SyncDouble)),
+            Err(CalculatorError::FunctionAlreadyRegistered(
+                "async_double_1".to_string()
+            ))
+        );
+    }
 
-        let result = engine
-            .get_result("second")
-            .expect("second formula should have result");
-        assert_eq!(result, Value::Number(20.0));
+    #[test]
+    fn test_register_function_rejects_reserved_names() {
+        struct Named(&'static str);
+        impl Function for Named {
+            fn name(&self) -> &str {
+                self.0
+            }
+            fn num_args(&self) -> usize {
+                2
+            }
+            fn execute(&self, _params: &[Value]) -> Result<Value> {
+                Ok(Value::Null)
+            }
+        }
+
+        let mut engine = Engine::new();
+        for name in ["Max", "if", "GetOutputFrom", "true", "stddev_p"] {
+            assert!(
+                matches!(
+                    engine.register_function(Arc::new(Named(name))),
+                    Err(CalculatorError::InvalidArgument(_))
+                ),
+                "{} was accepted",
+                name
+            );
+            assert!(matches!(
+                engine.register_function_overwrite(Arc::new(Named(name))),
+                Err(CalculatorError::InvalidArgument(_))
+            ));
+        }
+
+        // Names merely containing a keyword are fine
+        engine.register_function(Arc::new(Named("max_of"))).unwrap();
+        engine.register_function(Arc::new(Named("ifs"))).unwrap();
     }
 
     #[test]
-    fn test_if_statement() {
+    fn test_unresolved_dependencies() {
+        let mut engine = Engine::new();
+        engine
+            .execute(vec![
+                Formula::new("ok", "return 1"),
+                Formula::new(
+                    "missing",
+                    "return get_output_from('ok') + get_output_from('absent')",
+                ),
+                Formula::new("downstream", "return get_output_from('missing') + 1"),
+            ])
+            .unwrap();
+
+        let unresolved = engine.get_unresolved_dependencies();
+        assert_eq!(unresolved.len(), 2);
+        assert_eq!(unresolved["missing"], vec!["absent".to_string()]);
+        assert_eq!(unresolved["downstream"], vec!["missing".to_string()]);
+        assert!(engine.get_errors().contains_key("missing"));
+        assert!(engine.get_errors().contains_key("downstream"));
+        assert_eq!(engine.get_result("ok"), Some(Value::Number(1.0)));
+
+        engine.clear();
+        assert!(engine.get_unresolved_dependencies().is_empty());
+    }
+
+    #[test]
+    fn test_unknown_identifier_modes() {
+        let formulas = || {
+            vec![
+                Formula::new("label", "return draft"),
+                Formula::new("defined", "return x + 1"),
+                Formula::new(
+                    "compare",
+                    "if (state = approved) then return 1 else return 0 end",
+                ),
+            ]
+        };
+
+        let engine = Engine::new();
+        engine.set_variable("x".to_string(), Value::Number(1.0));
+        engine.set_variable("state".to_string(), Value::String("approved".to_string()));
+        engine.execute(formulas()).unwrap();
+        assert!(engine.get_errors()["label"].contains("Variable not found: draft"));
+        assert!(engine.get_errors()["compare"].contains("Variable not found: approved"));
+        assert_eq!(engine.get_result("defined"), Some(Value::Number(2.0)));
+
         let mut engine = Engine::new();
+        engine.set_unknown_identifier_as_string(true);
+        engine.set_variable("x".to_string(), Value::Number(1.0));
+        engine.set_variable("state".to_string(), Value::String("approved".to_string()));
+        engine.execute(formulas()).unwrap();
+        assert!(engine.get_errors().is_empty());
+        assert_eq!(
+            engine.get_result("label"),
+            Some(Value::String("draft".to_string()))
+        );
+        assert_eq!(engine.get_result("defined"), Some(Value::Number(2.0)));
+        assert_eq!(engine.get_result("compare"), Some(Value::Number(1.0)));
+        assert!(engine.validate_all(&formulas()).is_empty());
+    }
+
+    #[test]
+    fn test_if_statement() {
+        let engine = Engine::new();
         let formula = Formula::new("test", "if (5 > 3) then return 100 else return 200 end");
 
         engine.execute(vec![formula]).unwrap();
@@ -362,7 +3659,7 @@ mod tests {
 
     #[test]
     fn test_parallel_execution() {
-        let mut engine = Engine::new();
+        let engine = Engine::new();
 
         // Create multiple independent formulas that can be executed in parallel
         let formulas = vec![
@@ -384,7 +3681,7 @@ mod tests {
 
     #[test]
     fn test_parallel_with_dependencies() {
-        let mut engine = Engine::new();
+        let engine = Engine::new();
 
         // Layer 0: a, b (can execute in parallel)
         // Layer 1: c, d (can execute in parallel, both depend on layer 0)
@@ -405,4 +3702,661 @@ mod tests {
         assert_eq!(engine.get_result("d").unwrap(), Value::Number(40.0));
         assert_eq!(engine.get_result("e").unwrap(), Value::Number(60.0));
     }
+
+    #[test]
+    fn test_get_diff_months_is_signed() {
+        let engine = Engine::new();
+        let formulas = vec![
+            Formula::new(
+                "forward",
+                "return get_diff_months('2024-03-01', '2023-12-15')",
+            ),
+            Formula::new(
+                "backward",
+                "return get_diff_months('2023-12-15', '2024-03-01')",
+            ),
+        ];
+
+        engine.execute(formulas).unwrap();
+
+        assert_eq!(engine.get_result("forward").unwrap(), Value::Number(3.0));
+        assert_eq!(engine.get_result("backward").unwrap(), Value::Number(-3.0));
+    }
+
+    #[test]
+    fn test_difference_in_months_alias() {
+        let engine = Engine::new();
+        let formula = Formula::new(
+            "months",
+            "return difference_in_months('2024-03-01', '2023-12-15')",
+        );
+
+        engine.execute(vec![formula]).unwrap();
+
+        assert!(engine.get_errors().is_empty());
+        assert_eq!(engine.get_result("months").unwrap(), Value::Number(3.0));
+    }
+
+    #[test]
+    fn test_reject_nonfinite_result() {
+        let mut engine = Engine::new();
+        let formula = Formula::new("overflow", "return 10 ^ 200 * 10 ^ 200");
+
+        engine.execute(vec![formula.clone()]).unwrap();
+        assert_eq!(
+            engine.get_result("overflow").unwrap(),
+            Value::Number(f64::INFINITY)
+        );
+
+        engine.clear();
+        engine.set_reject_nonfinite(true);
+        engine.execute(vec![formula]).unwrap();
+
+        assert_eq!(engine.get_result("overflow"), None);
+        assert!(engine.get_errors()["overflow"].contains("non-finite result"));
+    }
+
+    #[test]
+    fn test_business_days_between_uses_engine_holidays() {
+        let mut engine = Engine::new();
+        engine.set_holidays(vec![
+            NaiveDate::from_ymd_opt(2024, 1, 8).unwrap(),
+            NaiveDate::from_ymd_opt(2024, 1, 9).unwrap(),
+        ]);
+
+        let formula = Formula::new(
+            "days",
+            "return business_days_between('2024-01-05', '2024-01-15', '2024-01-10')",
+        );
+        engine.execute(vec![formula]).unwrap();
+
+        assert_eq!(engine.get_result("days").unwrap(), Value::Number(3.0));
+    }
+
+    #[test]
+    fn test_execute_compiled_with_changing_variables() {
+        let engine = Engine::new();
+        let compiled = engine
+            .precompile(&[
+                Formula::new("subtotal", "return price * quantity"),
+                Formula::new("total", "return get_output_from('subtotal') * (1 + 0.5)"),
+            ])
+            .unwrap();
+
+        engine.set_variable("quantity".to_string(), Value::Number(2.0));
+        for (price, expected) in [(10.0, 30.0), (20.0, 60.0)] {
+            engine.set_variable("price".to_string(), Value::Number(price));
+            engine.execute_compiled(compiled.clone()).unwrap();
+            assert_eq!(engine.get_result("total").unwrap(), Value::Number(expected));
+        }
+    }
+
+    #[test]
+    fn test_precompile_reports_every_parse_error() {
+        let engine = Engine::new();
+        let errors = engine
+            .precompile(&[
+                Formula::new("good", "return 1"),
+                Formula::new("bad", "return 1 +"),
+                Formula::new("worse", "return (2"),
+            ])
+            .unwrap_err();
+
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(errors["bad"], CalculatorError::ParseError(_)));
+        assert!(matches!(errors["worse"], CalculatorError::ParseError(_)));
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let mut engine = Engine::new();
+        engine.set_variable("rate".to_string(), Value::Number(0.1));
+        engine
+            .execute(vec![Formula::new("interest", "return 1000 * rate")])
+            .unwrap();
+        let baseline = engine.snapshot();
+
+        engine.set_variable("rate".to_string(), Value::Number(0.2));
+        engine.set_variable("extra".to_string(), Value::Number(1.0));
+        engine
+            .execute(vec![Formula::new("interest", "return 1000 * rate")])
+            .unwrap();
+        let scenario = engine.snapshot();
+        assert_eq!(engine.get_result("interest").unwrap(), Value::Number(200.0));
+
+        engine.restore(baseline.clone());
+        assert_eq!(engine.get_result("interest").unwrap(), Value::Number(100.0));
+        assert_eq!(engine.snapshot(), baseline);
+        assert!(!engine.snapshot().variables().contains_key("extra"));
+
+        engine.restore(scenario);
+        assert_eq!(engine.get_result("interest").unwrap(), Value::Number(200.0));
+        assert_eq!(
+            engine.snapshot().variables().get("extra"),
+            Some(&Value::Number(1.0))
+        );
+    }
+
+    #[test]
+    fn test_export_and_import_state() {
+        let engine = Engine::new();
+        engine.set_variable("x".to_string(), Value::Number(3.0));
+        engine
+            .execute(vec![Formula::new("tripled", "return x * 3")])
+            .unwrap();
+
+        let mut fresh = Engine::new();
+        fresh.import_state(engine.export_state());
+        assert_eq!(fresh.get_result("tripled"), Some(Value::Number(9.0)));
+        assert!(fresh.has_variable("x"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_snapshot_survives_serialization() {
+        let engine = Engine::new();
+        engine.set_variable("x".to_string(), Value::Number(4.0));
+        engine
+            .execute(vec![
+                Formula::new("squared", "return x * x"),
+                Formula::new("label", "return 'x=' & x"),
+                Formula::new("day", "return date('2024-03-01')"),
+                Formula::new("overflow", "return exp(1000)"),
+                Formula::new("underflow", "return -exp(1000)"),
+            ])
+            .unwrap();
+        engine.set_variable("missing".to_string(), Value::Number(f64::NAN));
+
+        let json = serde_json::to_string(&engine.snapshot()).unwrap();
+
+        let mut restored = Engine::new();
+        restored.restore(serde_json::from_str(&json).unwrap());
+        assert_eq!(restored.get_result("squared"), Some(Value::Number(16.0)));
+        assert_eq!(
+            restored.get_result("overflow"),
+            Some(Value::Number(f64::INFINITY))
+        );
+        assert_eq!(
+            restored.get_result("underflow"),
+            Some(Value::Number(f64::NEG_INFINITY))
+        );
+        assert!(restored.snapshot().variables()["missing"]
+            .as_number()
+            .unwrap()
+            .is_nan());
+        assert_eq!(restored.get_result("label"), engine.get_result("label"));
+        assert_eq!(restored.get_result("day"), engine.get_result("day"));
+        assert!(restored.has_variable("x"));
+    }
+
+    #[test]
+    fn test_get_result_or() {
+        let engine = Engine::new();
+        engine
+            .execute(vec![
+                Formula::new("present", "return 42"),
+                Formula::new("failed", "return missing * 2"),
+            ])
+            .unwrap();
+
+        let default = Value::String("n/a".to_string());
+        assert_eq!(
+            engine.get_result_or("present", default.clone()),
+            Value::Number(42.0)
+        );
+        assert_eq!(engine.get_result_or("failed", default.clone()), default);
+        assert_eq!(engine.get_result_or("absent", default.clone()), default);
+    }
+
+    #[test]
+    fn test_recompute_runs_only_dirty_formulas() {
+        let mut engine = Engine::new();
+        engine.set_variable("x".to_string(), Value::Number(1.0));
+        engine.add_formula(Formula::new("a", "return x")).unwrap();
+        engine
+            .add_formula(Formula::new("b", "return get_output_from('a') * 10"))
+            .unwrap();
+        engine
+            .add_formula(Formula::new("c", "return x + 100"))
+            .unwrap();
+        engine.recompute().unwrap();
+
+        assert_eq!(engine.get_result("b"), Some(Value::Number(10.0)));
+        assert_eq!(engine.get_result("c"), Some(Value::Number(101.0)));
+
+        // Only the replaced formula and its dependents see the new variable value
+        engine.set_variable("x".to_string(), Value::Number(2.0));
+        engine
+            .add_formula(Formula::new("a", "return x * 2"))
+            .unwrap();
+        engine.recompute().unwrap();
+
+        assert_eq!(engine.get_result("a"), Some(Value::Number(4.0)));
+        assert_eq!(engine.get_result("b"), Some(Value::Number(40.0)));
+        assert_eq!(engine.get_result("c"), Some(Value::Number(101.0)));
+    }
+
+    #[test]
+    fn test_remove_formula_marks_dependents_dirty() {
+        let mut engine = Engine::new();
+        engine.add_formula(Formula::new("a", "return 1")).unwrap();
+        engine
+            .add_formula(Formula::new("b", "return get_output_from('a') + 1"))
+            .unwrap();
+        engine
+            .add_formula(Formula::new("c", "return get_output_from('b') + 1"))
+            .unwrap();
+        engine.recompute().unwrap();
+        assert_eq!(engine.get_result("c"), Some(Value::Number(3.0)));
+
+        assert!(engine.remove_formula("a"));
+        assert!(!engine.remove_formula("a"));
+        assert_eq!(engine.get_result("a"), None);
+
+        engine.recompute().unwrap();
+        assert_eq!(engine.get_result("b"), None);
+        assert_eq!(engine.get_result("c"), None);
+        assert!(engine.get_errors().contains_key("b"));
+
+        // Restoring the dependency resolves the chain again
+        engine.add_formula(Formula::new("a", "return 5")).unwrap();
+        engine.recompute().unwrap();
+        assert_eq!(engine.get_result("c"), Some(Value::Number(7.0)));
+        assert!(engine.get_errors().is_empty());
+    }
+
+    #[test]
+    fn test_add_formula_reports_parse_error() {
+        let mut engine = Engine::new();
+        assert!(engine.add_formula(Formula::new("bad", "return (")).is_err());
+        engine.recompute().unwrap();
+        assert_eq!(engine.get_result("bad"), None);
+    }
+
+    #[test]
+    fn test_add_formula_rejects_cycle_and_keeps_previous() {
+        let mut engine = Engine::new();
+        engine.add_formula(Formula::new("a", "return 1")).unwrap();
+        engine
+            .add_formula(Formula::new("b", "return get_output_from('a') + 1"))
+            .unwrap();
+
+        let result = engine.add_formula(Formula::new("a", "return get_output_from('b') + 1"));
+        assert!(matches!(result, Err(CalculatorError::DependencyError(_))));
+        assert!(matches!(
+            engine.add_formula(Formula::new("c", "return get_output_from('c')")),
+            Err(CalculatorError::DependencyError(_))
+        ));
+
+        engine.recompute().unwrap();
+        assert_eq!(engine.get_result("a"), Some(Value::Number(1.0)));
+        assert_eq!(engine.get_result("b"), Some(Value::Number(2.0)));
+        assert!(engine.get_errors().is_empty());
+    }
+
+    #[test]
+    fn test_cache_stats_count_hits() {
+        let mut engine = Engine::new();
+        engine
+            .execute(vec![
+                Formula::new("base", "return 21"),
+                Formula::new(
+                    "total",
+                    "return get_output_from('base') + get_output_from('base')",
+                ),
+            ])
+            .unwrap();
+
+        assert_eq!(engine.get_result("total"), Some(Value::Number(42.0)));
+        let stats = engine.cache_stats();
+        assert!(stats.formula_results.hits > 0);
+        assert_eq!(stats.formula_results.misses, 0);
+
+        engine.clear();
+        assert_eq!(engine.cache_stats(), EngineCacheStats::default());
+    }
+
+    #[test]
+    fn test_unused_variables() {
+        let engine = Engine::new();
+        engine.set_variable("used".to_string(), Value::Number(1.0));
+        engine.set_variable("unused".to_string(), Value::Number(2.0));
+        engine.set_variable("in_branch".to_string(), Value::Number(3.0));
+
+        let formulas = [
+            Formula::new("a", "return used * 2"),
+            Formula::new(
+                "b",
+                "if (false) then return in_branch else return get_output_from('a') end",
+            ),
+            Formula::new("broken", "return unused +"),
+        ];
+        assert_eq!(engine.unused_variables(&formulas), ["unused"]);
+        assert_eq!(
+            engine.unused_variables(&[]),
+            ["in_branch", "unused", "used"]
+        );
+    }
+
+    #[test]
+    fn test_validate_all_reports_every_problem() {
+        let engine = Engine::new();
+        engine.set_variable("x".to_string(), Value::Number(1.0));
+
+        let formulas = vec![
+            Formula::new("ok", "return x + 1"),
+            Formula::new("broken", "return (x +"),
+            Formula::new(
+                "refs",
+                "if (x > 0) then return get_output_from('ok') + y else return missing(x, get_output_from('nope')) end",
+            ),
+        ];
+        let errors = engine.validate_all(&formulas);
+
+        assert_eq!(errors.len(), 2);
+        assert!(!errors.contains_key("ok"));
+        assert!(matches!(
+            errors["broken"].as_slice(),
+            [CalculatorError::ParseError(_)]
+        ));
+        assert_eq!(
+            errors["refs"],
+            vec![
+                CalculatorError::VariableNotFound("y".to_string()),
+                CalculatorError::FormulaNotFound("nope".to_string()),
+                CalculatorError::FunctionNotFound(build_function_id("missing", 2)),
+            ]
+        );
+
+        // Nothing was executed
+        assert_eq!(engine.get_result("ok"), None);
+        assert!(engine.get_errors().is_empty());
+    }
+
+    #[test]
+    fn test_execute_best_effort_skips_failed_dependents() {
+        let engine = Engine::new();
+        // A stale result from an earlier run must not be used by dependents
+        engine
+            .execute(vec![Formula::new("rate", "return 2")])
+            .unwrap();
+
+        engine
+            .execute_best_effort(vec![
+                Formula::new("rate", "return missing_var"),
+                Formula::new("base", "return 10"),
+                Formula::new(
+                    "scaled",
+                    "return get_output_from('base') * get_output_from('rate')",
+                ),
+                Formula::new("total", "return get_output_from('scaled') + 1"),
+                Formula::new("doubled", "return get_output_from('base') * 2"),
+            ])
+            .unwrap();
+
+        assert_eq!(engine.get_result("doubled"), Some(Value::Number(20.0)));
+        assert_eq!(engine.get_result("scaled"), None);
+        assert_eq!(engine.get_result("total"), None);
+
+        let errors = engine.get_errors();
+        assert!(errors["rate"].contains("Variable not found"));
+        assert!(errors["scaled"].contains("Dependency error: upstream formula 'rate' failed"));
+        assert!(errors["total"].contains("upstream formula 'scaled' failed"));
+        assert!(!errors.contains_key("doubled"));
+    }
+
+    #[test]
+    fn test_skip_on_dependency_error_option() {
+        let formulas = || {
+            vec![
+                Formula::new("bad", "return 1 / 0"),
+                Formula::new("next", "return get_output_from('bad')"),
+            ]
+        };
+
+        let engine = Engine::new();
+        engine.execute(formulas()).unwrap();
+        assert!(engine.get_errors()["next"].contains("Formula not found"));
+
+        let mut engine = Engine::new();
+        engine.set_skip_on_dependency_error(true);
+        engine.execute(formulas()).unwrap();
+        assert!(engine.get_errors()["next"].contains("upstream formula 'bad' failed"));
+    }
+
+    #[test]
+    fn test_rename_formula() {
+        let mut engine = Engine::new();
+        engine.add_formula(Formula::new("a", "return 2")).unwrap();
+        engine
+            .add_formula(Formula::new("b", "return get_output_from('a') * 3"))
+            .unwrap();
+        engine.add_formula(Formula::new("c", "return 1")).unwrap();
+        engine.recompute().unwrap();
+
+        engine.rename_formula("a", "base").unwrap();
+
+        assert_eq!(engine.get_result("a"), None);
+        assert_eq!(engine.get_result("base"), Some(Value::Number(2.0)));
+        assert!(engine.get_errors()["b"].contains("renamed formula 'a'"));
+        assert!(!engine.get_errors().contains_key("c"));
+
+        // Updating the dependent's body resolves it against the new name
+        engine
+            .add_formula(Formula::new("b", "return get_output_from('base') * 3"))
+            .unwrap();
+        engine.recompute().unwrap();
+        assert_eq!(engine.get_result("b"), Some(Value::Number(6.0)));
+        assert!(engine.get_errors().is_empty());
+
+        assert!(matches!(
+            engine.rename_formula("missing", "x"),
+            Err(CalculatorError::FormulaNotFound(_))
+        ));
+        assert!(matches!(
+            engine.rename_formula("base", "c"),
+            Err(CalculatorError::DependencyError(_))
+        ));
+    }
+
+    #[test]
+    fn test_engine_moves_across_threads() {
+        let engine = std::sync::Arc::new(std::sync::Mutex::new(Engine::new()));
+
+        let worker = {
+            let engine = engine.clone();
+            std::thread::spawn(move || {
+                let engine = engine.lock().unwrap();
+                engine.set_variable("x".to_string(), Value::Number(20.0));
+                engine
+                    .execute(vec![Formula::new("answer", "return x + 22")])
+                    .unwrap();
+            })
+        };
+        worker.join().unwrap();
+
+        assert_eq!(
+            engine.lock().unwrap().get_result("answer"),
+            Some(Value::Number(42.0))
+        );
+
+        let owned = Engine::new();
+        let handle = std::thread::spawn(move || owned.get_result("missing"));
+        assert_eq!(handle.join().unwrap(), None);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_execute_async() {
+        let engine = Engine::new();
+        engine.set_variable("x".to_string(), Value::Number(4.0));
+
+        engine
+            .execute_async(vec![
+                Formula::new("a", "return x * 2"),
+                Formula::new("b", "return get_output_from('a') + 1"),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(engine.get_result("b"), Some(Value::Number(9.0)));
+        assert!(engine.get_errors().is_empty());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_execute_async_propagates_errors() {
+        let engine = Engine::new();
+
+        engine
+            .execute_async(vec![Formula::new("bad", "return 1 / 0")])
+            .await
+            .unwrap();
+        assert!(engine.get_errors().contains_key("bad"));
+
+        let duplicate = engine
+            .execute_async(vec![
+                Formula::new("twice", "return 1"),
+                Formula::new("twice", "return 2"),
+            ])
+            .await;
+        assert!(matches!(
+            duplicate,
+            Err(CalculatorError::DependencyError(_))
+        ));
+    }
+
+    struct AsyncDouble;
+
+    impl AsyncFunction for AsyncDouble {
+        fn name(&self) -> &str {
+            "async_double"
+        }
+
+        fn num_args(&self) -> usize {
+            1
+        }
+
+        fn execute<'a>(
+            &'a self,
+            params: &'a [Value],
+        ) -> crate::function::BoxFuture<'a, Result<Value>> {
+            Box::pin(async move {
+                match params[0] {
+                    Value::Number(n) => Ok(Value::Number(n * 2.0)),
+                    _ => Err(CalculatorError::TypeError("Expected number".to_string())),
+                }
+            })
+        }
+    }
+
+    #[test]
+    fn test_async_function_requires_async_execution() {
+        let mut engine = Engine::new();
+        engine
+            .register_async_function(Arc::new(AsyncDouble))
+            .unwrap();
+
+        engine
+            .execute(vec![Formula::new("doubled", "return async_double(21)")])
+            .unwrap();
+
+        assert_eq!(engine.get_result("doubled"), None);
+        assert!(engine.get_errors()["doubled"]
+            .contains("Evaluation error: Async function requires async execution"));
+        assert!(engine
+            .validate_all(&[Formula::new("doubled", "return async_double(21)")])
+            .is_empty());
+    }
+
+    #[test]
+    fn test_register_async_function_rejects_duplicates_and_reserved_names() {
+        struct AsyncMax;
+        impl AsyncFunction for AsyncMax {
+            fn name(&self) -> &str {
+                "max"
+            }
+            fn num_args(&self) -> usize {
+                2
+            }
+            fn execute<'a>(
+                &'a self,
+                _params: &'a [Value],
+            ) -> crate::function::BoxFuture<'a, Result<Value>> {
+                Box::pin(async { Ok(Value::Null) })
+            }
+        }
+        struct SyncDouble;
+        impl Function for SyncDouble {
+            fn name(&self) -> &str {
+                "async_double"
+            }
+            fn num_args(&self) -> usize {
+                1
+            }
+            fn execute(&self, _params: &[Value]) -> Result<Value> {
+                Ok(Value::Null)
+            }
+        }
+
+        let mut engine = Engine::new();
+        assert!(matches!(
+            engine.register_async_function(Arc::new(AsyncMax)),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+
+        engine
+            .register_async_function(Arc::new(AsyncDouble))
+            .unwrap();
+        let duplicate = Err(CalculatorError::FunctionAlreadyRegistered(
+            "async_double_1".to_string(),
+        ));
+        assert_eq!(
+            engine.register_async_function(Arc::new(AsyncDouble)),
+            duplicate
+        );
+        assert_eq!(engine.register_function(Arc::new(SyncDouble)), duplicate);
+
+        let mut engine = Engine::new();
+        engine.register_function(Arc::new(SyncDouble)).unwrap();
+        assert_eq!(
+            engine.register_async_function(Arc::new(AsyncDouble)),
+            duplicate
+        );
+
+        assert!(Engine::builder()
+            .register_async_function(Arc::new(AsyncMax))
+            .build()
+            .is_err());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_execute_async_calls_async_functions() {
+        let mut engine = Engine::new();
+        engine
+            .register_async_function(Arc::new(AsyncDouble))
+            .unwrap();
+
+        engine
+            .execute_async(vec![
+                Formula::new("doubled", "return async_double(21)"),
+                Formula::new("plus_one", "return get_output_from('doubled') + 1"),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(engine.get_result("plus_one"), Some(Value::Number(43.0)));
+        assert!(engine.get_errors().is_empty());
+
+        let mut engine = Engine::new();
+        engine
+            .register_async_function(Arc::new(AsyncDouble))
+            .unwrap();
+        engine
+            .execute_async(vec![Formula::new("bad", "return async_double('x')")])
+            .await
+            .unwrap();
+        assert!(engine.get_errors()["bad"].contains("Expected number"));
+    }
 }
@@ -18,6 +18,9 @@ pub enum CalculatorError {
     #[error("Function not found: {0}")]
     FunctionNotFound(String),
 
+    #[error("Function already registered: {0}")]
+    FunctionAlreadyRegistered(String),
+
     #[error("Variable not found: {0}")]
     VariableNotFound(String),
 
@@ -35,6 +38,79 @@ pub enum CalculatorError {
 
     #[error("Division by zero")]
     DivisionByZero,
+
+    #[error("Limit exceeded: {0}")]
+    LimitExceeded(String),
+
+    #[error("Execution cancelled")]
+    Cancelled,
+
+    /// An error annotated with where it occurred; displays as the underlying error.
+    #[error("{source}")]
+    WithContext {
+        context: ErrorContext,
+        source: Box<CalculatorError>,
+    },
+}
+
+/// Where a [`CalculatorError`] occurred, attached by [`CalculatorError::with_context`].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ErrorContext {
+    /// The formula being executed
+    pub formula_name: Option<String>,
+    /// The expression being evaluated
+    pub expression: Option<String>,
+}
+
+impl CalculatorError {
+    /// Records that the error occurred while executing the formula `name`.
+    ///
+    /// The message is unchanged; use [`CalculatorError::context`] to read the formula
+    /// name and [`CalculatorError::root`] to match on the underlying error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::CalculatorError;
+    ///
+    /// let error = CalculatorError::DivisionByZero.with_context("ratio");
+    /// assert_eq!(error.to_string(), "Division by zero");
+    /// assert_eq!(error.context().unwrap().formula_name.as_deref(), Some("ratio"));
+    /// assert_eq!(error.root(), &CalculatorError::DivisionByZero);
+    /// ```
+    pub fn with_context(self, name: &str) -> Self {
+        self.map_context(|context| context.formula_name = Some(name.to_string()))
+    }
+
+    /// Records that the error occurred while evaluating `expression`.
+    pub fn with_expression(self, expression: &str) -> Self {
+        self.map_context(|context| context.expression = Some(expression.to_string()))
+    }
+
+    /// Returns the context attached to this error, if any.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            CalculatorError::WithContext { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// Returns the underlying error, without any attached context.
+    pub fn root(&self) -> &CalculatorError {
+        match self {
+            CalculatorError::WithContext { source, .. } => source.root(),
+            error => error,
+        }
+    }
+
+    fn map_context(self, update: impl FnOnce(&mut ErrorContext)) -> Self {
+        let (mut context, source) = match self {
+            CalculatorError::WithContext { context, source } => (context, source),
+            error => (ErrorContext::default(), Box::new(error)),
+        };
+        update(&mut context);
+        CalculatorError::WithContext { context, source }
+    }
 }
 
 /// A specialized `Result` type for formula operations.
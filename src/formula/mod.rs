@@ -1,4 +1,10 @@
+use crate::error::Result;
+use crate::parser::{ConstantFolder, ExprVisitor, Parser, Program, References, Statement};
 use regex::Regex;
+use std::fmt;
+
+/// Bodies longer than this many characters are cut short by [`FormulaT::display_str`].
+const DISPLAY_BODY_CHARS: usize = 60;
 
 /// Trait representing a formula with name, body, and dependencies.
 ///
@@ -7,6 +13,41 @@ pub trait FormulaT {
     fn name(&self) -> &str;
     fn body(&self) -> &str;
     fn depends_on(&self) -> &[String];
+
+    /// Human-readable documentation for the formula, if any.
+    fn description(&self) -> Option<&str> {
+        None
+    }
+
+    /// Labels grouping the formula for selective execution.
+    fn tags(&self) -> &[String] {
+        &[]
+    }
+
+    /// Checks that the body parses, without executing it; see [`Formula::validate`].
+    fn validate_syntax(&self) -> Result<()> {
+        Formula::validate(self.body())
+    }
+
+    /// One-line summary for logs: `name: [dependencies] → body`.
+    ///
+    /// Whitespace in the body is collapsed and bodies over 60 characters are
+    /// truncated with `...`.
+    fn display_str(&self) -> String {
+        let deps = if self.depends_on().is_empty() {
+            "no deps".to_string()
+        } else {
+            self.depends_on().join(", ")
+        };
+
+        let body = self.body().split_whitespace().collect::<Vec<_>>().join(" ");
+        let body = match body.char_indices().nth(DISPLAY_BODY_CHARS) {
+            Some((end, _)) => format!("{}...", &body[..end]),
+            None => body,
+        };
+
+        format!("{}: [{}] → {}", self.name(), deps, body)
+    }
 }
 
 /// A formula with a name, body, and automatically detected dependencies.
@@ -30,13 +71,18 @@ pub struct Formula {
     name: String,
     body: String,
     depends_on: Vec<String>,
+    description: Option<String>,
+    tags: Vec<String>,
 }
 
 impl Formula {
     /// Creates a new formula with the given name and body.
     ///
     /// Dependencies are automatically extracted from `get_output_from('name')` calls
-    /// in the formula body using regex pattern matching.
+    /// in the formula body using regex pattern matching. Only literal names are
+    /// found: a formula calling `get_output_from('q' + quarter)` gets no dependency
+    /// for it and may run before its target, failing with a dependency error. Use
+    /// [`Formula::new_explicit`] to declare such dependencies.
     ///
     /// # Arguments
     ///
@@ -52,26 +98,184 @@ impl Formula {
     /// let with_dep = Formula::new("total", "return get_output_from('tax') + get_output_from('price')");
     /// ```
     pub fn new(name: impl Into<String>, body: impl Into<String>) -> Self {
-        let name = name.into();
         let body = body.into();
         let depends_on = Self::build_depends_on(&body);
+        Self::from_parts(name.into(), body, depends_on)
+    }
+
+    /// Creates a formula with an explicit dependency list.
+    ///
+    /// The body is not scanned for `get_output_from` calls, so this suits generated
+    /// formula sets whose dependency graph is already known, and formulas whose
+    /// dependency names are computed at runtime.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Formula, FormulaT};
+    ///
+    /// let formula = Formula::new_explicit(
+    ///     "latest",
+    ///     "return get_output_from('q' + quarter)",
+    ///     vec!["q1".to_string(), "q2".to_string()],
+    /// );
+    /// assert_eq!(formula.depends_on(), ["q1".to_string(), "q2".to_string()]);
+    /// ```
+    pub fn new_explicit(
+        name: impl Into<String>,
+        body: impl Into<String>,
+        depends_on: Vec<String>,
+    ) -> Self {
+        Self::from_parts(name.into(), body.into(), depends_on)
+    }
 
+    fn from_parts(name: String, body: String, depends_on: Vec<String>) -> Self {
         Self {
             name,
             body,
             depends_on,
+            description: None,
+            tags: Vec::new(),
         }
     }
 
+    /// Attaches a human-readable description, for documenting formula sets.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::Formula;
+    ///
+    /// let formula = Formula::new("tax", "return price * 0.2")
+    ///     .with_description("Sales tax at the standard 20% rate");
+    /// assert_eq!(formula.description(), Some("Sales tax at the standard 20% rate"));
+    /// ```
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Returns the description set with [`Formula::with_description`], if any.
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Attaches tags grouping the formula, e.g. `"pricing"` or `"validation"`.
+    ///
+    /// See [`crate::Engine::execute_tagged`] to execute only formulas with given tags.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::Formula;
+    ///
+    /// let formula = Formula::new("discount", "return 0.1").with_tags(vec!["pricing".to_string()]);
+    /// assert_eq!(formula.tags(), ["pricing".to_string()]);
+    /// ```
+    pub fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// Returns the tags set with [`Formula::with_tags`].
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    /// Creates a formula named `namespace.name`, so modules can reuse short names.
+    ///
+    /// Other formulas refer to it by its qualified name, as in
+    /// `get_output_from('pricing.base')`. An empty namespace gives a plain
+    /// [`Formula::new`] name.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{Formula, FormulaT};
+    ///
+    /// let base = Formula::new_namespaced("pricing", "base", "return 100");
+    /// assert_eq!(base.name(), "pricing.base");
+    ///
+    /// let total = Formula::new_namespaced("billing", "total", "return get_output_from('pricing.base') * 2");
+    /// assert_eq!(total.depends_on(), ["pricing.base".to_string()]);
+    /// ```
+    pub fn new_namespaced(
+        namespace: &str,
+        name: impl Into<String>,
+        body: impl Into<String>,
+    ) -> Self {
+        Self::new(qualified_name(namespace, &name.into()), body)
+    }
+
+    /// Checks that a formula body lexes and parses, without evaluating it.
+    ///
+    /// Variables, functions, and `get_output_from` targets are not resolved, so a
+    /// body can be validated before any of them exist.
+    /// See [`crate::Engine::validate_all`] to check those references too.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::{CalculatorError, Formula};
+    ///
+    /// assert!(Formula::validate("return price * 2").is_ok());
+    /// assert!(matches!(
+    ///     Formula::validate("return (price *"),
+    ///     Err(CalculatorError::ParseError(_))
+    /// ));
+    /// ```
+    pub fn validate(body: &str) -> Result<()> {
+        Parser::new(body)?.parse().map(|_| ())
+    }
+
+    /// Parses the formula body and returns its syntax tree.
+    ///
+    /// The tree is exactly as written, before constant folding, so it can be
+    /// walked for debugging or static analysis (see [`crate::parser::References`]).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::Formula;
+    /// use formcalc::parser::{Expr, Statement};
+    ///
+    /// let program = Formula::new("total", "return price * 2").ast().unwrap();
+    /// assert!(matches!(program.statement, Statement::Return(Expr::Multiply(_, _))));
+    /// ```
+    pub fn ast(&self) -> Result<Program> {
+        Parser::new(&self.body)?.parse()
+    }
+
+    /// Returns the names of the variables the formula body reads, sorted and deduplicated.
+    ///
+    /// Function names are not variables and are never included. Variables read only
+    /// in a branch that would not be taken are still listed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use formcalc::Formula;
+    ///
+    /// let formula = Formula::new("total", "return max(price, floor_price) * qty");
+    /// assert_eq!(
+    ///     formula.referenced_variables().unwrap(),
+    ///     vec!["floor_price", "price", "qty"]
+    /// );
+    /// ```
+    pub fn referenced_variables(&self) -> Result<Vec<String>> {
+        let references = References::collect(self.ast()?)?;
+        Ok(references.variables.into_iter().collect())
+    }
+
     /// Extract dependencies from the formula body by finding get_output_from calls
-    /// Pattern: get_output_from('formula_name')
+    /// Pattern: get_output_from('formula_name') or get_output_from("formula_name")
     fn build_depends_on(body: &str) -> Vec<String> {
         // Rust regex doesn't support lookahead/lookbehind, so we'll use a simpler approach
-        let pattern = r"get_output_from\('([^']+)'\)";
+        let pattern = r#"get_output_from\((?:'([^']+)'|"([^"]+)")\)"#;
         let re = Regex::new(pattern).unwrap();
 
         re.captures_iter(body)
-            .filter_map(|cap| cap.get(1).map(|m| m.as_str().to_string()))
+            .filter_map(|cap| cap.get(1).or(cap.get(2)).map(|m| m.as_str().to_string()))
             .collect()
     }
 }
@@ -88,6 +292,137 @@ impl FormulaT for Formula {
     fn depends_on(&self) -> &[String] {
         &self.depends_on
     }
+
+    fn description(&self) -> Option<&str> {
+        Formula::description(self)
+    }
+
+    fn tags(&self) -> &[String] {
+        Formula::tags(self)
+    }
+}
+
+/// Lets boxed trait objects, e.g. `Box<dyn FormulaT + Send + Sync>`, be executed
+/// alongside concrete formula types.
+impl<T: FormulaT + ?Sized> FormulaT for Box<T> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn body(&self) -> &str {
+        (**self).body()
+    }
+
+    fn depends_on(&self) -> &[String] {
+        (**self).depends_on()
+    }
+
+    fn description(&self) -> Option<&str> {
+        (**self).description()
+    }
+
+    fn tags(&self) -> &[String] {
+        (**self).tags()
+    }
+
+    fn display_str(&self) -> String {
+        (**self).display_str()
+    }
+}
+
+impl fmt::Display for Formula {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.display_str())
+    }
+}
+
+/// A [`Formula`] whose body has already been parsed and constant-folded.
+///
+/// Compiling once and executing many times via [`crate::Engine::execute_compiled`]
+/// skips the parse step, which pays off when the same formula set is run
+/// repeatedly with different variables.
+///
+/// # Examples
+///
+/// ```
+/// use formcalc::{CompiledFormula, Formula, FormulaT};
+///
+/// let compiled = CompiledFormula::new(Formula::new("total", "return price * (1 + 0.2)")).unwrap();
+/// assert_eq!(compiled.formula().name(), "total");
+///
+/// assert!(CompiledFormula::new(Formula::new("bad", "return (1 +")).is_err());
+/// ```
+#[derive(Debug, Clone)]
+pub struct CompiledFormula {
+    formula: Formula,
+    program: Program,
+}
+
+impl CompiledFormula {
+    /// Parses and folds the formula body, returning the first parse error if any.
+    pub fn new(formula: Formula) -> Result<Self> {
+        let program = compile(formula.body())?;
+        Ok(Self { formula, program })
+    }
+
+    /// Returns the source formula.
+    pub fn formula(&self) -> &Formula {
+        &self.formula
+    }
+
+    /// Returns the parsed and folded program.
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub(crate) fn set_name(&mut self, name: String) {
+        self.formula.name = name;
+    }
+}
+
+/// Joins a namespace and a formula name with `.`; the empty namespace leaves the name as is.
+pub(crate) fn qualified_name(namespace: &str, name: &str) -> String {
+    if namespace.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", namespace, name)
+    }
+}
+
+/// Parses a formula body and folds its constant sub-expressions.
+pub(crate) fn compile(body: &str) -> Result<Program> {
+    let program = Parser::new(body)?.parse()?;
+    ConstantFolder::new().visit_program(program)
+}
+
+/// Compiles a bare expression, such as `price * 2`, as an implicit `return`.
+pub(crate) fn compile_expression(expression: &str) -> Result<Program> {
+    let expr = Parser::new(expression)?.parse_expression()?;
+    ConstantFolder::new().visit_program(Program {
+        statement: Statement::Return(expr),
+    })
+}
+
+impl FormulaT for CompiledFormula {
+    fn name(&self) -> &str {
+        self.formula.name()
+    }
+
+    fn body(&self) -> &str {
+        self.formula.body()
+    }
+
+    fn depends_on(&self) -> &[String] {
+        self.formula.depends_on()
+    }
+
+    fn description(&self) -> Option<&str> {
+        self.formula.description()
+    }
+
+    fn tags(&self) -> &[String] {
+        self.formula.tags()
+    }
 }
 
 #[cfg(test)]
@@ -110,6 +445,57 @@ mod tests {
         assert_eq!(formula.depends_on().len(), 2);
         assert!(formula.depends_on().contains(&"formula1".to_string()));
         assert!(formula.depends_on().contains(&"formula2".to_string()));
+
+        let formula = Formula::new("test", r#"return get_output_from("formula1") * 2"#);
+        assert_eq!(formula.depends_on(), ["formula1".to_string()]);
+    }
+
+    #[test]
+    fn test_formula_explicit_dependencies() {
+        let formula = Formula::new_explicit(
+            "test",
+            "return get_output_from('formula1') + get_output_from(name)",
+            vec!["formula2".to_string()],
+        );
+        assert_eq!(formula.depends_on(), ["formula2".to_string()]);
+    }
+
+    #[test]
+    fn test_formula_description() {
+        let formula = Formula::new("tax", "return price * 0.2");
+        assert_eq!(formula.description(), None);
+
+        let formula = formula.with_description("Sales tax");
+        assert_eq!(formula.description(), Some("Sales tax"));
+        assert_eq!(FormulaT::description(&formula), Some("Sales tax"));
+
+        let compiled = CompiledFormula::new(formula).unwrap();
+        assert_eq!(FormulaT::description(&compiled), Some("Sales tax"));
+    }
+
+    #[test]
+    fn test_formula_display() {
+        assert_eq!(
+            Formula::new("base_price", "return 100").to_string(),
+            "base_price: [no deps] → return 100"
+        );
+        assert_eq!(
+            Formula::new(
+                "final_price",
+                "return get_output_from('with_tax') + get_output_from('fee')"
+            )
+            .to_string(),
+            "final_price: [with_tax, fee] → return get_output_from('with_tax') + get_output_from('fee')"
+        );
+
+        let long = Formula::new(
+            "long",
+            "if (x > 1) then\n    return 'a very long string literal that keeps going'\nelse\n    return 'b'\nend",
+        );
+        assert_eq!(
+            long.to_string(),
+            "long: [no deps] → if (x > 1) then return 'a very long string literal that keep..."
+        );
     }
 
     #[test]
@@ -117,4 +503,103 @@ mod tests {
         let formula = Formula::new("simple", "return 42");
         assert_eq!(formula.depends_on().len(), 0);
     }
+
+    #[test]
+    fn test_namespaced_formula() {
+        let formula = Formula::new_namespaced(
+            "billing",
+            "total",
+            "return get_output_from('pricing.base') + get_output_from('tax')",
+        );
+        assert_eq!(formula.name(), "billing.total");
+        assert_eq!(
+            formula.depends_on(),
+            ["pricing.base".to_string(), "tax".to_string()]
+        );
+
+        assert_eq!(
+            Formula::new_namespaced("", "plain", "return 1").name(),
+            "plain"
+        );
+    }
+
+    #[test]
+    fn test_compiled_formula_folds_constants() {
+        use crate::parser::{Expr, Statement};
+
+        let compiled = CompiledFormula::new(Formula::new("test", "return 2 * 3 + 1")).unwrap();
+        assert_eq!(compiled.name(), "test");
+        assert_eq!(
+            compiled.program().statement,
+            Statement::Return(Expr::Number(7.0))
+        );
+    }
+
+    #[test]
+    fn test_compiled_formula_parse_error() {
+        let result = CompiledFormula::new(Formula::new("bad", "return 1 +"));
+        assert!(matches!(
+            result,
+            Err(crate::error::CalculatorError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate_body() {
+        assert_eq!(Formula::validate("return unknown_var / 0"), Ok(()));
+        assert_eq!(
+            Formula::validate("if (x > 1) then return 'a' else return 'b' end"),
+            Ok(())
+        );
+        assert!(matches!(
+            Formula::validate("return 1 +"),
+            Err(crate::error::CalculatorError::ParseError(_))
+        ));
+        assert!(Formula::validate("return max(1)").is_err());
+    }
+
+    #[test]
+    fn test_validate_syntax_before_execution() {
+        assert_eq!(Formula::new("ok", "return x * 2").validate_syntax(), Ok(()));
+        let boxed: Box<dyn FormulaT> = Box::new(Formula::new("bad", "return (x *"));
+        assert!(matches!(
+            boxed.validate_syntax(),
+            Err(crate::error::CalculatorError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_formula_ast() {
+        use crate::parser::{Expr, Statement};
+
+        let formula = Formula::new("test", "return (2 + rate) * bonus(3)");
+        assert_eq!(
+            formula.ast().unwrap().statement,
+            Statement::Return(Expr::Multiply(
+                Box::new(Expr::Add(
+                    Box::new(Expr::Number(2.0)),
+                    Box::new(Expr::Identifier("rate".to_string()))
+                )),
+                Box::new(Expr::FunctionCall {
+                    name: "bonus".to_string(),
+                    args: vec![Expr::Number(3.0)],
+                })
+            ))
+        );
+
+        assert!(Formula::new("bad", "return (1 +").ast().is_err());
+    }
+
+    #[test]
+    fn test_referenced_variables() {
+        let formula = Formula::new("test", "return bonus(rate) + rate * base");
+        assert_eq!(
+            formula.referenced_variables().unwrap(),
+            vec!["base".to_string(), "rate".to_string()]
+        );
+        assert!(Formula::new("none", "return 42")
+            .referenced_variables()
+            .unwrap()
+            .is_empty());
+    }
 }
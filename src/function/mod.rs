@@ -1,5 +1,15 @@
 use crate::error::Result;
 use crate::value::Value;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// A boxed, `Send` future, as returned by [`AsyncFunction::execute`].
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Drives an async function call to completion from synchronous evaluation code.
+pub(crate) type AsyncRunner =
+    Arc<dyn for<'a> Fn(BoxFuture<'a, Result<Value>>) -> Result<Value> + Send + Sync>;
 
 /// Trait for custom functions that can be called from formulas.
 ///
@@ -57,6 +67,50 @@ pub trait Function: Send + Sync {
     fn execute(&self, params: &[Value]) -> Result<Value>;
 }
 
+/// Trait for custom functions that need to await I/O, such as database or HTTP lookups.
+///
+/// Async functions are registered with [`crate::Engine::register_async_function`] and
+/// share the namespace of [`Function`]s. Formulas calling them must be run with
+/// `Engine::execute_async` (requires the `tokio` feature); synchronous execution
+/// reports an error for those formulas instead.
+///
+/// # Examples
+///
+/// ```
+/// use formcalc::function::BoxFuture;
+/// use formcalc::{AsyncFunction, Result, Value};
+///
+/// struct ExchangeRate;
+///
+/// impl AsyncFunction for ExchangeRate {
+///     fn name(&self) -> &str {
+///         "exchange_rate"
+///     }
+///
+///     fn num_args(&self) -> usize {
+///         1
+///     }
+///
+///     fn execute<'a>(&'a self, params: &'a [Value]) -> BoxFuture<'a, Result<Value>> {
+///         Box::pin(async move {
+///             // e.g. `let rate = client.get_rate(&params[0].get()).await?;`
+///             let _currency = &params[0];
+///             Ok(Value::Number(1.1))
+///         })
+///     }
+/// }
+/// ```
+pub trait AsyncFunction: Send + Sync {
+    /// Returns the function name used to call it from formulas.
+    fn name(&self) -> &str;
+
+    /// Returns the number of arguments this function expects.
+    fn num_args(&self) -> usize;
+
+    /// Starts executing the function with the given parameters.
+    fn execute<'a>(&'a self, params: &'a [Value]) -> BoxFuture<'a, Result<Value>>;
+}
+
 /// Builds a function identifier from name and number of arguments.
 ///
 /// The function ID is used internally to uniquely identify functions,
@@ -77,7 +131,7 @@ pub fn build_function_id(name: &str, num_args: usize) -> String {
 }
 
 /// Convert a string to snake_case
-fn to_snake_case(s: &str) -> String {
+pub(crate) fn to_snake_case(s: &str) -> String {
     let mut result = String::new();
     let chars: Vec<char> = s.chars().collect();
 
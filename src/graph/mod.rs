@@ -40,11 +40,93 @@ where
         self.data.get(key)
     }
 
+    /// Get mutable data for a specific key
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.data.get_mut(key)
+    }
+
     /// Check if a key exists in the graph
     pub fn contains(&self, key: &K) -> bool {
         self.outgoing_edges.contains_key(key)
     }
 
+    /// Remove a node and its outgoing edges, returning its data
+    ///
+    /// Nodes that depended on the removed node keep their edge to it and are
+    /// reported as detached until a node with the same key is added again.
+    pub fn remove_node(&mut self, key: &K) -> Option<V> {
+        let outgoing = self.outgoing_edges.remove(key)?;
+
+        for dest in &outgoing {
+            if let Some(incoming) = self.incoming_edges.get_mut(dest) {
+                incoming.remove(key);
+                if incoming.is_empty() {
+                    self.incoming_edges.remove(dest);
+                }
+            }
+        }
+
+        self.data.remove(key)
+    }
+
+    /// Rename a node, rewriting every edge that points to or from it
+    pub fn rename_node(&mut self, old: &K, new: K) -> Result<(), String> {
+        if !self.outgoing_edges.contains_key(old) {
+            return Err("Node with the provided key does not exist".to_string());
+        }
+        if self.outgoing_edges.contains_key(&new) {
+            return Err("Node with the provided key already exists".to_string());
+        }
+
+        // Dependencies of the node now record the new key as their dependent
+        let outgoing = self.outgoing_edges.remove(old).unwrap_or_default();
+        for dest in &outgoing {
+            if let Some(incoming) = self.incoming_edges.get_mut(dest) {
+                incoming.remove(old);
+                incoming.insert(new.clone());
+            }
+        }
+        self.outgoing_edges.insert(new.clone(), outgoing);
+
+        // Dependents of the node now point at the new key
+        if let Some(incoming) = self.incoming_edges.remove(old) {
+            for source in &incoming {
+                if let Some(outgoing) = self.outgoing_edges.get_mut(source) {
+                    outgoing.remove(old);
+                    outgoing.insert(new.clone());
+                }
+            }
+            self.incoming_edges
+                .entry(new.clone())
+                .or_default()
+                .extend(incoming);
+        }
+
+        if let Some(data) = self.data.remove(old) {
+            self.data.insert(new, data);
+        }
+        Ok(())
+    }
+
+    /// Get the existing nodes that depend directly on a key
+    pub fn dependents(&self, key: &K) -> Vec<K> {
+        self.incoming_edges
+            .get(key)
+            .map(|incoming| {
+                incoming
+                    .iter()
+                    .filter(|dependent| self.outgoing_edges.contains_key(*dependent))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Get the keys of all nodes in the graph
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.outgoing_edges.keys()
+    }
+
     /// Add edges from a key to its dependencies
     fn add_edges(&mut self, key: K, outgoing: Vec<K>) {
         let outgoing_set: HashSet<K> = outgoing.into_iter().collect();
@@ -119,8 +201,16 @@ where
         // Remove the last empty layer
         layers.pop();
 
-        // Add remaining unsatisfied keys to detached
-        detached.extend(unsatisfied_keys);
+        // Every other node is detached: the remaining unsatisfied keys, and nodes that
+        // only depend on detached nodes and so were never candidates above
+        let known: HashSet<&K> = detached.iter().collect();
+        let mut unresolved: Vec<K> = self
+            .outgoing_edges
+            .keys()
+            .filter(|key| !satisfied_keys.contains(*key) && !known.contains(key))
+            .cloned()
+            .collect();
+        detached.append(&mut unresolved);
 
         (layers, detached)
     }
@@ -191,4 +281,93 @@ mod tests {
         assert_eq!(detached.len(), 1);
         assert_eq!(detached[0], "a".to_string());
     }
+
+    #[test]
+    fn test_nodes_behind_detached_nodes_are_detached() {
+        let mut graph = DAGraph::new();
+        graph.add_node("root".to_string(), 1, vec![]).unwrap();
+        graph
+            .add_node(
+                "a".to_string(),
+                2,
+                vec!["root".to_string(), "missing".to_string()],
+            )
+            .unwrap();
+        graph
+            .add_node("b".to_string(), 3, vec!["a".to_string()])
+            .unwrap();
+        graph
+            .add_node("c".to_string(), 4, vec!["b".to_string()])
+            .unwrap();
+
+        let (layers, mut detached) = graph.topological_sort();
+        detached.sort();
+        assert_eq!(layers, vec![vec!["root".to_string()]]);
+        assert_eq!(detached, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_remove_node() {
+        let mut graph = DAGraph::new();
+        graph.add_node("a".to_string(), 1, vec![]).unwrap();
+        graph
+            .add_node("b".to_string(), 2, vec!["a".to_string()])
+            .unwrap();
+
+        assert_eq!(graph.dependents(&"a".to_string()), vec!["b".to_string()]);
+        assert_eq!(graph.remove_node(&"a".to_string()), Some(1));
+        assert_eq!(graph.remove_node(&"a".to_string()), None);
+        assert!(!graph.contains(&"a".to_string()));
+
+        let (layers, detached) = graph.topological_sort();
+        assert!(layers.is_empty());
+        assert_eq!(detached, vec!["b".to_string()]);
+
+        graph.add_node("a".to_string(), 3, vec![]).unwrap();
+        let (layers, detached) = graph.topological_sort();
+        assert_eq!(layers.len(), 2);
+        assert!(detached.is_empty());
+
+        assert_eq!(graph.remove_node(&"b".to_string()), Some(2));
+        assert!(graph.dependents(&"a".to_string()).is_empty());
+    }
+
+    #[test]
+    fn test_rename_node() {
+        let mut graph = DAGraph::new();
+        graph.add_node("a".to_string(), 1, vec![]).unwrap();
+        graph
+            .add_node("b".to_string(), 2, vec!["a".to_string()])
+            .unwrap();
+        graph
+            .add_node("c".to_string(), 3, vec!["b".to_string()])
+            .unwrap();
+
+        graph
+            .rename_node(&"b".to_string(), "m".to_string())
+            .unwrap();
+
+        assert!(!graph.contains(&"b".to_string()));
+        assert_eq!(graph.get(&"m".to_string()), Some(&2));
+        assert_eq!(graph.dependents(&"a".to_string()), vec!["m".to_string()]);
+        assert_eq!(graph.dependents(&"m".to_string()), vec!["c".to_string()]);
+
+        let (layers, detached) = graph.topological_sort();
+        assert_eq!(
+            layers,
+            vec![
+                vec!["a".to_string()],
+                vec!["m".to_string()],
+                vec!["c".to_string()]
+            ]
+        );
+        assert!(detached.is_empty());
+
+        assert!(graph
+            .rename_node(&"b".to_string(), "x".to_string())
+            .is_err());
+        assert!(graph
+            .rename_node(&"m".to_string(), "a".to_string())
+            .is_err());
+    }
 }
@@ -19,13 +19,16 @@
 //! ```rust
 //! use formcalc::{Engine, Formula, Value};
 //!
-//! let mut engine = Engine::new();
+//! let engine = Engine::new();
 //!
 //! // Simple calculation
 //! let formula = Formula::new("calculation", "return 2 + 2 * 3");
 //! engine.execute(vec![formula]).unwrap();
 //! let result = engine.get_result("calculation").unwrap();
 //! assert_eq!(result, Value::Number(8.0));
+//!
+//! // The same calculation as a one-off expression
+//! assert_eq!(engine.evaluate("2 + 2 * 3"), Ok(Value::Number(8.0)));
 //! ```
 //!
 //! ## Using Variables
@@ -33,7 +36,7 @@
 //! ```rust
 //! use formcalc::{Engine, Formula, Value};
 //!
-//! let mut engine = Engine::new();
+//! let engine = Engine::new();
 //! engine.set_variable("price".to_string(), Value::Number(100.0));
 //! engine.set_variable("tax_rate".to_string(), Value::Number(0.2));
 //!
@@ -51,7 +54,7 @@
 //! ```rust
 //! use formcalc::{Engine, Formula, Value};
 //!
-//! let mut engine = Engine::new();
+//! let engine = Engine::new();
 //!
 //! let formula1 = Formula::new("base_price", "return 100");
 //! let formula2 = Formula::new("with_tax", "return get_output_from('base_price') * 1.2");
@@ -92,7 +95,7 @@
 //! }
 //!
 //! let mut engine = Engine::new();
-//! engine.register_function(Arc::new(DoubleFunction));
+//! engine.register_function(Arc::new(DoubleFunction)).unwrap();
 //!
 //! let formula = Formula::new("test", "return double(21)");
 //! engine.execute(vec![formula]).unwrap();
@@ -102,11 +105,13 @@
 //! ```
 
 pub mod cache;
+pub mod cancellation;
 pub mod engine;
 pub mod error;
 pub mod formula;
 pub mod function;
 pub mod graph;
+pub mod options;
 pub mod parser;
 pub mod value;
 
@@ -115,10 +120,15 @@ pub mod value;
 pub mod wasm;
 
 // Re-export main types
-pub use engine::Engine;
-pub use error::{CalculatorError, Result};
-pub use formula::{Formula, FormulaT};
-pub use function::Function;
+pub use cancellation::CancellationToken;
+pub use engine::{
+    Engine, EngineBuilder, EngineCacheStats, EngineSnapshot, EngineState, ExecutionReport,
+    FormulaTiming, LayerStats, ProgressEvent, RunStats,
+};
+pub use error::{CalculatorError, ErrorContext, Result};
+pub use formula::{CompiledFormula, Formula, FormulaT};
+pub use function::{AsyncFunction, Function};
+pub use options::EngineOptions;
 pub use value::Value;
 
 // WASM initialization support
@@ -137,7 +147,7 @@ mod tests {
 
     #[test]
     fn test_basic_calculation() {
-        let mut engine = Engine::new();
+        let engine = Engine::new();
         let formula = Formula::new("simple", "return 1 + 1");
 
         engine.execute(vec![formula]).unwrap();
@@ -148,7 +158,7 @@ mod tests {
 
     #[test]
     fn test_complex_expression() {
-        let mut engine = Engine::new();
+        let engine = Engine::new();
         let formula = Formula::new("complex", "return (5 + 3) * 2 - 1");
 
         engine.execute(vec![formula]).unwrap();
@@ -159,7 +169,7 @@ mod tests {
 
     #[test]
     fn test_string_concatenation() {
-        let mut engine = Engine::new();
+        let engine = Engine::new();
         let formula = Formula::new("concat", "return 'Hello' + ' ' + 'World'");
 
         engine.execute(vec![formula]).unwrap();
@@ -170,7 +180,7 @@ mod tests {
 
     #[test]
     fn test_builtin_functions() {
-        let mut engine = Engine::new();
+        let engine = Engine::new();
         let formula = Formula::new("funcs", "return max(10, 20) + min(5, 3)");
 
         engine.execute(vec![formula]).unwrap();
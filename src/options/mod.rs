@@ -0,0 +1,57 @@
+use chrono::NaiveDate;
+
+/// Configuration switches that change how the [`crate::Engine`] executes formulas.
+///
+/// All options default to the engine's historical behavior.
+///
+/// # Examples
+///
+/// ```
+/// use formcalc::EngineOptions;
+///
+/// let options = EngineOptions::default();
+/// assert!(!options.reject_nonfinite);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct EngineOptions {
+    /// When `true`, a formula whose result is `NaN` or infinite is recorded as an
+    /// error instead of being stored as a result.
+    pub reject_nonfinite: bool,
+
+    /// Holidays excluded by `business_days_between`, in addition to any passed
+    /// explicitly to the function.
+    pub holidays: Vec<NaiveDate>,
+
+    /// When `true`, a formula whose dependency failed is not executed; it is
+    /// recorded as a dependency error naming the failed upstream formula instead.
+    pub skip_on_dependency_error: bool,
+
+    /// Number of threads used to execute each dependency layer. `None` runs on
+    /// rayon's global thread pool; `Some(n)` gives the engine its own pool of `n`
+    /// threads, and `Some(1)` executes formulas one at a time.
+    pub parallelism: Option<usize>,
+
+    /// When `true`, an identifier with no variable set evaluates to its own name as
+    /// a string instead of failing with `VariableNotFound`.
+    pub unknown_identifier_as_string: bool,
+
+    /// When `true`, arithmetic operators and numeric built-ins fail with `EvalError`
+    /// as soon as they produce `NaN` or an infinite number, naming the operation,
+    /// instead of letting the value propagate.
+    pub strict_math: bool,
+
+    /// Maximum number of expression nodes a single formula evaluation may visit.
+    /// `None` means unlimited; once exceeded the evaluation fails with
+    /// `CalculatorError::LimitExceeded`.
+    pub max_eval_steps: Option<usize>,
+
+    /// When `true`, every [`crate::ExecutionReport`] carries a [`crate::RunStats`]
+    /// with the duration of each layer and the parse and evaluation time of each
+    /// formula, at the cost of a few clock reads per formula.
+    pub collect_run_stats: bool,
+
+    /// When `true`, an execution stops after the first dependency layer in which a
+    /// formula fails. Formulas in later layers are not run and are listed in
+    /// [`crate::ExecutionReport::skipped`].
+    pub fail_fast: bool,
+}
@@ -1,3 +1,9 @@
+use std::fmt;
+
+/// An expression node.
+///
+/// Displays as equivalent formula source in which every nested operator is
+/// parenthesized, so `2 + 3 * 4` renders as `2 + (3 * 4)`.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     // Literals
@@ -39,16 +45,59 @@ pub enum Expr {
     Rnd(Box<Expr>, Box<Expr>),
     Ceil(Box<Expr>),
     Floor(Box<Expr>),
+    MRound(Box<Expr>, Box<Expr>),
+    FloorTo(Box<Expr>, Box<Expr>),
+    CeilTo(Box<Expr>, Box<Expr>),
     Exp(Box<Expr>),
+    Log(Box<Expr>, Box<Expr>),
     Year(Box<Expr>),
     Month(Box<Expr>),
     Day(Box<Expr>),
-    Substr(Box<Expr>, Box<Expr>, Box<Expr>),
+    Substr(Box<Expr>, Box<Expr>, Option<Box<Expr>>),
     AddDays(Box<Expr>, Box<Expr>),
     GetDiffDays(Box<Expr>, Box<Expr>),
+    GetDiffHours(Box<Expr>, Box<Expr>),
+    GetDiffMinutes(Box<Expr>, Box<Expr>),
     PaddedString(Box<Expr>, Box<Expr>),
+    FormatNumber(Box<Expr>, Box<Expr>, Option<Box<Expr>>),
     GetDiffMonths(Box<Expr>, Box<Expr>),
     GetOutputFrom(Box<Expr>),
+    BusinessDaysBetween(Box<Expr>, Box<Expr>, Option<Box<Expr>>),
+    Iif(Box<Expr>, Box<Expr>, Box<Expr>),
+    Date(Box<Expr>),
+    Sum(Vec<Expr>),
+    Avg(Vec<Expr>),
+    Count(Vec<Expr>),
+    Product(Vec<Expr>),
+    Median(Vec<Expr>),
+    Percentile(Box<Expr>, Box<Expr>),
+    Variance(Vec<Expr>),
+    VarianceP(Vec<Expr>),
+    Stddev(Vec<Expr>),
+    StddevP(Vec<Expr>),
+    Pmt(Vec<Expr>),
+    ModE(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+    Gcd(Box<Expr>, Box<Expr>),
+    BitAnd(Box<Expr>, Box<Expr>),
+    BitOr(Box<Expr>, Box<Expr>),
+    BitXor(Box<Expr>, Box<Expr>),
+    Lcm(Box<Expr>, Box<Expr>),
+    Factorial(Box<Expr>),
+    IsEven(Box<Expr>),
+    IsOdd(Box<Expr>),
+    Combinations(Box<Expr>, Box<Expr>),
+    Npv(Box<Expr>, Vec<Expr>),
+    Irr(Vec<Expr>),
+    Split(Box<Expr>, Box<Expr>),
+    Join(Box<Expr>, Box<Expr>),
+    Fv(Box<Expr>, Box<Expr>, Box<Expr>),
+    Cagr(Box<Expr>, Box<Expr>, Box<Expr>),
+    ApproxEqual(Box<Expr>, Box<Expr>, Box<Expr>),
+    FormatFixed(Box<Expr>, Box<Expr>, Box<Expr>),
+    PctChange(Box<Expr>, Box<Expr>),
+    Lookup(Box<Expr>, Box<Expr>),
+    PadNumber(Box<Expr>, Box<Expr>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -67,3 +116,204 @@ pub enum Statement {
 pub struct Program {
     pub statement: Statement,
 }
+
+impl Expr {
+    /// Returns the infix operator and operands if this is an operator expression.
+    fn binary_parts(&self) -> Option<(&Expr, &'static str, &Expr)> {
+        let (l, op, r) = match self {
+            Expr::Add(l, r) => (l, "+", r),
+            Expr::Subtract(l, r) => (l, "-", r),
+            Expr::Multiply(l, r) => (l, "*", r),
+            Expr::Divide(l, r) => (l, "/", r),
+            Expr::Power(l, r) => (l, "^", r),
+            Expr::Modulo(l, r) => (l, "mod", r),
+            Expr::Div(l, r) => (l, "div", r),
+            Expr::Equal(l, r) => (l, "=", r),
+            Expr::NotEqual(l, r) => (l, "<>", r),
+            Expr::LessThan(l, r) => (l, "<", r),
+            Expr::GreaterThan(l, r) => (l, ">", r),
+            Expr::LessThanOrEqual(l, r) => (l, "<=", r),
+            Expr::GreaterThanOrEqual(l, r) => (l, ">=", r),
+            Expr::And(l, r) => (l, "and", r),
+            Expr::Or(l, r) => (l, "or", r),
+            _ => return None,
+        };
+        Some((l, op, r))
+    }
+
+    /// Returns the function name and arguments if this is a built-in or custom call.
+    fn call_parts(&self) -> Option<(&str, Vec<&Expr>)> {
+        let (name, args): (&str, Vec<&Expr>) = match self {
+            Expr::FunctionCall { name, args } => (name, args.iter().collect()),
+            Expr::Max(a, b) => ("max", vec![a, b]),
+            Expr::Min(a, b) => ("min", vec![a, b]),
+            Expr::Rnd(a, b) => ("rnd", vec![a, b]),
+            Expr::Ceil(a) => ("ceil", vec![a]),
+            Expr::Floor(a) => ("floor", vec![a]),
+            Expr::MRound(a, b) => ("mround", vec![a, b]),
+            Expr::FloorTo(a, b) => ("floor_to", vec![a, b]),
+            Expr::CeilTo(a, b) => ("ceil_to", vec![a, b]),
+            Expr::Exp(a) => ("exp", vec![a]),
+            Expr::Log(a, b) => ("log", vec![a, b]),
+            Expr::Year(a) => ("year", vec![a]),
+            Expr::Month(a) => ("month", vec![a]),
+            Expr::Day(a) => ("day", vec![a]),
+            Expr::Substr(a, b, c) => (
+                "substr",
+                [a, b].into_iter().chain(c).map(|e| e.as_ref()).collect(),
+            ),
+            Expr::AddDays(a, b) => ("add_days", vec![a, b]),
+            Expr::GetDiffDays(a, b) => ("get_diff_days", vec![a, b]),
+            Expr::GetDiffHours(a, b) => ("get_diff_hours", vec![a, b]),
+            Expr::GetDiffMinutes(a, b) => ("get_diff_minutes", vec![a, b]),
+            Expr::PaddedString(a, b) => ("padded_string", vec![a, b]),
+            Expr::FormatNumber(a, b, c) => (
+                "format_number",
+                [a, b].into_iter().chain(c).map(|e| e.as_ref()).collect(),
+            ),
+            Expr::GetDiffMonths(a, b) => ("get_diff_months", vec![a, b]),
+            Expr::GetOutputFrom(a) => ("get_output_from", vec![a]),
+            Expr::BusinessDaysBetween(a, b, c) => (
+                "business_days_between",
+                [a, b].into_iter().chain(c).map(|e| e.as_ref()).collect(),
+            ),
+            Expr::Iif(a, b, c) => ("iif", vec![a, b, c]),
+            Expr::Date(a) => ("date", vec![a]),
+            Expr::Sum(args) => ("sum", args.iter().collect()),
+            Expr::Avg(args) => ("avg", args.iter().collect()),
+            Expr::Count(args) => ("count", args.iter().collect()),
+            Expr::Product(args) => ("product", args.iter().collect()),
+            Expr::Median(args) => ("median", args.iter().collect()),
+            Expr::Percentile(a, b) => ("percentile", vec![a, b]),
+            Expr::Variance(args) => ("variance", args.iter().collect()),
+            Expr::VarianceP(args) => ("variance_p", args.iter().collect()),
+            Expr::Stddev(args) => ("stddev", args.iter().collect()),
+            Expr::StddevP(args) => ("stddev_p", args.iter().collect()),
+            Expr::Pmt(args) => ("pmt", args.iter().collect()),
+            Expr::ModE(a, b) => ("mod_e", vec![a, b]),
+            Expr::Gcd(a, b) => ("gcd", vec![a, b]),
+            Expr::BitAnd(a, b) => ("bit_and", vec![a, b]),
+            Expr::BitOr(a, b) => ("bit_or", vec![a, b]),
+            Expr::BitXor(a, b) => ("bit_xor", vec![a, b]),
+            Expr::Lcm(a, b) => ("lcm", vec![a, b]),
+            Expr::Factorial(a) => ("factorial", vec![a]),
+            Expr::IsEven(a) => ("is_even", vec![a]),
+            Expr::IsOdd(a) => ("is_odd", vec![a]),
+            Expr::Combinations(a, b) => ("combinations", vec![a, b]),
+            Expr::Npv(rate, flows) => {
+                ("npv", std::iter::once(rate.as_ref()).chain(flows).collect())
+            }
+            Expr::Irr(args) => ("irr", args.iter().collect()),
+            Expr::Split(a, b) => ("split", vec![a, b]),
+            Expr::Join(a, b) => ("join", vec![a, b]),
+            Expr::Fv(a, b, c) => ("fv", vec![a, b, c]),
+            Expr::Cagr(a, b, c) => ("cagr", vec![a, b, c]),
+            Expr::ApproxEqual(a, b, c) => ("approx_equal", vec![a, b, c]),
+            Expr::FormatFixed(a, b, c) => ("format_fixed", vec![a, b, c]),
+            Expr::PctChange(a, b) => ("pct_change", vec![a, b]),
+            Expr::Lookup(a, b) => ("lookup", vec![a, b]),
+            Expr::PadNumber(a, b) => ("pad_number", vec![a, b]),
+            _ => return None,
+        };
+        Some((name, args))
+    }
+
+    /// Returns the direct subexpressions, left to right.
+    fn children(&self) -> Vec<&Expr> {
+        if let Some((l, _, r)) = self.binary_parts() {
+            return vec![l, r];
+        }
+        match self {
+            Expr::Not(e) | Expr::UnaryMinus(e) => vec![e],
+            _ => self.call_parts().map(|(_, args)| args).unwrap_or_default(),
+        }
+    }
+
+    /// Number of levels in the expression tree: `x` has depth 1 and `x + 1` depth 2.
+    ///
+    /// Walks the tree without recursion, so it is safe on trees too deep to evaluate.
+    pub(crate) fn depth(&self) -> usize {
+        let mut depth = 0;
+        let mut pending = vec![(self, 1)];
+        while let Some((expr, level)) = pending.pop() {
+            depth = depth.max(level);
+            pending.extend(expr.children().into_iter().map(|child| (child, level + 1)));
+        }
+        depth
+    }
+}
+
+/// An operand of an operator, parenthesized unless it is a single term.
+struct Operand<'a>(&'a Expr);
+
+impl fmt::Display for Operand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Expr::Number(n) if n.is_sign_negative() => write!(f, "({})", self.0),
+            expr if expr.binary_parts().is_some() => write!(f, "({})", expr),
+            expr => write!(f, "{}", expr),
+        }
+    }
+}
+
+fn write_call(f: &mut fmt::Formatter<'_>, name: &str, args: &[&Expr]) -> fmt::Result {
+    write!(f, "{}(", name)?;
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", arg)?;
+    }
+    write!(f, ")")
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((l, op, r)) = self.binary_parts() {
+            return write!(f, "{} {} {}", Operand(l), op, Operand(r));
+        }
+        if let Some((name, args)) = self.call_parts() {
+            return write_call(f, name, &args);
+        }
+
+        match self {
+            Expr::Number(n) => write!(f, "{}", n),
+            Expr::String(s) => write!(f, "'{}'", s.replace('\\', "\\\\").replace('\'', "\\'")),
+            Expr::Bool(b) => write!(f, "{}", b),
+            Expr::Identifier(name) => write!(f, "{}", name),
+            Expr::Not(e) => write!(f, "!{}", Operand(e)),
+            Expr::UnaryMinus(e) => write!(f, "-{}", Operand(e)),
+            _ => unreachable!("operators and calls are written above"),
+        }
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Return(expr) => write!(f, "return {}", expr),
+            Statement::Error(expr) => write!(f, "error({})", expr),
+            Statement::If {
+                condition,
+                then_block,
+                else_ifs,
+                else_block,
+            } => {
+                write!(f, "if ({}) then {}", condition, then_block)?;
+                for (condition, block) in else_ifs {
+                    write!(f, " else if ({}) then {}", condition, block)?;
+                }
+                if let Some(block) = else_block {
+                    write!(f, " else {}", block)?;
+                }
+                write!(f, " end")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.statement)
+    }
+}
@@ -1,17 +1,31 @@
 use super::ast::{Expr, Program, Statement};
-use crate::cache::{FormulaResultCache, FunctionCache, FunctionResultCache, VariableCache};
+use crate::cache::{
+    AsyncFunctionCache, FormulaResultCache, FunctionCache, FunctionResultCache, VariableCache,
+};
+use crate::cancellation::CancellationToken;
 use crate::error::{CalculatorError, Result};
-use crate::function::build_function_id;
-use crate::value::Value;
-use chrono::{Datelike, NaiveDateTime};
+use crate::function::{build_function_id, AsyncRunner};
+use crate::options::EngineOptions;
+use crate::value::{Value, DATE_FORMAT};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Weekday};
+use std::cell::Cell;
+use std::collections::HashSet;
 
 pub struct Evaluator {
     variable_cache: VariableCache,
     formula_result_cache: FormulaResultCache,
     function_cache: FunctionCache,
     function_result_cache: FunctionResultCache,
+    async_function_cache: AsyncFunctionCache,
+    async_runner: Option<AsyncRunner>,
+    options: EngineOptions,
+    steps: Cell<usize>,
+    cancellation: Option<CancellationToken>,
 }
 
+/// How many expression nodes are evaluated between checks of the cancellation token.
+const CANCELLATION_CHECK_INTERVAL: usize = 1024;
+
 impl Evaluator {
     pub fn new(
         variable_cache: VariableCache,
@@ -24,13 +38,167 @@ impl Evaluator {
             formula_result_cache,
             function_cache,
             function_result_cache,
+            async_function_cache: AsyncFunctionCache::new(),
+            async_runner: None,
+            options: EngineOptions::default(),
+            steps: Cell::new(0),
+            cancellation: None,
         }
     }
 
+    /// Makes async functions callable, driving them with `runner` when one is given.
+    pub(crate) fn with_async_functions(
+        mut self,
+        async_function_cache: AsyncFunctionCache,
+        async_runner: Option<AsyncRunner>,
+    ) -> Self {
+        self.async_function_cache = async_function_cache;
+        self.async_runner = async_runner;
+        self
+    }
+
+    pub fn with_options(mut self, options: EngineOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Makes long evaluations fail with [`CalculatorError::Cancelled`] once `token`
+    /// is cancelled.
+    pub(crate) fn with_cancellation(mut self, token: Option<CancellationToken>) -> Self {
+        self.cancellation = token;
+        self
+    }
+
     pub fn evaluate(&self, program: &Program) -> Result<Value> {
+        self.steps.set(0);
         self.evaluate_statement(&program.statement)
     }
 
+    /// Counts one expression node against [`EngineOptions::max_eval_steps`], checking
+    /// for cancellation every [`CANCELLATION_CHECK_INTERVAL`] nodes.
+    fn count_step(&self) -> Result<()> {
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+        if let Some(limit) = self.options.max_eval_steps {
+            if steps > limit {
+                return Err(CalculatorError::LimitExceeded(format!(
+                    "evaluation exceeded the budget of {} steps",
+                    limit
+                )));
+            }
+        }
+        if steps.is_multiple_of(CANCELLATION_CHECK_INTERVAL)
+            && self.cancellation.as_ref().is_some_and(|t| t.is_cancelled())
+        {
+            return Err(CalculatorError::Cancelled);
+        }
+        Ok(())
+    }
+
+    /// Evaluates aggregate arguments: a single array argument supplies the elements,
+    /// otherwise each argument is one element.
+    fn aggregate_elements(&self, args: &[Expr]) -> Result<Vec<Value>> {
+        if let [arg] = args {
+            return match self.evaluate_expr(arg)? {
+                Value::Array(items) => Ok(items),
+                value => Ok(vec![value]),
+            };
+        }
+
+        self.evaluate_args(args)
+    }
+
+    fn evaluate_round_to(
+        &self,
+        value: &Expr,
+        multiple: &Expr,
+        round: fn(f64) -> f64,
+        snap_halves: bool,
+        function: &str,
+    ) -> Result<Value> {
+        match (self.evaluate_expr(value)?, self.evaluate_expr(multiple)?) {
+            (Value::Number(value), Value::Number(multiple)) => Ok(Value::Number(
+                round_to_multiple(value, multiple, round, snap_halves, function)?,
+            )),
+            _ => Err(CalculatorError::TypeError(format!(
+                "{} requires numbers",
+                function
+            ))),
+        }
+    }
+
+    fn evaluate_integer_pair(
+        &self,
+        left: &Expr,
+        right: &Expr,
+        function: &str,
+    ) -> Result<(u64, u64)> {
+        match (self.evaluate_expr(left)?, self.evaluate_expr(right)?) {
+            (Value::Number(a), Value::Number(b)) => Ok((
+                non_negative_integer(a, function)?,
+                non_negative_integer(b, function)?,
+            )),
+            _ => Err(CalculatorError::TypeError(format!(
+                "{} requires numbers",
+                function
+            ))),
+        }
+    }
+
+    /// Tests whether a number is even after truncating it toward zero, so `4.9` is even.
+    fn evaluate_is_even(&self, expr: &Expr, function: &str) -> Result<bool> {
+        match self.evaluate_expr(expr)? {
+            Value::Number(n) if n.is_finite() => Ok(n.trunc() % 2.0 == 0.0),
+            Value::Number(n) => Err(CalculatorError::InvalidArgument(format!(
+                "{} requires a finite number, got {}",
+                function, n
+            ))),
+            _ => Err(CalculatorError::TypeError(format!(
+                "{} requires number",
+                function
+            ))),
+        }
+    }
+
+    /// Applies a bitwise operation to two integer arguments.
+    fn evaluate_bitwise(
+        &self,
+        left: &Expr,
+        right: &Expr,
+        function: &str,
+        op: fn(i64, i64) -> i64,
+    ) -> Result<Value> {
+        match (self.evaluate_expr(left)?, self.evaluate_expr(right)?) {
+            (Value::Number(a), Value::Number(b)) => Ok(Value::Number(op(
+                integer(a, function)?,
+                integer(b, function)?,
+            ) as f64)),
+            _ => Err(CalculatorError::TypeError(format!(
+                "{} requires numbers",
+                function
+            ))),
+        }
+    }
+
+    /// Wraps a numeric result, failing under `strict_math` when it is `NaN` or infinite.
+    fn checked_number(&self, operation: &str, operands: &[f64], result: f64) -> Result<Value> {
+        if self.options.strict_math && !result.is_finite() {
+            let operands: Vec<String> = operands.iter().map(|n| format!("{:?}", n)).collect();
+            return Err(CalculatorError::EvalError(format!(
+                "{} of ({}) produced {}",
+                operation,
+                operands.join(", "),
+                result
+            )));
+        }
+        Ok(Value::Number(result))
+    }
+
+    /// Evaluates each argument in order, stopping at the first error.
+    fn evaluate_args(&self, args: &[Expr]) -> Result<Vec<Value>> {
+        args.iter().map(|arg| self.evaluate_expr(arg)).collect()
+    }
+
     fn evaluate_statement(&self, stmt: &Statement) -> Result<Value> {
         match stmt {
             Statement::Return(expr) => self.evaluate_expr(expr),
@@ -74,21 +242,53 @@ impl Evaluator {
                     Value::String(s) => format!("Error function called with message: {}", s),
                     Value::Number(n) => format!("Error function called with code: {}", n),
                     Value::Bool(b) => format!("Error function called with value: {}", b),
+                    Value::Date(_) | Value::Array(_) | Value::Null => {
+                        format!("Error function called with value: {}", val)
+                    }
                 };
                 Err(CalculatorError::ErrorCall(msg))
             }
         }
     }
 
-    fn evaluate_expr(&self, expr: &Expr) -> Result<Value> {
+    /// Evaluates `expr`, enforcing `strict_math` on every number it computes.
+    ///
+    /// Operators report their own operands through [`Evaluator::checked_number`];
+    /// any other built-in producing `NaN` or an infinity is reported here by its
+    /// formula text. Literals, variables, formula outputs, and custom functions only
+    /// pass values along, so they are not checked.
+    pub fn evaluate_expr(&self, expr: &Expr) -> Result<Value> {
+        let value = self.evaluate_node(expr)?;
+        match value {
+            Value::Number(n) if self.options.strict_math && !n.is_finite() => match expr {
+                Expr::Number(_)
+                | Expr::Identifier(_)
+                | Expr::GetOutputFrom(_)
+                | Expr::Lookup(..)
+                | Expr::Iif(..)
+                | Expr::FunctionCall { .. } => Ok(value),
+                _ => Err(CalculatorError::EvalError(format!(
+                    "{} produced {}",
+                    expr, n
+                ))),
+            },
+            value => Ok(value),
+        }
+    }
+
+    fn evaluate_node(&self, expr: &Expr) -> Result<Value> {
+        self.count_step()?;
         match expr {
             Expr::Number(n) => Ok(Value::Number(*n)),
             Expr::String(s) => Ok(Value::String(s.clone())),
             Expr::Bool(b) => Ok(Value::Bool(*b)),
-            Expr::Identifier(name) => self
-                .variable_cache
-                .get(name)
-                .ok_or_else(|| CalculatorError::VariableNotFound(name.clone())),
+            Expr::Identifier(name) => match self.variable_cache.get(name) {
+                Some(value) => Ok(value),
+                None if self.options.unknown_identifier_as_string => {
+                    Ok(Value::String(name.clone()))
+                }
+                None => Err(CalculatorError::VariableNotFound(name.clone())),
+            },
 
             // Arithmetic
             Expr::Add(left, right) => {
@@ -96,7 +296,17 @@ impl Evaluator {
                 let r = self.evaluate_expr(right)?;
 
                 match (&l, &r) {
-                    (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a + b)),
+                    (Value::Number(a), Value::Number(b)) => {
+                        self.checked_number("Addition", &[*a, *b], a + b)
+                    }
+                    (Value::Date(d), Value::Number(days))
+                    | (Value::Number(days), Value::Date(d)) => {
+                        Ok(Value::Date(add_days(*d, *days)?))
+                    }
+                    // Never concatenate an absent value as the text "null"
+                    (Value::Null, _) | (_, Value::Null) => Err(CalculatorError::TypeError(
+                        "Addition does not accept null".to_string(),
+                    )),
                     _ => Ok(Value::String(format!("{}{}", l.get(), r.get()))),
                 }
             }
@@ -105,9 +315,15 @@ impl Evaluator {
                 let r = self.evaluate_expr(right)?;
 
                 match (l, r) {
-                    (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a - b)),
+                    (Value::Number(a), Value::Number(b)) => {
+                        self.checked_number("Subtraction", &[a, b], a - b)
+                    }
+                    (Value::Date(d), Value::Number(days)) => Ok(Value::Date(add_days(d, -days)?)),
+                    (Value::Date(a), Value::Date(b)) => {
+                        Ok(Value::Number((a - b).num_days() as f64))
+                    }
                     _ => Err(CalculatorError::TypeError(
-                        "Subtraction requires numbers".to_string(),
+                        "Subtraction requires numbers or dates".to_string(),
                     )),
                 }
             }
@@ -116,7 +332,9 @@ impl Evaluator {
                 let r = self.evaluate_expr(right)?;
 
                 match (l, r) {
-                    (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a * b)),
+                    (Value::Number(a), Value::Number(b)) => {
+                        self.checked_number("Multiplication", &[a, b], a * b)
+                    }
                     _ => Err(CalculatorError::TypeError(
                         "Multiplication requires numbers".to_string(),
                     )),
@@ -131,7 +349,7 @@ impl Evaluator {
                         if b == 0.0 {
                             Err(CalculatorError::DivisionByZero)
                         } else {
-                            Ok(Value::Number(a / b))
+                            self.checked_number("Division", &[a, b], a / b)
                         }
                     }
                     _ => Err(CalculatorError::TypeError(
@@ -144,7 +362,9 @@ impl Evaluator {
                 let r = self.evaluate_expr(right)?;
 
                 match (l, r) {
-                    (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a.powf(b))),
+                    (Value::Number(a), Value::Number(b)) => {
+                        self.checked_number("Power", &[a, b], a.powf(b))
+                    }
                     _ => Err(CalculatorError::TypeError(
                         "Power requires numbers".to_string(),
                     )),
@@ -155,67 +375,60 @@ impl Evaluator {
                 let r = self.evaluate_expr(right)?;
 
                 match (l, r) {
+                    (Value::Number(_), Value::Number(0.0)) => Err(CalculatorError::DivisionByZero),
                     (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a % b)),
                     _ => Err(CalculatorError::TypeError(
                         "Modulo requires numbers".to_string(),
                     )),
                 }
             }
+            Expr::Div(left, right) => {
+                let l = self.evaluate_expr(left)?;
+                let r = self.evaluate_expr(right)?;
+
+                match (l, r) {
+                    (Value::Number(_), Value::Number(0.0)) => Err(CalculatorError::DivisionByZero),
+                    (Value::Number(a), Value::Number(b)) => Ok(Value::Number((a / b).floor())),
+                    _ => Err(CalculatorError::TypeError(
+                        "Div requires numbers".to_string(),
+                    )),
+                }
+            }
 
             // Comparison
             Expr::Equal(left, right) => {
                 let l = self.evaluate_expr(left)?;
                 let r = self.evaluate_expr(right)?;
-                Ok(Value::Bool(l == r))
+                Ok(Value::Bool(values_equal(&l, &r)))
             }
             Expr::NotEqual(left, right) => {
                 let l = self.evaluate_expr(left)?;
                 let r = self.evaluate_expr(right)?;
-                Ok(Value::Bool(l != r))
+                Ok(Value::Bool(!values_equal(&l, &r)))
             }
             Expr::LessThan(left, right) => {
                 let l = self.evaluate_expr(left)?;
                 let r = self.evaluate_expr(right)?;
-
-                match l.partial_cmp(&r) {
-                    Some(ord) => Ok(Value::Bool(ord == std::cmp::Ordering::Less)),
-                    None => Err(CalculatorError::TypeError(
-                        "Cannot compare values of different types".to_string(),
-                    )),
-                }
+                let ord = compare_values(&l, &r)?;
+                Ok(Value::Bool(ord == std::cmp::Ordering::Less))
             }
             Expr::GreaterThan(left, right) => {
                 let l = self.evaluate_expr(left)?;
                 let r = self.evaluate_expr(right)?;
-
-                match l.partial_cmp(&r) {
-                    Some(ord) => Ok(Value::Bool(ord == std::cmp::Ordering::Greater)),
-                    None => Err(CalculatorError::TypeError(
-                        "Cannot compare values of different types".to_string(),
-                    )),
-                }
+                let ord = compare_values(&l, &r)?;
+                Ok(Value::Bool(ord == std::cmp::Ordering::Greater))
             }
             Expr::LessThanOrEqual(left, right) => {
                 let l = self.evaluate_expr(left)?;
                 let r = self.evaluate_expr(right)?;
-
-                match l.partial_cmp(&r) {
-                    Some(ord) => Ok(Value::Bool(ord != std::cmp::Ordering::Greater)),
-                    None => Err(CalculatorError::TypeError(
-                        "Cannot compare values of different types".to_string(),
-                    )),
-                }
+                let ord = compare_values(&l, &r)?;
+                Ok(Value::Bool(ord != std::cmp::Ordering::Greater))
             }
             Expr::GreaterThanOrEqual(left, right) => {
                 let l = self.evaluate_expr(left)?;
                 let r = self.evaluate_expr(right)?;
-
-                match l.partial_cmp(&r) {
-                    Some(ord) => Ok(Value::Bool(ord != std::cmp::Ordering::Less)),
-                    None => Err(CalculatorError::TypeError(
-                        "Cannot compare values of different types".to_string(),
-                    )),
-                }
+                let ord = compare_values(&l, &r)?;
+                Ok(Value::Bool(ord != std::cmp::Ordering::Less))
             }
 
             // Logical
@@ -263,8 +476,16 @@ impl Evaluator {
                     )),
                 }
             }
+            _ => self.evaluate_call(expr),
+        }
+    }
 
-            // Built-in functions
+    /// Evaluates a built-in or custom function call.
+    ///
+    /// Kept out of [`Evaluator::evaluate_node`], which operator chains recurse through
+    /// once per operator, so that its stack frame stays small.
+    fn evaluate_call(&self, expr: &Expr) -> Result<Value> {
+        match expr {
             Expr::Max(left, right) => {
                 let l = self.evaluate_expr(left)?;
                 let r = self.evaluate_expr(right)?;
@@ -276,6 +497,63 @@ impl Evaluator {
                     )),
                 }
             }
+            Expr::ModE(left, right) => {
+                let l = self.evaluate_expr(left)?;
+                let r = self.evaluate_expr(right)?;
+
+                match (l, r) {
+                    (Value::Number(_), Value::Number(0.0)) => Err(CalculatorError::DivisionByZero),
+                    (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a.rem_euclid(b))),
+                    _ => Err(CalculatorError::TypeError(
+                        "ModE requires numbers".to_string(),
+                    )),
+                }
+            }
+            Expr::Gcd(left, right) => {
+                let (a, b) = self.evaluate_integer_pair(left, right, "Gcd")?;
+                Ok(Value::Number(gcd(a, b) as f64))
+            }
+            Expr::BitAnd(left, right) => self.evaluate_bitwise(left, right, "BitAnd", |a, b| a & b),
+            Expr::BitOr(left, right) => self.evaluate_bitwise(left, right, "BitOr", |a, b| a | b),
+            Expr::BitXor(left, right) => self.evaluate_bitwise(left, right, "BitXor", |a, b| a ^ b),
+            Expr::Lcm(left, right) => {
+                let (a, b) = self.evaluate_integer_pair(left, right, "Lcm")?;
+                if a == 0 || b == 0 {
+                    return Ok(Value::Number(0.0));
+                }
+                // Dividing first keeps the intermediate no larger than the result
+                let lcm = (a / gcd(a, b)) as u128 * b as u128;
+                // Compared before converting, since the conversion itself rounds
+                if lcm > 1u128 << 53 {
+                    return Err(CalculatorError::InvalidArgument(format!(
+                        "Lcm of {} and {} exceeds 2^53 and cannot be represented exactly",
+                        a, b
+                    )));
+                }
+                Ok(Value::Number(lcm as f64))
+            }
+            Expr::Factorial(expr) => match self.evaluate_expr(expr)? {
+                Value::Number(n) => {
+                    let n = non_negative_integer(n, "Factorial")?;
+                    // 171! exceeds f64::MAX
+                    if n > 170 {
+                        return Err(CalculatorError::InvalidArgument(format!(
+                            "Factorial is limited to 170, got {}",
+                            n
+                        )));
+                    }
+                    Ok(Value::Number((1..=n).map(|i| i as f64).product()))
+                }
+                _ => Err(CalculatorError::TypeError(
+                    "Factorial requires number".to_string(),
+                )),
+            },
+            Expr::IsEven(expr) => Ok(Value::Bool(self.evaluate_is_even(expr, "IsEven")?)),
+            Expr::IsOdd(expr) => Ok(Value::Bool(!self.evaluate_is_even(expr, "IsOdd")?)),
+            Expr::Combinations(left, right) => {
+                let (n, k) = self.evaluate_integer_pair(left, right, "Combinations")?;
+                Ok(Value::Number(combinations(n, k)?))
+            }
             Expr::Min(left, right) => {
                 let l = self.evaluate_expr(left)?;
                 let r = self.evaluate_expr(right)?;
@@ -293,14 +571,22 @@ impl Evaluator {
 
                 match (l, r) {
                     (Value::Number(value), Value::Number(decimals)) => {
-                        let factor = 10_f64.powi(decimals as i32);
-                        Ok(Value::Number((value * factor).round() / factor))
+                        Ok(Value::Number(round_to_decimals(value, decimals)?))
                     }
                     _ => Err(CalculatorError::TypeError(
                         "Rnd requires numbers".to_string(),
                     )),
                 }
             }
+            Expr::MRound(value, multiple) => {
+                self.evaluate_round_to(value, multiple, f64::round, true, "MRound")
+            }
+            Expr::FloorTo(value, multiple) => {
+                self.evaluate_round_to(value, multiple, f64::floor, false, "FloorTo")
+            }
+            Expr::CeilTo(value, multiple) => {
+                self.evaluate_round_to(value, multiple, f64::ceil, false, "CeilTo")
+            }
             Expr::Ceil(expr) => {
                 let val = self.evaluate_expr(expr)?;
 
@@ -325,65 +611,116 @@ impl Evaluator {
                 let val = self.evaluate_expr(expr)?;
 
                 match val {
-                    Value::Number(n) => Ok(Value::Number(n.exp())),
+                    Value::Number(n) => self.checked_number("Exp", &[n], n.exp()),
                     _ => Err(CalculatorError::TypeError(
                         "Exp requires number".to_string(),
                     )),
                 }
             }
-            Expr::Year(expr) => {
-                let val = self.evaluate_expr(expr)?;
+            Expr::Log(value_expr, base_expr) => {
+                let value = self.evaluate_expr(value_expr)?;
+                let base = self.evaluate_expr(base_expr)?;
 
-                match val {
-                    Value::String(s) => {
-                        let date = parse_date(&s)?;
-                        Ok(Value::Number(date.year() as f64))
+                match (value, base) {
+                    (Value::Number(value), Value::Number(base)) => {
+                        if value <= 0.0 || value.is_nan() {
+                            return Err(CalculatorError::InvalidArgument(format!(
+                                "Log value must be greater than 0, got {}",
+                                value
+                            )));
+                        }
+                        if base <= 0.0 || base.is_nan() {
+                            return Err(CalculatorError::InvalidArgument(format!(
+                                "Log base must be greater than 0, got {}",
+                                base
+                            )));
+                        }
+                        if base == 1.0 {
+                            return Err(CalculatorError::InvalidArgument(
+                                "Log base must not be 1".to_string(),
+                            ));
+                        }
+                        Ok(Value::Number(value.ln() / base.ln()))
                     }
                     _ => Err(CalculatorError::TypeError(
-                        "Year requires string date".to_string(),
+                        "Log requires numbers".to_string(),
                     )),
                 }
             }
+            Expr::Year(expr) => {
+                let val = self.evaluate_expr(expr)?;
+                let date = to_date(&val, "Year requires a date")?;
+                Ok(Value::Number(date.year() as f64))
+            }
             Expr::Month(expr) => {
                 let val = self.evaluate_expr(expr)?;
+                let date = to_date(&val, "Month requires a date")?;
+                Ok(Value::Number(date.month() as f64))
+            }
+            Expr::Day(expr) => {
+                let val = self.evaluate_expr(expr)?;
+                let date = to_date(&val, "Day requires a date")?;
+                Ok(Value::Number(date.day() as f64))
+            }
+            Expr::Substr(str_expr, start_expr, len_expr) => {
+                let s = date_to_string(self.evaluate_expr(str_expr)?);
+                let start = self.evaluate_expr(start_expr)?;
+                let len = match len_expr {
+                    Some(expr) => Some(self.evaluate_expr(expr)?),
+                    None => None,
+                };
 
-                match val {
-                    Value::String(s) => {
-                        let date = parse_date(&s)?;
-                        Ok(Value::Number(date.month() as f64))
+                match (s, start, len) {
+                    (Value::String(s), Value::Number(start), None) => {
+                        Ok(Value::String(substring(&s, start, None)?))
+                    }
+                    (Value::String(s), Value::Number(start), Some(Value::Number(len))) => {
+                        Ok(Value::String(substring(&s, start, Some(len))?))
                     }
                     _ => Err(CalculatorError::TypeError(
-                        "Month requires string date".to_string(),
+                        "Substr requires (string, number, number?)".to_string(),
                     )),
                 }
             }
-            Expr::Day(expr) => {
-                let val = self.evaluate_expr(expr)?;
+            Expr::Split(str_expr, delimiter_expr) => {
+                let s = self.evaluate_expr(str_expr)?;
+                let delimiter = self.evaluate_expr(delimiter_expr)?;
 
-                match val {
-                    Value::String(s) => {
-                        let date = parse_date(&s)?;
-                        Ok(Value::Number(date.day() as f64))
+                match (s, delimiter) {
+                    (Value::String(_), Value::String(d)) if d.is_empty() => {
+                        Err(CalculatorError::InvalidArgument(
+                            "Split delimiter must not be empty".to_string(),
+                        ))
                     }
+                    (Value::String(s), Value::String(d)) => Ok(Value::Array(
+                        s.split(d.as_str())
+                            .map(|piece| Value::String(piece.to_string()))
+                            .collect(),
+                    )),
                     _ => Err(CalculatorError::TypeError(
-                        "Day requires string date".to_string(),
+                        "Split requires (string, string)".to_string(),
                     )),
                 }
             }
-            Expr::Substr(str_expr, start_expr, len_expr) => {
-                let s = self.evaluate_expr(str_expr)?;
-                let start = self.evaluate_expr(start_expr)?;
-                let len = self.evaluate_expr(len_expr)?;
+            Expr::Join(array_expr, separator_expr) => {
+                let array = self.evaluate_expr(array_expr)?;
+                let separator = self.evaluate_expr(separator_expr)?;
 
-                match (s, start, len) {
-                    (Value::String(s), Value::Number(start), Value::Number(len)) => {
-                        let start = start as usize;
-                        let len = len as usize;
-                        let result = s.chars().skip(start).take(len).collect::<String>();
-                        Ok(Value::String(result))
+                match (array, separator) {
+                    (Value::Array(items), Value::String(_)) if items.iter().any(Value::is_null) => {
+                        Err(CalculatorError::TypeError(
+                            "Join does not accept null elements".to_string(),
+                        ))
                     }
+                    (Value::Array(items), Value::String(separator)) => Ok(Value::String(
+                        items
+                            .iter()
+                            .map(Value::get)
+                            .collect::<Vec<_>>()
+                            .join(&separator),
+                    )),
                     _ => Err(CalculatorError::TypeError(
-                        "Substr requires (string, number, number)".to_string(),
+                        "Join requires (array, string)".to_string(),
                     )),
                 }
             }
@@ -391,16 +728,12 @@ impl Evaluator {
                 let date_val = self.evaluate_expr(date_expr)?;
                 let days_val = self.evaluate_expr(days_expr)?;
 
-                match (date_val, days_val) {
-                    (Value::String(s), Value::Number(days)) => {
-                        let date = parse_date(&s)?;
-                        let new_date = date + chrono::Duration::days(days as i64);
-                        Ok(Value::String(
-                            new_date.format("%Y-%m-%dT%H:%M:%S").to_string(),
-                        ))
-                    }
+                let date = to_date(&date_val, "AddDays requires (date, number)")?;
+
+                match days_val {
+                    Value::Number(days) => Ok(Value::Date(add_days(date, days)?)),
                     _ => Err(CalculatorError::TypeError(
-                        "AddDays requires (string date, number)".to_string(),
+                        "AddDays requires (date, number)".to_string(),
                     )),
                 }
             }
@@ -408,20 +741,31 @@ impl Evaluator {
                 let date1_val = self.evaluate_expr(date1_expr)?;
                 let date2_val = self.evaluate_expr(date2_expr)?;
 
-                match (date1_val, date2_val) {
-                    (Value::String(s1), Value::String(s2)) => {
-                        let date1 = parse_date(&s1)?;
-                        let date2 = parse_date(&s2)?;
-                        let diff = (date1 - date2).num_days();
-                        Ok(Value::Number(diff as f64))
-                    }
-                    _ => Err(CalculatorError::TypeError(
-                        "GetDiffDays requires two string dates".to_string(),
-                    )),
-                }
+                let date1 = to_date(&date1_val, "GetDiffDays requires two dates")?;
+                let date2 = to_date(&date2_val, "GetDiffDays requires two dates")?;
+                let diff = (date1 - date2).num_days();
+                Ok(Value::Number(diff as f64))
+            }
+            Expr::GetDiffHours(date1_expr, date2_expr) => {
+                let date1_val = self.evaluate_expr(date1_expr)?;
+                let date2_val = self.evaluate_expr(date2_expr)?;
+
+                let date1 = to_date(&date1_val, "GetDiffHours requires two dates")?;
+                let date2 = to_date(&date2_val, "GetDiffHours requires two dates")?;
+                let diff = (date1 - date2).num_hours();
+                Ok(Value::Number(diff as f64))
+            }
+            Expr::GetDiffMinutes(date1_expr, date2_expr) => {
+                let date1_val = self.evaluate_expr(date1_expr)?;
+                let date2_val = self.evaluate_expr(date2_expr)?;
+
+                let date1 = to_date(&date1_val, "GetDiffMinutes requires two dates")?;
+                let date2 = to_date(&date2_val, "GetDiffMinutes requires two dates")?;
+                let diff = (date1 - date2).num_minutes();
+                Ok(Value::Number(diff as f64))
             }
             Expr::PaddedString(str_expr, width_expr) => {
-                let s = self.evaluate_expr(str_expr)?;
+                let s = date_to_string(self.evaluate_expr(str_expr)?);
                 let width = self.evaluate_expr(width_expr)?;
 
                 match (s, width) {
@@ -435,27 +779,134 @@ impl Evaluator {
                     )),
                 }
             }
-            Expr::GetDiffMonths(date1_expr, date2_expr) => {
-                let date1_val = self.evaluate_expr(date1_expr)?;
-                let date2_val = self.evaluate_expr(date2_expr)?;
+            Expr::FormatNumber(n_expr, decimals_expr, separator_expr) => {
+                let n = self.evaluate_expr(n_expr)?;
+                let decimals = self.evaluate_expr(decimals_expr)?;
+                let separator = match separator_expr {
+                    Some(expr) => Some(self.evaluate_expr(expr)?),
+                    None => None,
+                };
+
+                match (n, decimals, separator) {
+                    (Value::Number(n), Value::Number(decimals), None) => {
+                        Ok(Value::String(format_number(n, decimals, "")?))
+                    }
+                    (Value::Number(n), Value::Number(decimals), Some(Value::String(separator))) => {
+                        Ok(Value::String(format_number(n, decimals, &separator)?))
+                    }
+                    _ => Err(CalculatorError::TypeError(
+                        "FormatNumber requires (number, number, string?)".to_string(),
+                    )),
+                }
+            }
+            Expr::PctChange(old_expr, new_expr) => {
+                match (self.evaluate_expr(old_expr)?, self.evaluate_expr(new_expr)?) {
+                    (Value::Number(old), Value::Number(new)) => {
+                        if old == 0.0 {
+                            return Err(CalculatorError::DivisionByZero);
+                        }
+                        Ok(Value::Number((new - old) / old))
+                    }
+                    _ => Err(CalculatorError::TypeError(
+                        "PctChange requires (number, number)".to_string(),
+                    )),
+                }
+            }
+            Expr::Lookup(prefix_expr, key_expr) => {
+                match (
+                    self.evaluate_expr(prefix_expr)?,
+                    self.evaluate_expr(key_expr)?,
+                ) {
+                    (Value::String(prefix), Value::String(key)) => {
+                        let name = prefix + &key;
+                        self.variable_cache
+                            .get(&name)
+                            .ok_or(CalculatorError::VariableNotFound(name))
+                    }
+                    _ => Err(CalculatorError::TypeError(
+                        "Lookup requires (string, string)".to_string(),
+                    )),
+                }
+            }
+            Expr::PadNumber(value_expr, width_expr) => {
+                match (
+                    self.evaluate_expr(value_expr)?,
+                    self.evaluate_expr(width_expr)?,
+                ) {
+                    (Value::Number(value), Value::Number(width)) => {
+                        let value = non_negative_integer(value, "PadNumber")?;
+                        if width > MAX_FORMAT_WIDTH {
+                            return Err(CalculatorError::InvalidArgument(format!(
+                                "PadNumber width must be at most {}, got {}",
+                                MAX_FORMAT_WIDTH, width
+                            )));
+                        }
+                        let width = non_negative_integer(width, "PadNumber")? as usize;
+                        let padded = format!("{:0width$}", value);
+                        if padded.len() > width {
+                            return Err(CalculatorError::InvalidArgument(format!(
+                                "PadNumber: {} does not fit in {} digits",
+                                value, width
+                            )));
+                        }
+                        Ok(Value::String(padded))
+                    }
+                    _ => Err(CalculatorError::TypeError(
+                        "PadNumber requires (number, number)".to_string(),
+                    )),
+                }
+            }
+            Expr::FormatFixed(n_expr, width_expr, decimals_expr) => {
+                let n = self.evaluate_expr(n_expr)?;
+                let width = self.evaluate_expr(width_expr)?;
+                let decimals = self.evaluate_expr(decimals_expr)?;
 
-                match (date1_val, date2_val) {
-                    (Value::String(s1), Value::String(s2)) => {
-                        let date1 = parse_date(&s1)?;
-                        let date2 = parse_date(&s2)?;
-                        let months = (date1.year() - date2.year()) * 12
-                            + (date1.month() as i32 - date2.month() as i32);
-                        Ok(Value::Number(months.abs() as f64))
+                match (n, width, decimals) {
+                    (Value::Number(n), Value::Number(width), Value::Number(decimals)) => {
+                        if !(0.0..=MAX_FORMAT_WIDTH).contains(&width) || width.fract() != 0.0 {
+                            return Err(CalculatorError::InvalidArgument(format!(
+                                "FormatFixed width must be an integer from 0 to {}, got {}",
+                                MAX_FORMAT_WIDTH, width
+                            )));
+                        }
+                        let formatted = format_number(n, decimals, "")?;
+                        // Wider numbers overflow the field rather than being truncated
+                        Ok(Value::String(format!(
+                            "{:>width$}",
+                            formatted,
+                            width = width as usize
+                        )))
                     }
                     _ => Err(CalculatorError::TypeError(
-                        "GetDiffMonths requires two string dates".to_string(),
+                        "FormatFixed requires (number, number, number)".to_string(),
                     )),
                 }
             }
+            Expr::GetDiffMonths(date1_expr, date2_expr) => {
+                let date1_val = self.evaluate_expr(date1_expr)?;
+                let date2_val = self.evaluate_expr(date2_expr)?;
+
+                let date1 = to_date(&date1_val, "GetDiffMonths requires two dates")?;
+                let date2 = to_date(&date2_val, "GetDiffMonths requires two dates")?;
+                // Signed like GetDiffDays: positive when date1 is later than date2
+                let months = (date1.year() - date2.year()) * 12
+                    + (date1.month() as i32 - date2.month() as i32);
+                Ok(Value::Number(months as f64))
+            }
             Expr::GetOutputFrom(formula_expr) => {
                 let formula_name = self.evaluate_expr(formula_expr)?;
 
                 match formula_name {
+                    // A computed name is invisible to `Formula::new`'s dependency scan,
+                    // so the target may simply not have run yet
+                    Value::String(name) if !matches!(formula_expr.as_ref(), Expr::String(_)) => {
+                        self.formula_result_cache.get(&name).ok_or_else(|| {
+                            CalculatorError::DependencyError(format!(
+                                "no result for formula '{}'; names computed at runtime are not detected as dependencies, so declare them with Formula::new_explicit",
+                                name
+                            ))
+                        })
+                    }
                     Value::String(name) => self
                         .formula_result_cache
                         .get(&name)
@@ -466,40 +917,753 @@ impl Evaluator {
                 }
             }
 
+            Expr::BusinessDaysBetween(start_expr, end_expr, holidays_expr) => {
+                let start_val = self.evaluate_expr(start_expr)?;
+                let end_val = self.evaluate_expr(end_expr)?;
+                let holidays_val = match holidays_expr {
+                    Some(expr) => Some(self.evaluate_expr(expr)?),
+                    None => None,
+                };
+
+                let start = to_date(&start_val, "BusinessDaysBetween requires two dates")?.date();
+                let end = to_date(&end_val, "BusinessDaysBetween requires two dates")?.date();
+
+                let mut holidays: HashSet<NaiveDate> =
+                    self.options.holidays.iter().copied().collect();
+                match holidays_val {
+                    Some(Value::String(list)) => {
+                        for item in list.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                            holidays.insert(parse_date(item)?.date());
+                        }
+                    }
+                    Some(Value::Array(items)) => {
+                        for item in &items {
+                            holidays.insert(
+                                to_date(item, "BusinessDaysBetween holidays must be dates")?
+                                    .date(),
+                            );
+                        }
+                    }
+                    Some(_) => return Err(CalculatorError::TypeError(
+                        "BusinessDaysBetween holidays must be an array or comma-separated string of dates"
+                            .to_string(),
+                    )),
+                    None => {}
+                }
+
+                Ok(Value::Number(
+                    business_days_between(start, end, &holidays) as f64
+                ))
+            }
+
+            Expr::Iif(cond_expr, then_expr, else_expr) => {
+                let cond = self.evaluate_expr(cond_expr)?;
+
+                // Only the selected branch is evaluated
+                match cond {
+                    Value::Bool(true) => self.evaluate_expr(then_expr),
+                    Value::Bool(false) => self.evaluate_expr(else_expr),
+                    _ => Err(CalculatorError::TypeError(
+                        "Iif condition must be boolean".to_string(),
+                    )),
+                }
+            }
+
+            Expr::Date(expr) => {
+                let val = self.evaluate_expr(expr)?;
+                Ok(Value::Date(to_date(&val, "Date requires a date string")?))
+            }
+
+            // Aggregates
+            Expr::Sum(args) => {
+                let numbers = numeric_elements(self.aggregate_elements(args)?, "Sum")?;
+                self.checked_number("Sum", &numbers, numbers.iter().sum())
+            }
+            Expr::Avg(args) => {
+                let numbers = numeric_elements(self.aggregate_elements(args)?, "Avg")?;
+                if numbers.is_empty() {
+                    return Err(CalculatorError::InvalidArgument(
+                        "Avg requires at least one element".to_string(),
+                    ));
+                }
+                Ok(Value::Number(
+                    numbers.iter().sum::<f64>() / numbers.len() as f64,
+                ))
+            }
+            // Like SQL's COUNT(column), null elements are not counted
+            Expr::Count(args) => Ok(Value::Number(
+                self.aggregate_elements(args)?
+                    .iter()
+                    .filter(|value| !value.is_null())
+                    .count() as f64,
+            )),
+            Expr::Product(args) => {
+                let numbers = numeric_elements(self.aggregate_elements(args)?, "Product")?;
+                self.checked_number("Product", &numbers, numbers.iter().product())
+            }
+            Expr::Median(args) => {
+                let numbers = numeric_elements(self.aggregate_elements(args)?, "Median")?;
+                Ok(Value::Number(percentile(numbers, 50.0, "Median")?))
+            }
+            Expr::Variance(args) => {
+                let numbers = numeric_elements(self.aggregate_elements(args)?, "Variance")?;
+                Ok(Value::Number(variance(&numbers, true, "Variance")?))
+            }
+            Expr::VarianceP(args) => {
+                let numbers = numeric_elements(self.aggregate_elements(args)?, "VarianceP")?;
+                Ok(Value::Number(variance(&numbers, false, "VarianceP")?))
+            }
+            Expr::Stddev(args) => {
+                let numbers = numeric_elements(self.aggregate_elements(args)?, "Stddev")?;
+                Ok(Value::Number(variance(&numbers, true, "Stddev")?.sqrt()))
+            }
+            Expr::StddevP(args) => {
+                let numbers = numeric_elements(self.aggregate_elements(args)?, "StddevP")?;
+                Ok(Value::Number(variance(&numbers, false, "StddevP")?.sqrt()))
+            }
+            Expr::Percentile(values_expr, p_expr) => {
+                let numbers = numeric_elements(
+                    self.aggregate_elements(std::slice::from_ref(values_expr.as_ref()))?,
+                    "Percentile",
+                )?;
+
+                match self.evaluate_expr(p_expr)? {
+                    Value::Number(p) => Ok(Value::Number(percentile(numbers, p, "Percentile")?)),
+                    _ => Err(CalculatorError::TypeError(
+                        "Percentile requires a numeric percentile".to_string(),
+                    )),
+                }
+            }
+
+            // Financial
+            Expr::Pmt(args) => {
+                // The parser enforces this, but `Expr` trees can also be built by hand
+                if !(3..=5).contains(&args.len()) {
+                    return Err(CalculatorError::InvalidArgument(format!(
+                        "Pmt requires 3 to 5 arguments, got {}",
+                        args.len()
+                    )));
+                }
+                let numbers = numeric_elements(self.evaluate_args(args)?, "Pmt")?;
+                let fv = numbers.get(3).copied().unwrap_or(0.0);
+                let due = numbers.get(4).copied().unwrap_or(0.0);
+                Ok(Value::Number(pmt(
+                    numbers[0], numbers[1], numbers[2], fv, due,
+                )?))
+            }
+            Expr::Fv(rate_expr, nper_expr, pv_expr) => {
+                let rate = self.evaluate_expr(rate_expr)?;
+                let nper = self.evaluate_expr(nper_expr)?;
+                let pv = self.evaluate_expr(pv_expr)?;
+
+                match (rate, nper, pv) {
+                    (Value::Number(_), Value::Number(nper), Value::Number(_)) if nper < 0.0 => {
+                        Err(CalculatorError::InvalidArgument(format!(
+                            "Fv requires a non-negative number of periods, got {}",
+                            nper
+                        )))
+                    }
+                    (Value::Number(rate), Value::Number(nper), Value::Number(pv)) => {
+                        Ok(Value::Number(fv(rate, nper, pv)))
+                    }
+                    _ => Err(CalculatorError::TypeError(
+                        "Fv requires (number, number, number)".to_string(),
+                    )),
+                }
+            }
+            Expr::Cagr(begin_expr, end_expr, years_expr) => {
+                let begin = self.evaluate_expr(begin_expr)?;
+                let end = self.evaluate_expr(end_expr)?;
+                let years = self.evaluate_expr(years_expr)?;
+
+                match (begin, end, years) {
+                    (Value::Number(begin), Value::Number(end), Value::Number(years)) => {
+                        if years <= 0.0 {
+                            return Err(CalculatorError::InvalidArgument(format!(
+                                "Cagr requires a positive number of years, got {}",
+                                years
+                            )));
+                        }
+                        if begin <= 0.0 || end < 0.0 {
+                            return Err(CalculatorError::InvalidArgument(format!(
+                                "Cagr requires a positive begin and non-negative end value, got {} and {}",
+                                begin, end
+                            )));
+                        }
+                        Ok(Value::Number((end / begin).powf(1.0 / years) - 1.0))
+                    }
+                    _ => Err(CalculatorError::TypeError(
+                        "Cagr requires (number, number, number)".to_string(),
+                    )),
+                }
+            }
+            Expr::ApproxEqual(a_expr, b_expr, epsilon_expr) => {
+                let a = self.evaluate_expr(a_expr)?;
+                let b = self.evaluate_expr(b_expr)?;
+                let epsilon = self.evaluate_expr(epsilon_expr)?;
+
+                match (a, b, epsilon) {
+                    (Value::Number(a), Value::Number(b), Value::Number(epsilon)) => {
+                        if epsilon.is_nan() || epsilon < 0.0 {
+                            return Err(CalculatorError::InvalidArgument(format!(
+                                "ApproxEqual requires a non-negative epsilon, got {}",
+                                epsilon
+                            )));
+                        }
+                        Ok(Value::Bool((a - b).abs() <= epsilon))
+                    }
+                    _ => Err(CalculatorError::TypeError(
+                        "ApproxEqual requires (number, number, number)".to_string(),
+                    )),
+                }
+            }
+            Expr::Npv(rate_expr, flows) => {
+                let rate = match self.evaluate_expr(rate_expr)? {
+                    Value::Number(rate) => rate,
+                    _ => {
+                        return Err(CalculatorError::TypeError(
+                            "Npv rate must be a number".to_string(),
+                        ))
+                    }
+                };
+                let flows = numeric_elements(self.aggregate_elements(flows)?, "Npv")?;
+                Ok(Value::Number(npv(rate, &flows)))
+            }
+            Expr::Irr(flows) => {
+                let flows = numeric_elements(self.aggregate_elements(flows)?, "Irr")?;
+                Ok(Value::Number(irr(&flows)?))
+            }
+
             // Custom function calls
             Expr::FunctionCall { name, args } => {
                 let function_id = build_function_id(name, args.len());
+                let function = self.function_cache.get(&function_id);
+                let async_function = self.async_function_cache.get(&function_id);
+                if function.is_none() && async_function.is_none() {
+                    return Err(CalculatorError::FunctionNotFound(function_id));
+                }
 
-                // Check cache first
-                if let Some(cached) = self.function_result_cache.get(&function_id) {
+                // Results are cached per argument values, so check after evaluating them
+                let param_values = self.evaluate_args(args)?;
+                let key = FunctionResultCache::key(&function_id, &param_values);
+                if let Some(cached) = self.function_result_cache.get(&key) {
                     return Ok(cached);
                 }
 
-                let function = self
-                    .function_cache
-                    .get(&function_id)
-                    .ok_or_else(|| CalculatorError::FunctionNotFound(function_id.clone()))?;
-
-                let mut param_values = Vec::new();
-                for arg in args {
-                    param_values.push(self.evaluate_expr(arg)?);
-                }
+                let result = if let Some(function) = function {
+                    function.execute(&param_values)?
+                } else if let Some(function) = async_function {
+                    let runner = self.async_runner.as_ref().ok_or_else(|| {
+                        CalculatorError::EvalError(
+                            "Async function requires async execution".to_string(),
+                        )
+                    })?;
+                    runner(function.execute(&param_values))?
+                } else {
+                    return Err(CalculatorError::FunctionNotFound(function_id));
+                };
 
-                let result = function.execute(&param_values)?;
-                self.function_result_cache.set(function_id, result.clone());
+                self.function_result_cache.set(key, result.clone());
                 Ok(result)
             }
+            _ => unreachable!("operators and literals are evaluated by evaluate_node"),
         }
     }
 }
 
-fn parse_date(s: &str) -> Result<NaiveDateTime> {
-    NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S")
-        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S"))
-        .or_else(|_| {
-            chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
-                .map(|d| d.and_hms_opt(0, 0, 0).unwrap())
-        })
+/// Rounds `value` to `decimals` places, halves away from zero.
+///
+/// Negative `decimals` round to tens, hundreds, and so on, like Excel's `ROUND`:
+/// `-2` rounds `1234` to `1200`. Places beyond what an `f64` can hold leave
+/// `value` unchanged (or round it to zero, for negative places).
+fn round_to_decimals(value: f64, decimals: f64) -> Result<f64> {
+    if decimals.fract() != 0.0 || decimals.is_nan() {
+        return Err(CalculatorError::InvalidArgument(format!(
+            "Rnd decimals must be an integer, got {}",
+            decimals
+        )));
+    }
+
+    // 10^400 is already infinite, so larger exponents change nothing
+    let places = decimals.clamp(-400.0, 400.0) as i32;
+    if places >= 0 {
+        let factor = 10_f64.powi(places);
+        let scaled = value * factor;
+        if scaled.is_finite() {
+            Ok(scaled.round() / factor)
+        } else {
+            Ok(value)
+        }
+    } else {
+        // Dividing by an exact power of ten avoids the inexact factor 10^-n
+        let factor = 10_f64.powi(-places);
+        if factor.is_finite() {
+            Ok((value / factor).round() * factor)
+        } else {
+            Ok(0.0)
+        }
+    }
+}
+
+/// The largest magnitude up to which `f64` represents every integer, `2^53`.
+const MAX_EXACT_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+/// Converts `n` to an integer, allowing for floating point error such as `3.0000000001`.
+///
+/// Negative numbers, non-integers, and integers beyond `2^53` (where `f64` stops
+/// representing every integer) are rejected.
+fn non_negative_integer(n: f64, function: &str) -> Result<u64> {
+    let rounded = n.round();
+    if (n - rounded).abs() > 1e-9 || !(0.0..=MAX_EXACT_INTEGER).contains(&rounded) {
+        return Err(CalculatorError::InvalidArgument(format!(
+            "{} requires non-negative integers, got {}",
+            function, n
+        )));
+    }
+    Ok(rounded as u64)
+}
+
+/// Converts `n` to a signed integer, like [`non_negative_integer`] but allowing
+/// negative values down to `-2^53`.
+fn integer(n: f64, function: &str) -> Result<i64> {
+    let rounded = n.round();
+    if (n - rounded).abs() > 1e-9 || !(-MAX_EXACT_INTEGER..=MAX_EXACT_INTEGER).contains(&rounded) {
+        return Err(CalculatorError::InvalidArgument(format!(
+            "{} requires integers between -2^53 and 2^53, got {}",
+            function, n
+        )));
+    }
+    Ok(rounded as i64)
+}
+
+/// Greatest common divisor by Euclid's algorithm; `gcd(0, 0)` is `0`.
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Number of ways to choose `k` of `n` items, `0` when `k > n`.
+///
+/// Multiplies one ratio at a time, `(n - k + i) / i`, so no intermediate factorial
+/// overflows; the product is rounded because each step stays a whole number.
+/// The partial products only grow, so the loop stops as soon as one overflows.
+fn combinations(n: u64, k: u64) -> Result<f64> {
+    if k > n {
+        return Ok(0.0);
+    }
+
+    let k = k.min(n - k);
+    let mut result = 1.0;
+    for i in 1..=k {
+        result = (result * (n - k + i) as f64 / i as f64).round();
+        if !result.is_finite() {
+            return Err(CalculatorError::InvalidArgument(format!(
+                "Combinations({}, {}) is too large to represent",
+                n, k
+            )));
+        }
+    }
+
+    Ok(result)
+}
+
+/// The most fraction digits `format_number` will print; far more than an `f64` holds.
+const MAX_FORMAT_DECIMALS: f64 = 100.0;
+
+/// The widest field the padding built-ins will fill.
+const MAX_FORMAT_WIDTH: f64 = 1000.0;
+
+/// Formats `n` with exactly `decimals` fraction digits, grouping the integer digits
+/// in threes with `separator` (no grouping when it is empty).
+///
+/// Halves round away from zero, matching `rnd`.
+fn format_number(n: f64, decimals: f64, separator: &str) -> Result<String> {
+    if !n.is_finite() {
+        return Err(CalculatorError::InvalidArgument(format!(
+            "FormatNumber requires a finite number, got {}",
+            n
+        )));
+    }
+    if !(0.0..=MAX_FORMAT_DECIMALS).contains(&decimals) || decimals.fract() != 0.0 {
+        return Err(CalculatorError::InvalidArgument(format!(
+            "FormatNumber decimals must be an integer from 0 to {}, got {}",
+            MAX_FORMAT_DECIMALS, decimals
+        )));
+    }
+
+    // Numbers too large to scale have no fractional part left to round
+    let factor = 10_f64.powi(decimals as i32);
+    let scaled = n * factor;
+    let rounded = if scaled.is_finite() {
+        scaled.round() / factor
+    } else {
+        n
+    };
+    // `-0.001` rounds to negative zero, which would otherwise print as "-0.00"
+    let rounded = if rounded == 0.0 { 0.0 } else { rounded };
+    let fixed = format!("{:.*}", decimals as usize, rounded);
+    let (sign, unsigned) = match fixed.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", fixed.as_str()),
+    };
+    let (integer, fraction) = match unsigned.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (unsigned, None),
+    };
+
+    let mut grouped = String::new();
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push_str(separator);
+        }
+        grouped.push(digit);
+    }
+
+    Ok(match fraction {
+        Some(fraction) => format!("{}{}.{}", sign, grouped, fraction),
+        None => format!("{}{}", sign, grouped),
+    })
+}
+
+/// Rounds `value` to a multiple of `multiple` using `round` (`f64::round`, `floor`, or `ceil`).
+///
+/// A quotient within a few ULPs of a whole number (or of a half, with
+/// `snap_halves`, which only matters for `f64::round`) is snapped to it before
+/// rounding, so binary representation error (`8.05 / 0.05 = 160.99999999999997`)
+/// does not move the result to the wrong multiple, and the result is trimmed to
+/// the decimals of `multiple`. Rounding is applied to the signed value: `f64::round` takes halves
+/// away from zero like Excel's `MROUND`, while floor and ceil go toward negative
+/// and positive infinity like `floor` and `ceil`.
+fn round_to_multiple(
+    value: f64,
+    multiple: f64,
+    round: fn(f64) -> f64,
+    snap_halves: bool,
+    function: &str,
+) -> Result<f64> {
+    const SNAP_ULPS: f64 = 16.0;
+
+    if !(multiple > 0.0 && multiple.is_finite()) {
+        return Err(CalculatorError::InvalidArgument(format!(
+            "{} requires a positive multiple, got {}",
+            function, multiple
+        )));
+    }
+
+    let mut quotient = value / multiple;
+    let steps = if snap_halves { 2.0 } else { 1.0 };
+    let nearest = (quotient * steps).round() / steps;
+    if (quotient - nearest).abs() <= SNAP_ULPS * f64::EPSILON * quotient.abs().max(1.0) {
+        quotient = nearest;
+    }
+
+    let decimals = multiple
+        .to_string()
+        .split_once('.')
+        .map_or(0, |(_, fraction)| fraction.len().min(15));
+    let factor = 10_f64.powi(decimals as i32);
+    Ok((round(quotient) * multiple * factor).round() / factor)
+}
+
+/// Converts aggregate elements to numbers, naming the first non-numeric index.
+fn numeric_elements(elements: Vec<Value>, function: &str) -> Result<Vec<f64>> {
+    elements
+        .into_iter()
+        .enumerate()
+        .map(|(i, element)| match element {
+            Value::Number(n) => Ok(n),
+            other => Err(CalculatorError::TypeError(format!(
+                "{} requires numbers, but element {} is '{}'",
+                function, i, other
+            ))),
+        })
+        .collect()
+}
+
+/// Payment per period of an annuity, following the sign convention of Excel's `PMT`.
+///
+/// `due` is `0` for payments at the end of each period and `1` for the beginning.
+/// Money received (such as a loan's `pv`) is positive, so payments are negative.
+/// Unlike Excel, [`fv`] keeps the sign of `pv`, so `pmt(rate, nper, pv)` equals
+/// `pmt(rate, nper, 0, fv(rate, nper, pv))`.
+fn pmt(rate: f64, nper: f64, pv: f64, fv: f64, due: f64) -> Result<f64> {
+    if nper <= 0.0 {
+        return Err(CalculatorError::InvalidArgument(format!(
+            "Pmt requires a positive number of periods, got {}",
+            nper
+        )));
+    }
+    if due != 0.0 && due != 1.0 {
+        return Err(CalculatorError::InvalidArgument(format!(
+            "Pmt type must be 0 (end of period) or 1 (beginning), got {}",
+            due
+        )));
+    }
+
+    if rate == 0.0 {
+        return Ok(-(pv + fv) / nper);
+    }
+
+    let growth = (1.0 + rate).powf(nper);
+    Ok(-(rate * (pv * growth + fv)) / ((1.0 + rate * due) * (growth - 1.0)))
+}
+
+/// Future value of `pv` compounded once per period for `nper` periods.
+///
+/// The result has the same sign as `pv`: a plain growth projection rather than
+/// Excel's `FV`, which negates it as the cash flow paid back. See [`pmt`].
+fn fv(rate: f64, nper: f64, pv: f64) -> f64 {
+    pv * (1.0 + rate).powf(nper)
+}
+
+/// The `p`-th percentile (0–100) of `numbers`, like Excel's `PERCENTILE.INC`.
+///
+/// The value sits at rank `p / 100 * (n - 1)` of the sorted numbers, linearly
+/// interpolating between the two nearest ranks.
+fn percentile(mut numbers: Vec<f64>, p: f64, function: &str) -> Result<f64> {
+    if numbers.is_empty() {
+        return Err(CalculatorError::InvalidArgument(format!(
+            "{} requires at least one element",
+            function
+        )));
+    }
+    if !(0.0..=100.0).contains(&p) {
+        return Err(CalculatorError::InvalidArgument(format!(
+            "{} percentile must be between 0 and 100, got {}",
+            function, p
+        )));
+    }
+    if let Some(i) = numbers.iter().position(|n| n.is_nan()) {
+        return Err(CalculatorError::InvalidArgument(format!(
+            "{} cannot rank NaN at element {}",
+            function, i
+        )));
+    }
+
+    numbers.sort_by(f64::total_cmp);
+    let rank = p / 100.0 * (numbers.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    Ok(numbers[lower] + (numbers[upper] - numbers[lower]) * (rank - lower as f64))
+}
+
+/// Sample (`n - 1`) or population (`n`) variance of `numbers`.
+///
+/// Uses Welford's single-pass update, which stays accurate when the mean is large
+/// relative to the spread, unlike subtracting the squared mean from the mean square.
+fn variance(numbers: &[f64], sample: bool, function: &str) -> Result<f64> {
+    let required = if sample { 2 } else { 1 };
+    if numbers.len() < required {
+        return Err(CalculatorError::InvalidArgument(format!(
+            "{} requires at least {} element{}",
+            function,
+            required,
+            if required == 1 { "" } else { "s" }
+        )));
+    }
+
+    let mut mean = 0.0;
+    let mut squared_deviations = 0.0;
+    for (i, x) in numbers.iter().enumerate() {
+        let delta = x - mean;
+        mean += delta / (i + 1) as f64;
+        squared_deviations += delta * (x - mean);
+    }
+
+    let divisor = if sample {
+        numbers.len() - 1
+    } else {
+        numbers.len()
+    };
+    Ok(squared_deviations / divisor as f64)
+}
+
+/// Net present value of cash flows received at the end of each period, like Excel's `NPV`.
+fn npv(rate: f64, flows: &[f64]) -> f64 {
+    flows
+        .iter()
+        .enumerate()
+        .map(|(i, flow)| flow / (1.0 + rate).powi(i as i32 + 1))
+        .sum()
+}
+
+/// Internal rate of return of cash flows starting now, solved with Newton's method.
+fn irr(flows: &[f64]) -> Result<f64> {
+    const MAX_ITERATIONS: usize = 100;
+    const TOLERANCE: f64 = 1e-10;
+
+    if !(flows.iter().any(|f| *f > 0.0) && flows.iter().any(|f| *f < 0.0)) {
+        return Err(CalculatorError::EvalError(
+            "Irr requires at least one positive and one negative cash flow".to_string(),
+        ));
+    }
+
+    let mut rate: f64 = 0.1;
+    for _ in 0..MAX_ITERATIONS {
+        let mut value = 0.0;
+        let mut derivative = 0.0;
+        for (i, flow) in flows.iter().enumerate() {
+            let discount = (1.0 + rate).powi(i as i32);
+            value += flow / discount;
+            derivative -= i as f64 * flow / (discount * (1.0 + rate));
+        }
+
+        let next = rate - value / derivative;
+        if !next.is_finite() || next <= -1.0 {
+            break;
+        }
+        if (next - rate).abs() < TOLERANCE {
+            return Ok(next);
+        }
+        rate = next;
+    }
+
+    Err(CalculatorError::EvalError(
+        "Irr did not converge".to_string(),
+    ))
+}
+
+/// Extracts `len` characters of `s` starting at character `start`.
+///
+/// A negative `start` counts back from the end of the string and is clamped to its
+/// beginning; a `start` past the end yields an empty string. Omitting `len` takes
+/// the rest of the string, and a negative `len` is rejected.
+fn substring(s: &str, start: f64, len: Option<f64>) -> Result<String> {
+    if let Some(len) = len {
+        if len < 0.0 {
+            return Err(CalculatorError::InvalidArgument(format!(
+                "Substr length must not be negative, got {}",
+                len
+            )));
+        }
+    }
+
+    let char_count = s.chars().count() as i64;
+    let start = start as i64;
+    let start = if start < 0 {
+        (char_count + start).max(0)
+    } else {
+        start
+    } as usize;
+
+    let rest = s.chars().skip(start);
+    Ok(match len {
+        Some(len) => rest.take(len as usize).collect(),
+        None => rest.collect(),
+    })
+}
+
+/// Converts a date value or date string into a datetime, or fails with `type_error`.
+fn to_date(value: &Value, type_error: &str) -> Result<NaiveDateTime> {
+    match value {
+        Value::Date(d) => Ok(*d),
+        Value::String(s) => parse_date(s),
+        _ => Err(CalculatorError::TypeError(type_error.to_string())),
+    }
+}
+
+/// Moves `date` by whole `days` (truncated toward zero), failing instead of
+/// overflowing when the result falls outside the supported date range.
+fn add_days(date: NaiveDateTime, days: f64) -> Result<NaiveDateTime> {
+    chrono::TimeDelta::try_days(days as i64)
+        .and_then(|delta| date.checked_add_signed(delta))
+        .ok_or_else(|| {
+            CalculatorError::InvalidArgument(format!(
+                "Moving {} by {} days is outside the supported date range",
+                date.format(DATE_FORMAT),
+                days
+            ))
+        })
+}
+
+/// Renders dates as their canonical string so string built-ins keep accepting them.
+fn date_to_string(value: Value) -> Value {
+    match value {
+        Value::Date(_) => Value::String(value.get()),
+        other => other,
+    }
+}
+
+/// Interprets a string or date operand as a datetime for comparisons.
+fn date_operand(value: &Value) -> Option<NaiveDateTime> {
+    match value {
+        Value::Date(d) => Some(*d),
+        Value::String(s) => parse_date(s).ok(),
+        _ => None,
+    }
+}
+
+fn is_textual(value: &Value) -> bool {
+    matches!(value, Value::String(_) | Value::Date(_))
+}
+
+/// Orders two values, comparing dates and date strings chronologically.
+///
+/// A date compared against a non-date string is a type error rather than
+/// silently falling back to lexicographic order.
+fn compare_values(l: &Value, r: &Value) -> Result<std::cmp::Ordering> {
+    if is_textual(l) && is_textual(r) {
+        match (date_operand(l), date_operand(r)) {
+            (Some(date_a), Some(date_b)) => return Ok(date_a.cmp(&date_b)),
+            (None, None) => {}
+            _ => {
+                return Err(CalculatorError::TypeError(
+                    "Cannot compare a date with a non-date string".to_string(),
+                ))
+            }
+        }
+    }
+
+    l.partial_cmp(r).ok_or_else(|| {
+        CalculatorError::TypeError("Cannot compare values of different types".to_string())
+    })
+}
+
+/// Checks two values for equality, treating dates and date strings chronologically.
+fn values_equal(l: &Value, r: &Value) -> bool {
+    if is_textual(l) && is_textual(r) {
+        if let (Some(date_a), Some(date_b)) = (date_operand(l), date_operand(r)) {
+            return date_a == date_b;
+        }
+    }
+
+    l == r
+}
+
+/// Counts Monday-Friday days after `start` up to and including `end`, skipping holidays.
+///
+/// The result is negative when `end` is before `start`.
+fn business_days_between(start: NaiveDate, end: NaiveDate, holidays: &HashSet<NaiveDate>) -> i64 {
+    let (from, to, sign) = if start <= end {
+        (start, end, 1)
+    } else {
+        (end, start, -1)
+    };
+    let is_weekday = |date: &NaiveDate| !matches!(date.weekday(), Weekday::Sat | Weekday::Sun);
+
+    let total_days = (to - from).num_days();
+    let mut count = total_days / 7 * 5;
+    count += (0..total_days % 7)
+        .map(|offset| to - chrono::Duration::days(offset))
+        .filter(is_weekday)
+        .count() as i64;
+    count -= holidays
+        .iter()
+        .filter(|day| **day > from && **day <= to && is_weekday(day))
+        .count() as i64;
+
+    sign * count
+}
+
+fn parse_date(s: &str) -> Result<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S"))
+        .or_else(|_| {
+            chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .map(|d| d.and_hms_opt(0, 0, 0).unwrap())
+        })
         .map_err(|e| {
             CalculatorError::DateParseError(format!("Failed to parse date '{}': {}", s, e))
         })
@@ -519,6 +1683,11 @@ mod tests {
         )
     }
 
+    fn evaluate(input: &str) -> Result<Value> {
+        let program = Parser::new(input)?.parse()?;
+        create_evaluator().evaluate(&program)
+    }
+
     #[test]
     fn test_evaluate_number() {
         let mut parser = Parser::new("return 42").unwrap();
@@ -558,4 +1727,1546 @@ mod tests {
         let result = evaluator.evaluate(&program).unwrap();
         assert_eq!(result, Value::Number(200.0));
     }
+
+    #[test]
+    fn test_business_days_between_spanning_weekends() {
+        // Friday 2024-01-05 to Monday 2024-01-15
+        assert_eq!(
+            evaluate("return business_days_between('2024-01-05', '2024-01-15')").unwrap(),
+            Value::Number(6.0)
+        );
+    }
+
+    #[test]
+    fn test_business_days_between_within_weekend() {
+        assert_eq!(
+            evaluate("return business_days_between('2024-01-06', '2024-01-07')").unwrap(),
+            Value::Number(0.0)
+        );
+    }
+
+    #[test]
+    fn test_business_days_between_reversed() {
+        assert_eq!(
+            evaluate("return business_days_between('2024-01-15', '2024-01-05')").unwrap(),
+            Value::Number(-6.0)
+        );
+    }
+
+    #[test]
+    fn test_business_days_between_with_holidays() {
+        // The Saturday holiday does not reduce the count further
+        assert_eq!(
+            evaluate(
+                "return business_days_between('2024-01-05', '2024-01-15', '2024-01-08, 2024-01-13')"
+            )
+            .unwrap(),
+            Value::Number(5.0)
+        );
+    }
+
+    #[test]
+    fn test_business_days_between_with_holiday_array() {
+        let evaluator = create_evaluator();
+        evaluator.variable_cache.set(
+            "holidays".to_string(),
+            Value::Array(vec![
+                Value::String("2024-01-08".to_string()),
+                Value::Date(parse_date("2024-01-09").unwrap()),
+            ]),
+        );
+        evaluator
+            .variable_cache
+            .set("bad".to_string(), Value::from(vec![1.0]));
+
+        let eval = |input: &str| evaluator.evaluate(&Parser::new(input)?.parse()?);
+
+        assert_eq!(
+            eval("return business_days_between('2024-01-05', '2024-01-15', holidays)").unwrap(),
+            Value::Number(4.0)
+        );
+        assert!(matches!(
+            eval("return business_days_between('2024-01-05', '2024-01-15', bad)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_iif_selects_branch() {
+        assert_eq!(
+            evaluate("return 100 + iif(2 > 1, 10, 0)").unwrap(),
+            Value::Number(110.0)
+        );
+        assert_eq!(
+            evaluate("return 100 + iif(2 < 1, 10, 0)").unwrap(),
+            Value::Number(100.0)
+        );
+    }
+
+    #[test]
+    fn test_boolean_literals_ignore_case() {
+        for literal in ["true", "True", "TRUE"] {
+            assert_eq!(
+                evaluate(&format!("return {}", literal)).unwrap(),
+                Value::Bool(true)
+            );
+        }
+        assert_eq!(evaluate("return FALSE or True").unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn test_iif_only_evaluates_selected_branch() {
+        assert_eq!(
+            evaluate("return iif(true, 'ok', missing_variable)").unwrap(),
+            Value::String("ok".to_string())
+        );
+    }
+
+    #[test]
+    fn test_iif_requires_boolean_condition() {
+        assert!(matches!(
+            evaluate("return iif(1, 10, 0)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_compare_dates_chronologically_across_formats() {
+        assert_eq!(
+            evaluate("return '2024-1-5' = '2024-01-05'").unwrap(),
+            Value::Bool(true)
+        );
+        // Lexicographically '2024-1-5' > '2024-01-10', chronologically it is earlier
+        assert_eq!(
+            evaluate("return '2024-1-5' < '2024-01-10'").unwrap(),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            evaluate("return '2024-01-05 10:00:00' > '2024-1-5'").unwrap(),
+            Value::Bool(true)
+        );
+    }
+
+    #[test]
+    fn test_compare_plain_strings_lexicographically() {
+        assert_eq!(
+            evaluate("return 'apple' < 'banana'").unwrap(),
+            Value::Bool(true)
+        );
+    }
+
+    #[test]
+    fn test_compare_date_with_non_date_is_type_error() {
+        assert!(matches!(
+            evaluate("return '2024-01-05' < 'tomorrow'"),
+            Err(CalculatorError::TypeError(_))
+        ));
+        assert!(matches!(
+            evaluate("return '2024-01-05' >= 5"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_date_builtin_returns_date_value() {
+        let expected = NaiveDate::from_ymd_opt(2024, 1, 5)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        assert_eq!(
+            evaluate("return date('2024-1-5')").unwrap(),
+            Value::Date(expected)
+        );
+        assert_eq!(
+            evaluate("return year(date('2024-03-05')) + day(date('2024-03-05'))").unwrap(),
+            Value::Number(2029.0)
+        );
+    }
+
+    #[test]
+    fn test_date_arithmetic() {
+        assert_eq!(
+            evaluate("return date('2024-01-30') + 3")
+                .unwrap()
+                .to_string(),
+            "2024-02-02T00:00:00"
+        );
+        assert_eq!(
+            evaluate("return date('2024-01-30') - 30")
+                .unwrap()
+                .to_string(),
+            "2023-12-31T00:00:00"
+        );
+        assert_eq!(
+            evaluate("return date('2024-03-01') - date('2024-02-01')").unwrap(),
+            Value::Number(29.0)
+        );
+    }
+
+    #[test]
+    fn test_date_arithmetic_out_of_range() {
+        for input in [
+            "return date('2024-01-01') + 1000000000000000",
+            "return date('2024-01-01') - 100000000",
+            "return 100000000 + date('2024-01-01')",
+            "return add_days('2024-01-01', -100000000)",
+        ] {
+            assert!(
+                matches!(evaluate(input), Err(CalculatorError::InvalidArgument(_))),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_diff_hours_and_minutes() {
+        let eval = |input: &str| evaluate(input).unwrap();
+
+        assert_eq!(
+            eval("return get_diff_hours('2024-01-15 10:00:00', '2024-01-15 09:00:00')"),
+            Value::Number(1.0)
+        );
+        assert_eq!(
+            eval("return get_diff_minutes('2024-01-15 10:30:00', '2024-01-15 09:00:00')"),
+            Value::Number(90.0)
+        );
+        // Signed like get_diff_days, and partial units are truncated toward zero
+        assert_eq!(
+            eval("return get_diff_hours('2024-01-15 09:00:00', '2024-01-15 10:30:00')"),
+            Value::Number(-1.0)
+        );
+        assert_eq!(
+            eval("return get_diff_hours('2024-01-16', '2024-01-15')"),
+            Value::Number(24.0)
+        );
+        assert!(matches!(
+            evaluate("return get_diff_minutes(5, '2024-01-15')"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_date_results_coerce_to_strings() {
+        assert!(evaluate("return add_days('2024-01-15', 5)")
+            .unwrap()
+            .is_date());
+        assert_eq!(
+            evaluate("return substr(add_days('2024-01-15', 5), 0, 10)").unwrap(),
+            Value::String("2024-01-20".to_string())
+        );
+        assert_eq!(
+            evaluate("return 'Due: ' + add_days('2024-01-15', 5)").unwrap(),
+            Value::String("Due: 2024-01-20T00:00:00".to_string())
+        );
+        assert_eq!(
+            evaluate("return add_days('2024-01-15', 5) = '2024-01-20'").unwrap(),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            evaluate("return add_days('2024-01-15', 5) > '2024-1-19'").unwrap(),
+            Value::Bool(true)
+        );
+    }
+
+    #[test]
+    fn test_null_values() {
+        let evaluator = create_evaluator();
+        evaluator
+            .variable_cache
+            .set("missing".to_string(), Value::Null);
+        evaluator.variable_cache.set(
+            "scores".to_string(),
+            Value::Array(vec![Value::Number(1.0), Value::Null]),
+        );
+        let eval = |input: &str| evaluator.evaluate(&Parser::new(input)?.parse()?);
+
+        assert_eq!(eval("return missing").unwrap(), Value::Null);
+        assert_eq!(eval("return missing = missing").unwrap(), Value::Bool(true));
+        assert_eq!(eval("return missing = ''").unwrap(), Value::Bool(false));
+        assert_eq!(eval("return missing <> 0").unwrap(), Value::Bool(true));
+        assert_eq!(
+            eval("return iif(missing = missing, 'absent', 'present')").unwrap(),
+            Value::String("absent".to_string())
+        );
+        assert_eq!(eval("return count(scores)").unwrap(), Value::Number(1.0));
+
+        for input in [
+            "return missing + 1",
+            "return 'total: ' + missing",
+            "return missing * 2",
+            "return missing < 1",
+            "return join(scores, ',')",
+            "return sum(scores)",
+            "if (missing) then return 1 else return 2 end",
+        ] {
+            assert!(
+                matches!(eval(input), Err(CalculatorError::TypeError(_))),
+                "{} did not fail with a type error",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_substr_semantics() {
+        let cases = [
+            ("substr('abcdef', 0, 3)", "abc"),
+            ("substr('abcdef', 2, 3)", "cde"),
+            ("substr('abcdef', 4, 10)", "ef"),
+            ("substr('abc', 5, 2)", ""),
+            ("substr('abc', 3)", ""),
+            ("substr('abcdef', 2)", "cdef"),
+            ("substr('abcdef', 0, 0)", ""),
+            ("substr('abcdef', -2)", "ef"),
+            ("substr('abcdef', -3, 2)", "de"),
+            ("substr('abc', -10, 2)", "ab"),
+            ("substr('', 0)", ""),
+            ("substr('héllo wörld', 1, 4)", "éllo"),
+            ("substr('héllo wörld', -5)", "wörld"),
+            ("substr('日本語テキスト', 2, 3)", "語テキ"),
+            ("substr('a😀b', 1, 1)", "😀"),
+        ];
+
+        for (expr, expected) in cases {
+            assert_eq!(
+                evaluate(&format!("return {}", expr)).unwrap(),
+                Value::String(expected.to_string()),
+                "{}",
+                expr
+            );
+        }
+    }
+
+    #[test]
+    fn test_substr_rejects_invalid_arguments() {
+        assert!(matches!(
+            evaluate("return substr('abc', 0, -1)"),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            evaluate("return substr('abc', -1, -1)"),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            evaluate("return substr('abc', 'x')"),
+            Err(CalculatorError::TypeError(_))
+        ));
+        assert!(matches!(
+            evaluate("return substr('abc', 0, 1, 2)"),
+            Err(CalculatorError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_aggregates_over_array_variable() {
+        let evaluator = create_evaluator();
+        evaluator
+            .variable_cache
+            .set("totals".to_string(), Value::from(vec![2.0, 3.0, 5.0]));
+        evaluator
+            .variable_cache
+            .set("empty".to_string(), Value::Array(vec![]));
+
+        let eval = |input: &str| evaluator.evaluate(&Parser::new(input)?.parse()?);
+
+        assert_eq!(eval("return sum(totals)").unwrap(), Value::Number(10.0));
+        assert_eq!(
+            eval("return avg(totals)").unwrap(),
+            Value::Number(10.0 / 3.0)
+        );
+        assert_eq!(eval("return count(totals)").unwrap(), Value::Number(3.0));
+        assert_eq!(eval("return product(totals)").unwrap(), Value::Number(30.0));
+
+        assert_eq!(eval("return sum(empty)").unwrap(), Value::Number(0.0));
+        assert_eq!(eval("return count(empty)").unwrap(), Value::Number(0.0));
+        assert!(matches!(
+            eval("return avg(empty)"),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_aggregates_variadic() {
+        assert_eq!(evaluate("return sum(1, 2, 3)").unwrap(), Value::Number(6.0));
+        assert_eq!(
+            evaluate("return avg(1, 2, 3, 6)").unwrap(),
+            Value::Number(3.0)
+        );
+        assert_eq!(
+            evaluate("return count(1, 'a', true)").unwrap(),
+            Value::Number(3.0)
+        );
+        assert_eq!(
+            evaluate("return product(2, 3, 4)").unwrap(),
+            Value::Number(24.0)
+        );
+        assert_eq!(evaluate("return sum(7)").unwrap(), Value::Number(7.0));
+        assert!(matches!(
+            evaluate("return sum()"),
+            Err(CalculatorError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_aggregates_reject_non_numeric_elements() {
+        let evaluator = create_evaluator();
+        evaluator.variable_cache.set(
+            "mixed".to_string(),
+            Value::Array(vec![Value::Number(1.0), Value::String("x".to_string())]),
+        );
+        let program = Parser::new("return sum(mixed)").unwrap().parse().unwrap();
+
+        match evaluator.evaluate(&program) {
+            Err(CalculatorError::TypeError(msg)) => assert!(msg.contains("element 1"), "{}", msg),
+            other => panic!("expected a type error, got {:?}", other),
+        }
+        match evaluate("return product(2, 'three')") {
+            Err(CalculatorError::TypeError(msg)) => assert!(msg.contains("element 1"), "{}", msg),
+            other => panic!("expected a type error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_pmt_matches_excel() {
+        let cases = [
+            ("pmt(0.08 / 12, 10, 10000)", -1037.032089),
+            ("pmt(0.08 / 12, 10, 10000, 0, 1)", -1030.164327),
+            ("pmt(0.06 / 12, 18 * 12, 0, 50000)", -129.081161),
+            ("pmt(0.05 / 12, 360, 200000)", -1073.643246),
+            ("pmt(0, 10, 1000)", -100.0),
+            ("pmt(0, 10, 1000, 500)", -150.0),
+        ];
+
+        for (expr, expected) in cases {
+            match evaluate(&format!("return {}", expr)).unwrap() {
+                Value::Number(n) => assert!((n - expected).abs() < 1e-6, "{} = {}", expr, n),
+                other => panic!("{} returned {:?}", expr, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_pmt_rejects_invalid_arguments() {
+        assert!(matches!(
+            evaluate("return pmt(0.05, 0, 1000)"),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            evaluate("return pmt(0.05, -12, 1000)"),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            evaluate("return pmt(0.05, 12, 1000, 0, 2)"),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            evaluate("return pmt(0.05, '12', 1000)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+        assert!(matches!(
+            evaluate("return pmt(0.05, 12)"),
+            Err(CalculatorError::ParseError(_))
+        ));
+        assert!(matches!(
+            evaluate("return pmt(0.05, 12, 1000, 0, 0, 1)"),
+            Err(CalculatorError::ParseError(_))
+        ));
+
+        let program = Program {
+            statement: Statement::Return(Expr::Pmt(vec![Expr::Number(0.05)])),
+        };
+        assert!(matches!(
+            create_evaluator().evaluate(&program),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_rnd() {
+        let cases = [
+            ("return rnd(2.71828, 2)", 2.72),
+            ("return rnd(2.5, 0)", 3.0),
+            ("return rnd(-2.5, 0)", -3.0),
+            ("return rnd(1234.5678, -1)", 1230.0),
+            ("return rnd(1234.5678, -2)", 1200.0),
+            ("return rnd(1250, -2)", 1300.0),
+            ("return rnd(1234.5678, -3)", 1000.0),
+            ("return rnd(-1234.5678, -1)", -1230.0),
+            ("return rnd(-1250, -2)", -1300.0),
+            ("return rnd(-1234.5678, -3)", -1000.0),
+            ("return rnd(499, -3)", 0.0),
+            ("return rnd(1.5, 10 ^ 10)", 1.5),
+            ("return rnd(10 ^ 300, 20)", 1e300),
+            ("return rnd(123456, -(10 ^ 10))", 0.0),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                evaluate(input).unwrap(),
+                Value::Number(expected),
+                "{}",
+                input
+            );
+        }
+
+        assert!(matches!(
+            evaluate("return rnd(1.234, 1.5)"),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            evaluate("return rnd('a', 1)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_round_to_multiple() {
+        assert_eq!(
+            evaluate("return mround(8.05, 0.05)").unwrap(),
+            Value::Number(8.05)
+        );
+        assert_eq!(
+            evaluate("return mround(8.07, 0.05)").unwrap(),
+            Value::Number(8.05)
+        );
+        assert_eq!(
+            evaluate("return mround(8.025, 0.05)").unwrap(),
+            Value::Number(8.05)
+        );
+        assert_eq!(
+            evaluate("return mround(10, 3)").unwrap(),
+            Value::Number(9.0)
+        );
+        assert_eq!(
+            evaluate("return ceil_to(101, 25)").unwrap(),
+            Value::Number(125.0)
+        );
+        assert_eq!(
+            evaluate("return ceil_to(0.3, 0.1)").unwrap(),
+            Value::Number(0.3)
+        );
+        assert_eq!(
+            evaluate("return floor_to(8.05, 0.05)").unwrap(),
+            Value::Number(8.05)
+        );
+        assert_eq!(
+            evaluate("return floor_to(19.99, 0.25)").unwrap(),
+            Value::Number(19.75)
+        );
+
+        // Large values keep their small excess instead of snapping back to a multiple
+        assert_eq!(
+            evaluate("return ceil_to(1000000.0000001, 0.01)").unwrap(),
+            Value::Number(1000000.01)
+        );
+        assert_eq!(
+            evaluate("return floor_to(999999.9999999, 0.01)").unwrap(),
+            Value::Number(999999.99)
+        );
+        assert_eq!(
+            evaluate("return mround(1000000.0049999, 0.01)").unwrap(),
+            Value::Number(1000000.0)
+        );
+
+        // Negative values: mround is symmetric (halves away from zero), floor_to and
+        // ceil_to round toward negative and positive infinity
+        assert_eq!(
+            evaluate("return mround(-8.025, 0.05)").unwrap(),
+            Value::Number(-8.05)
+        );
+        assert_eq!(
+            evaluate("return floor_to(-101, 25)").unwrap(),
+            Value::Number(-125.0)
+        );
+        assert_eq!(
+            evaluate("return ceil_to(-101, 25)").unwrap(),
+            Value::Number(-100.0)
+        );
+
+        for input in [
+            "return mround(5, 0)",
+            "return floor_to(5, -1)",
+            "return ceil_to(5, 0)",
+        ] {
+            assert!(
+                matches!(evaluate(input), Err(CalculatorError::InvalidArgument(_))),
+                "{}",
+                input
+            );
+        }
+        assert!(matches!(
+            evaluate("return mround('a', 1)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_modulo_conventions() {
+        // `mod` keeps the sign of the dividend, `mod_e` is never negative
+        assert_eq!(evaluate("return -1 mod 3").unwrap(), Value::Number(-1.0));
+        assert_eq!(evaluate("return mod_e(-1, 3)").unwrap(), Value::Number(2.0));
+
+        assert_eq!(evaluate("return 7 mod -3").unwrap(), Value::Number(1.0));
+        assert_eq!(evaluate("return mod_e(7, -3)").unwrap(), Value::Number(1.0));
+        assert_eq!(
+            evaluate("return mod_e(-7, -3)").unwrap(),
+            Value::Number(2.0)
+        );
+        assert_eq!(
+            evaluate("return mod_e(-5.5, 2)").unwrap(),
+            Value::Number(0.5)
+        );
+        assert!(matches!(
+            evaluate("return mod_e('a', 2)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_gcd_and_lcm() {
+        let cases = [
+            ("return gcd(12, 18)", 6.0),
+            ("return lcm(12, 18)", 36.0),
+            ("return gcd(8, 15)", 1.0),
+            ("return lcm(8, 15)", 120.0),
+            ("return gcd(7, 7)", 7.0),
+            ("return lcm(7, 7)", 7.0),
+            ("return gcd(0, 5)", 5.0),
+            ("return lcm(0, 5)", 0.0),
+            ("return gcd(0, 0)", 0.0),
+            ("return lcm(0, 0)", 0.0),
+            ("return gcd(12, 8)", 4.0),
+            ("return lcm(4, 6)", 12.0),
+            ("return gcd(0.1 * 30, 9)", 3.0),
+            ("return lcm(67108864, 67108863)", 4503599560261632.0),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                evaluate(input).unwrap(),
+                Value::Number(expected),
+                "{}",
+                input
+            );
+        }
+
+        for input in [
+            "return gcd(-4, 6)",
+            "return lcm(4.5, 6)",
+            "return gcd(1, 10 ^ 20)",
+            "return lcm(4294967296, 4294967297)",
+            // 2^53 + 1, which would round to 2^53 as an f64
+            "return lcm(3, 3002399751580331)",
+        ] {
+            assert!(
+                matches!(evaluate(input), Err(CalculatorError::InvalidArgument(_))),
+                "{}",
+                input
+            );
+        }
+        assert!(matches!(
+            evaluate("return gcd('a', 6)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_is_even_and_is_odd() {
+        for (n, even) in [
+            ("0", true),
+            ("4", true),
+            ("7", false),
+            ("-4", true),
+            ("-3", false),
+            ("4.9", true),
+            ("-3.5", false),
+            ("0.5", true),
+            ("2 ^ 60", true),
+        ] {
+            assert_eq!(
+                evaluate(&format!("return is_even({})", n)).unwrap(),
+                Value::Bool(even),
+                "is_even({})",
+                n
+            );
+            assert_eq!(
+                evaluate(&format!("return is_odd({})", n)).unwrap(),
+                Value::Bool(!even),
+                "is_odd({})",
+                n
+            );
+        }
+
+        assert!(matches!(
+            evaluate("return is_even('4')"),
+            Err(CalculatorError::TypeError(_))
+        ));
+        assert!(matches!(
+            evaluate("return is_odd(true)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_approx_equal() {
+        assert_eq!(
+            evaluate("return 0.1 + 0.2 = 0.3").unwrap(),
+            Value::Bool(false)
+        );
+        assert_eq!(
+            evaluate("return approx_equal(0.1 + 0.2, 0.3, 0.000000001)").unwrap(),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            evaluate("return approx_equal(0.3, 0.1 + 0.2, 0)").unwrap(),
+            Value::Bool(false)
+        );
+        // The bound is inclusive
+        assert_eq!(
+            evaluate("return approx_equal(1, 1.5, 0.5)").unwrap(),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            evaluate("return approx_equal(1, 1.6, 0.5)").unwrap(),
+            Value::Bool(false)
+        );
+
+        assert!(matches!(
+            evaluate("return approx_equal(1, 1, -0.1)"),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            evaluate("return approx_equal('1', 1, 0.1)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_bitwise_functions() {
+        let eval = |input: &str| evaluate(input).unwrap();
+
+        assert_eq!(eval("return bit_and(6, 3)"), Value::Number(2.0));
+        assert_eq!(eval("return bit_or(4, 1)"), Value::Number(5.0));
+        assert_eq!(eval("return bit_xor(6, 3)"), Value::Number(5.0));
+        // Negative operands use two's complement
+        assert_eq!(eval("return bit_and(-1, 12)"), Value::Number(12.0));
+        assert_eq!(eval("return bit_xor(-1, 0)"), Value::Number(-1.0));
+        assert_eq!(
+            eval("return bit_or(2 ^ 53, 1)"),
+            Value::Number(2f64.powi(53) + 1.0)
+        );
+
+        for input in [
+            "return bit_and(1.5, 1)",
+            "return bit_or(1, 2 ^ 54)",
+            "return bit_xor(-(2 ^ 54), 1)",
+        ] {
+            assert!(
+                matches!(evaluate(input), Err(CalculatorError::InvalidArgument(_))),
+                "{}",
+                input
+            );
+        }
+        assert!(matches!(
+            evaluate("return bit_and('6', 3)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_factorial_and_combinations() {
+        let cases = [
+            ("return factorial(0)", 1.0),
+            ("return factorial(5)", 120.0),
+            ("return combinations(5, 2)", 10.0),
+            ("return combinations(5, 0)", 1.0),
+            ("return combinations(5, 5)", 1.0),
+            ("return combinations(0, 0)", 1.0),
+            ("return combinations(3, 4)", 0.0),
+            ("return combinations(52, 5)", 2598960.0),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                evaluate(input).unwrap(),
+                Value::Number(expected),
+                "{}",
+                input
+            );
+        }
+
+        let relative_error = |input: &str, expected: f64| match evaluate(input).unwrap() {
+            Value::Number(n) => ((n - expected) / expected).abs(),
+            other => panic!("{} returned {:?}", input, other),
+        };
+        assert!(relative_error("return combinations(60, 30)", 118264581564861424.0) < 1e-15);
+        assert!(relative_error("return factorial(170)", 7.257415615307994e306) < 1e-12);
+
+        for input in [
+            "return factorial(171)",
+            "return factorial(-1)",
+            "return factorial(2.5)",
+            "return combinations(-5, 2)",
+            "return combinations(5, 1.5)",
+            "return combinations(2000, 1000)",
+            // Overflows within a few hundred steps of a 10^11-step loop
+            "return combinations(200000000000, 100000000000)",
+        ] {
+            assert!(
+                matches!(evaluate(input), Err(CalculatorError::InvalidArgument(_))),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_pow_square_and_cube_match_operator() {
+        let evaluator = create_evaluator();
+        let eval = |input: &str| evaluator.evaluate(&Parser::new(input)?.parse()?);
+
+        for x in [0.0, -0.0, 1.1, -2.5, 1e-160, 1e155, f64::NAN] {
+            evaluator
+                .variable_cache
+                .set("x".to_string(), Value::Number(x));
+            let bits = |input: &str| match eval(input).unwrap() {
+                Value::Number(n) => n.to_bits(),
+                other => panic!("{} returned {:?}", input, other),
+            };
+
+            assert_eq!(bits("return pow(x, 1.5)"), bits("return x ^ 1.5"), "{}", x);
+            assert_eq!(bits("return pow(2, x)"), bits("return 2 ^ x"), "{}", x);
+            assert_eq!(bits("return square(x)"), bits("return x ^ 2"), "{}", x);
+            assert_eq!(bits("return cube(x)"), bits("return x ^ 3"), "{}", x);
+        }
+
+        assert_eq!(eval("return square(-3)").unwrap(), Value::Number(9.0));
+        assert_eq!(eval("return cube(-3)").unwrap(), Value::Number(-27.0));
+        for input in [
+            "return pow('a', 2)",
+            "return square('a')",
+            "return cube(true)",
+        ] {
+            assert_eq!(
+                eval(input),
+                Err(CalculatorError::TypeError(
+                    "Power requires numbers".to_string()
+                )),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_pow_and_log_growth() {
+        // Doubling time at 7% growth: log(2, 1.07) periods
+        let periods = match evaluate("return log(2, 1.07)").unwrap() {
+            Value::Number(n) => n,
+            other => panic!("log returned {:?}", other),
+        };
+        assert!((periods - 10.244768351058712).abs() < 1e-12);
+        assert_eq!(
+            evaluate("return pow(1.07, log(2, 1.07))").unwrap(),
+            evaluate("return 1.07 ^ log(2, 1.07)").unwrap()
+        );
+        assert_eq!(evaluate("return log(8, 2)").unwrap(), Value::Number(3.0));
+        assert!(matches!(
+            evaluate("return pow('a', 2)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_log() {
+        let number = |input: &str| match evaluate(input).unwrap() {
+            Value::Number(n) => n,
+            other => panic!("{} returned {:?}", input, other),
+        };
+
+        assert_eq!(number("return log(8, 2)"), 3.0);
+        assert!((number("return log(1024, 2)") - 10.0).abs() < 1e-12);
+        assert_eq!(number("return log(1, 7)"), 0.0);
+        for x in [0.5, 2.0, 1000.0, 12345.678] {
+            let n = number(&format!("return log({}, 10)", x));
+            assert!((n - f64::log10(x)).abs() < 1e-12, "log({}, 10) = {}", x, n);
+        }
+        assert!((number("return log(exp(3), exp(1))") - 3.0).abs() < 1e-12);
+        assert!((number("return log(0.25, 0.5)") - 2.0).abs() < 1e-12);
+
+        for (input, message) in [
+            ("return log(0, 2)", "value must be greater than 0"),
+            ("return log(-8, 2)", "value must be greater than 0"),
+            ("return log(8, 0)", "base must be greater than 0"),
+            ("return log(8, -2)", "base must be greater than 0"),
+            ("return log(8, 1)", "base must not be 1"),
+        ] {
+            match evaluate(input) {
+                Err(CalculatorError::InvalidArgument(e)) => {
+                    assert!(e.contains(message), "{}: {}", input, e)
+                }
+                other => panic!("{} returned {:?}", input, other),
+            }
+        }
+        assert!(matches!(
+            evaluate("return log('a', 2)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_div_and_modulo_sign_combinations() {
+        // (a, b, a mod b, mod_e(a, b), div(a, b))
+        for (a, b, modulo, euclidean, quotient) in [
+            (7.0, 3.0, 1.0, 1.0, 2.0),
+            (-7.0, 3.0, -1.0, 2.0, -3.0),
+            (7.0, -3.0, 1.0, 1.0, -3.0),
+            (-7.0, -3.0, -1.0, 2.0, 2.0),
+        ] {
+            let evaluator = create_evaluator();
+            evaluator
+                .variable_cache
+                .set("a".to_string(), Value::Number(a));
+            evaluator
+                .variable_cache
+                .set("b".to_string(), Value::Number(b));
+            let eval = |input: &str| evaluator.evaluate(&Parser::new(input)?.parse()?);
+
+            assert_eq!(eval("return a mod b").unwrap(), Value::Number(modulo));
+            assert_eq!(
+                eval("return mod_e(a, b)").unwrap(),
+                Value::Number(euclidean)
+            );
+            assert_eq!(eval("return div(a, b)").unwrap(), Value::Number(quotient));
+            assert_eq!(eval("return a div b").unwrap(), Value::Number(quotient));
+
+            // The floored remainder pairs with `div` to rebuild the dividend; it takes
+            // the divisor's sign, so it matches mod_e whenever b is positive
+            let remainder = match eval("return a - b * (a div b)").unwrap() {
+                Value::Number(r) => r,
+                other => panic!("unexpected {:?}", other),
+            };
+            assert!(remainder.abs() < b.abs() && remainder * b >= 0.0);
+            assert_eq!(
+                eval("return b * (a div b) + (a - b * (a div b))").unwrap(),
+                Value::Number(a)
+            );
+            if b > 0.0 {
+                assert_eq!(
+                    eval("return b * (a div b) + mod_e(a, b)").unwrap(),
+                    Value::Number(a)
+                );
+            }
+        }
+
+        assert_eq!(evaluate("return div(7.5, 2)").unwrap(), Value::Number(3.0));
+        assert_eq!(evaluate("return 7.5 div 2").unwrap(), Value::Number(3.0));
+
+        let evaluator = create_evaluator();
+        evaluator
+            .variable_cache
+            .set("zero".to_string(), Value::Number(0.0));
+        let eval = |input: &str| evaluator.evaluate(&Parser::new(input)?.parse()?);
+        for input in [
+            "return 5 mod zero",
+            "return mod_e(5, zero)",
+            "return div(5, zero)",
+            "return 5 div zero",
+        ] {
+            assert_eq!(
+                eval(input),
+                Err(CalculatorError::DivisionByZero),
+                "{}",
+                input
+            );
+        }
+        assert!(matches!(
+            evaluate("return div('a', 2)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_npv_and_irr() {
+        let close = |input: &str, expected: f64| match evaluate(input).unwrap() {
+            Value::Number(n) => assert!((n - expected).abs() < 1e-6, "{} = {}", input, n),
+            other => panic!("{} returned {:?}", input, other),
+        };
+
+        close("return npv(0.1, -10000, 3000, 4200, 6800)", 1188.443412);
+        close(
+            "return npv(0.08, 8000, 9200, 10000, 12000, 14500) - 40000",
+            1922.061555,
+        );
+        close(
+            "return irr(-70000, 12000, 15000, 18000, 21000, 26000)",
+            0.086631,
+        );
+        close("return irr(-70000, 12000, 15000, 18000, 21000)", -0.021245);
+        close("return irr(-100, 110)", 0.1);
+
+        // The rate returned by irr discounts the flows to zero
+        let evaluator = create_evaluator();
+        evaluator.variable_cache.set(
+            "flows".to_string(),
+            Value::from(vec![-1000.0, 300.0, 400.0, 500.0]),
+        );
+        let program = Parser::new("return npv(irr(flows), flows)")
+            .unwrap()
+            .parse()
+            .unwrap();
+        match evaluator.evaluate(&program).unwrap() {
+            Value::Number(n) => assert!(n.abs() < 1e-6, "{}", n),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_irr_errors() {
+        assert!(matches!(
+            evaluate("return irr(100, 200, 300)"),
+            Err(CalculatorError::EvalError(_))
+        ));
+        // Mixed signs, but 1 - 3x + 3x^2 has no real root
+        assert!(matches!(
+            evaluate("return irr(1, -3, 3)"),
+            Err(CalculatorError::EvalError(_))
+        ));
+        assert!(matches!(
+            evaluate("return npv(0.1)"),
+            Err(CalculatorError::ParseError(_))
+        ));
+        assert!(matches!(
+            evaluate("return npv('x', 1, 2)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_pct_change() {
+        assert_eq!(
+            evaluate("return pct_change(80, 100)").unwrap(),
+            Value::Number(0.25)
+        );
+        assert_eq!(
+            evaluate("return pct_change(200, 150)").unwrap(),
+            Value::Number(-0.25)
+        );
+        assert_eq!(
+            evaluate("return pct_change(0, 10)"),
+            Err(CalculatorError::DivisionByZero)
+        );
+        assert!(matches!(
+            evaluate("return pct_change('80', 100)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_pipe_operator() {
+        assert_eq!(
+            evaluate("return 2.3 |> ceil |> square").unwrap(),
+            Value::Number(9.0)
+        );
+        assert_eq!(
+            evaluate("return '2024-03-15' |> month").unwrap(),
+            Value::Number(3.0)
+        );
+    }
+
+    #[test]
+    fn test_lookup() {
+        let evaluator = create_evaluator();
+        evaluator
+            .variable_cache
+            .set("rate_US".to_string(), Value::Number(0.07));
+        evaluator
+            .variable_cache
+            .set("country".to_string(), Value::from("US"));
+        let eval = |input: &str| {
+            let program = Parser::new(input).unwrap().parse().unwrap();
+            evaluator.evaluate(&program)
+        };
+
+        assert_eq!(
+            eval("return lookup('rate_', 'US')"),
+            Ok(Value::Number(0.07))
+        );
+        assert_eq!(
+            eval("return lookup('rate_', country)"),
+            Ok(Value::Number(0.07))
+        );
+        assert_eq!(
+            eval("return lookup('rate_', 'EU')"),
+            Err(CalculatorError::VariableNotFound("rate_EU".to_string()))
+        );
+        assert!(matches!(
+            eval("return lookup('rate_', 1)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_pad_number() {
+        assert_eq!(
+            evaluate("return pad_number(42, 5)").unwrap(),
+            Value::from("00042")
+        );
+        assert_eq!(
+            evaluate("return pad_number(12345, 5)").unwrap(),
+            Value::from("12345")
+        );
+        for input in [
+            "return pad_number(123456, 5)",
+            "return pad_number(4.2, 5)",
+            "return pad_number(-42, 5)",
+            "return pad_number(1, 70000)",
+        ] {
+            assert!(
+                matches!(evaluate(input), Err(CalculatorError::InvalidArgument(_))),
+                "{}",
+                input
+            );
+        }
+        assert!(matches!(
+            evaluate("return pad_number('42', 5)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_format_fixed() {
+        let cases = [
+            ("return format_fixed(3.14159, 8, 2)", "    3.14"),
+            ("return format_fixed(-42, 6, 1)", " -42.0"),
+            ("return format_fixed(7, 0, 0)", "7"),
+            // Too wide for the field: kept whole, not truncated
+            ("return format_fixed(123456.789, 5, 2)", "123456.79"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                evaluate(input).unwrap(),
+                Value::String(expected.to_string()),
+                "{}",
+                input
+            );
+        }
+
+        assert!(matches!(
+            evaluate("return format_fixed(1, -1, 2)"),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            evaluate("return format_fixed(1, 5, 0.5)"),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            evaluate("return format_fixed(1, 70000, 0)"),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            evaluate("return format_fixed(1, 5, 300000000)"),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            evaluate("return format_fixed('1', 5, 2)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_format_number() {
+        let cases = [
+            ("return format_number(0.30000000000000004, 2)", "0.30"),
+            ("return format_number(0.1 + 0.2, 2)", "0.30"),
+            ("return format_number(2.5, 0)", "3"),
+            ("return format_number(1234567.891, 2)", "1234567.89"),
+            ("return format_number(1234567.891, 2, ',')", "1,234,567.89"),
+            ("return format_number(-1234.5, 1, ' ')", "-1 234.5"),
+            ("return format_number(999, 0, ',')", "999"),
+            ("return format_number(7, 3)", "7.000"),
+            ("return format_number(-0.001, 2)", "0.00"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                evaluate(input).unwrap(),
+                Value::String(expected.to_string()),
+                "{}",
+                input
+            );
+        }
+
+        assert!(matches!(
+            evaluate("return format_number(1, -1)"),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            evaluate("return format_number(1, 1.5)"),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            evaluate("return format_number(1, 300000000)"),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+        assert_eq!(
+            evaluate("return format_number(1, 100)").unwrap(),
+            Value::String(format!("1.{}", "0".repeat(100)))
+        );
+        assert_eq!(
+            evaluate("return format_number(10 ^ 300, 10)").unwrap(),
+            Value::String(format!("{:.10}", 1e300))
+        );
+        assert!(matches!(
+            evaluate("return format_number('1', 2)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(
+            evaluate("return split('a,b,,c', ',')").unwrap(),
+            Value::from(vec!["a", "b", "", "c"])
+        );
+        assert_eq!(
+            evaluate("return split('one -- two', ' -- ')").unwrap(),
+            Value::from(vec!["one", "two"])
+        );
+        assert_eq!(
+            evaluate("return split('abc', ';')").unwrap(),
+            Value::from(vec!["abc"])
+        );
+        assert_eq!(
+            evaluate("return count(split('x|y|z', '|'))").unwrap(),
+            Value::Number(3.0)
+        );
+        assert!(matches!(
+            evaluate("return split('abc', '')"),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            evaluate("return split(12, ',')"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_fv_and_cagr() {
+        let close = |input: &str, expected: f64| match evaluate(input).unwrap() {
+            Value::Number(n) => assert!((n - expected).abs() < 1e-9, "{} = {}", input, n),
+            other => panic!("{} returned {:?}", input, other),
+        };
+
+        // 1000 * 1.05^3 = 1157.625
+        close("return fv(0.05, 3, 1000)", 1157.625);
+        close("return fv(0.01, 12, 100)", 112.68250301319698);
+        close("return fv(0.05, 0, 1000)", 1000.0);
+        close("return fv(0, 10, 1000)", 1000.0);
+        close("return fv(0.05, 3, -1000)", -1157.625);
+
+        // fv keeps the sign of pv, so it reads as money received by pmt
+        close(
+            "return pmt(0.05, 3, 0, fv(0.05, 3, 1000)) - pmt(0.05, 3, 1000)",
+            0.0,
+        );
+        close(
+            "return pmt(0.05, 3, 0, fv(0.05, 3, 1000))",
+            -367.2085646312448,
+        );
+
+        // 100 -> 200 over 2 years: sqrt(2) - 1
+        close("return cagr(100, 200, 2)", std::f64::consts::SQRT_2 - 1.0);
+        close("return cagr(1000, 1157.625, 3)", 0.05);
+        close("return cagr(200, 100, 1)", -0.5);
+    }
+
+    #[test]
+    fn test_fv_and_cagr_domain_errors() {
+        for input in [
+            "return fv(0.05, -1, 1000)",
+            "return cagr(100, 200, 0)",
+            "return cagr(100, 200, -2)",
+            "return cagr(0, 200, 2)",
+            "return cagr(-100, 200, 2)",
+            "return cagr(100, -1, 2)",
+        ] {
+            assert!(
+                matches!(evaluate(input), Err(CalculatorError::InvalidArgument(_))),
+                "{}",
+                input
+            );
+        }
+        assert!(matches!(
+            evaluate("return fv('5%', 3, 1000)"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_join() {
+        let evaluator = create_evaluator();
+        evaluator.variable_cache.set(
+            "parts".to_string(),
+            Value::Array(vec![
+                Value::String("a".to_string()),
+                Value::Number(1.5),
+                Value::Bool(true),
+            ]),
+        );
+        evaluator
+            .variable_cache
+            .set("empty".to_string(), Value::Array(vec![]));
+        let eval = |input: &str| evaluator.evaluate(&Parser::new(input)?.parse()?);
+
+        assert_eq!(
+            eval("return join(parts, ', ')").unwrap(),
+            Value::String("a, 1.5, true".to_string())
+        );
+        assert_eq!(
+            eval("return join(empty, ',')").unwrap(),
+            Value::String(String::new())
+        );
+        assert_eq!(
+            eval("return join(split('x-y-z', '-'), '+')").unwrap(),
+            Value::String("x+y+z".to_string())
+        );
+        assert!(matches!(
+            eval("return join('abc', ',')"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_median_and_percentile() {
+        let evaluator = create_evaluator();
+        let set = |name: &str, values: Vec<f64>| {
+            evaluator
+                .variable_cache
+                .set(name.to_string(), Value::from(values))
+        };
+        set("odd", vec![7.0, 1.0, 3.0]);
+        set("even", vec![4.0, 1.0, 3.0, 2.0]);
+        set("latencies", vec![15.0, 20.0, 35.0, 40.0, 50.0]);
+        let eval = |input: &str| evaluator.evaluate(&Parser::new(input)?.parse()?);
+
+        assert_eq!(eval("return median(odd)").unwrap(), Value::Number(3.0));
+        assert_eq!(eval("return median(even)").unwrap(), Value::Number(2.5));
+        assert_eq!(
+            eval("return median(5, 1, 9, 3)").unwrap(),
+            Value::Number(4.0)
+        );
+
+        // PERCENTILE.INC reference values
+        assert_eq!(
+            eval("return percentile(latencies, 0)").unwrap(),
+            Value::Number(15.0)
+        );
+        assert_eq!(
+            eval("return percentile(latencies, 100)").unwrap(),
+            Value::Number(50.0)
+        );
+        assert_eq!(
+            eval("return percentile(latencies, 40)").unwrap(),
+            Value::Number(29.0)
+        );
+        assert_eq!(
+            eval("return percentile(latencies, 90)").unwrap(),
+            Value::Number(46.0)
+        );
+        assert_eq!(
+            eval("return percentile(even, 50)").unwrap(),
+            Value::Number(2.5)
+        );
+        assert_eq!(
+            eval("return percentile(42, 75)").unwrap(),
+            Value::Number(42.0)
+        );
+
+        // The variable itself is not reordered
+        assert_eq!(
+            evaluator.variable_cache.get("odd"),
+            Some(Value::from(vec![7.0, 1.0, 3.0]))
+        );
+    }
+
+    #[test]
+    fn test_variance_and_stddev() {
+        let evaluator = create_evaluator();
+        evaluator.variable_cache.set(
+            "values".to_string(),
+            Value::from(vec![2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]),
+        );
+        // A large mean with a tiny spread, where sum-of-squares cancellation loses every digit
+        evaluator.variable_cache.set(
+            "offset".to_string(),
+            Value::from(vec![1e9 + 4.0, 1e9 + 7.0, 1e9 + 13.0, 1e9 + 16.0]),
+        );
+        let eval = |input: &str| match evaluator.evaluate(&Parser::new(input)?.parse()?)? {
+            Value::Number(n) => Ok(n),
+            other => panic!("expected a number, got {:?}", other),
+        };
+
+        assert_eq!(eval("return variance_p(values)").unwrap(), 4.0);
+        assert_eq!(eval("return stddev_p(values)").unwrap(), 2.0);
+        assert!((eval("return variance(values)").unwrap() - 32.0 / 7.0).abs() < 1e-12);
+        assert!((eval("return stddev(values)").unwrap() - (32.0f64 / 7.0).sqrt()).abs() < 1e-12);
+        assert_eq!(eval("return variance(1, 3)").unwrap(), 2.0);
+
+        assert!((eval("return variance(offset)").unwrap() - 30.0).abs() < 1e-6);
+        assert!((eval("return variance_p(offset)").unwrap() - 22.5).abs() < 1e-6);
+
+        assert_eq!(eval("return variance_p(5)").unwrap(), 0.0);
+        assert!(matches!(
+            eval("return variance(5)"),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            eval("return stddev(5)"),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+        evaluator
+            .variable_cache
+            .set("empty".to_string(), Value::Array(vec![]));
+        assert!(matches!(
+            eval("return stddev_p(empty)"),
+            Err(CalculatorError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            eval("return variance(1, 'x')"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_median_and_percentile_errors() {
+        let evaluator = create_evaluator();
+        evaluator
+            .variable_cache
+            .set("empty".to_string(), Value::Array(vec![]));
+        evaluator
+            .variable_cache
+            .set("with_nan".to_string(), Value::from(vec![1.0, f64::NAN]));
+        let eval = |input: &str| evaluator.evaluate(&Parser::new(input)?.parse()?);
+
+        for input in [
+            "return median(empty)",
+            "return percentile(empty, 50)",
+            "return percentile(5, -1)",
+            "return percentile(5, 100.5)",
+            "return median(with_nan)",
+        ] {
+            assert!(
+                matches!(eval(input), Err(CalculatorError::InvalidArgument(_))),
+                "{}",
+                input
+            );
+        }
+        assert!(matches!(
+            eval("return median(1, 'x')"),
+            Err(CalculatorError::TypeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_max_eval_steps_limits_each_evaluation() {
+        let evaluator = create_evaluator().with_options(EngineOptions {
+            max_eval_steps: Some(1_000),
+            ..Default::default()
+        });
+        evaluator
+            .variable_cache
+            .set("x".to_string(), Value::Number(1.0));
+
+        // A flat call keeps the tree shallow, and the variable stops constant folding
+        let input = format!("return sum({})", vec!["x"; 1_000_000].join(", "));
+        let program = Parser::new(&input).unwrap().parse().unwrap();
+        match evaluator.evaluate(&program) {
+            Err(CalculatorError::LimitExceeded(msg)) => assert!(msg.contains("1000"), "{}", msg),
+            other => panic!("expected LimitExceeded, got {:?}", other),
+        }
+
+        // The count restarts with each evaluation
+        let small = Parser::new("return sum(x, x, x)").unwrap().parse().unwrap();
+        for _ in 0..1_000 {
+            assert_eq!(evaluator.evaluate(&small), Ok(Value::Number(3.0)));
+        }
+
+        // Without a budget the same expression evaluates
+        let unlimited = create_evaluator();
+        unlimited
+            .variable_cache
+            .set("x".to_string(), Value::Number(1.0));
+        assert_eq!(unlimited.evaluate(&program), Ok(Value::Number(1_000_000.0)));
+    }
+
+    #[test]
+    fn test_cancelled_evaluation_stops() {
+        let token = CancellationToken::new();
+        let evaluator = create_evaluator().with_cancellation(Some(token.clone()));
+        evaluator
+            .variable_cache
+            .set("x".to_string(), Value::Number(1.0));
+        let input = format!("return sum({})", vec!["x"; 10_000].join(", "));
+        let program = Parser::new(&input).unwrap().parse().unwrap();
+
+        assert_eq!(evaluator.evaluate(&program), Ok(Value::Number(10_000.0)));
+        token.cancel();
+        assert_eq!(
+            evaluator.evaluate(&program),
+            Err(CalculatorError::Cancelled)
+        );
+    }
+
+    #[test]
+    fn test_strict_math_names_the_overflowing_operation() {
+        let strict = create_evaluator().with_options(EngineOptions {
+            strict_math: true,
+            ..Default::default()
+        });
+        strict
+            .variable_cache
+            .set("big".to_string(), Value::Number(1000.0));
+        strict
+            .variable_cache
+            .set("huge".to_string(), Value::Number(1e200));
+        strict
+            .variable_cache
+            .set("top".to_string(), Value::Number(f64::MAX));
+        let eval = |evaluator: &Evaluator, input: &str| {
+            let program = Parser::new(input).unwrap().parse().unwrap();
+            evaluator.evaluate(&program)
+        };
+
+        for (input, operation) in [
+            ("return exp(big)", "Exp of (1000.0)"),
+            ("return 10 ^ big", "Power of (10.0, 1000.0)"),
+            ("return huge * huge", "Multiplication of (1e200, 1e200)"),
+            ("return fv(10, big, 1)", "fv(10, big, 1) produced inf"),
+            (
+                "return variance_p(huge, -huge)",
+                "variance_p(huge, -huge) produced inf",
+            ),
+            (
+                "return cagr(0.0000001, huge, 0.01)",
+                "cagr(0.0000001, huge, 0.01) produced inf",
+            ),
+            ("return 1 + avg(top, top)", "avg(top, top) produced inf"),
+        ] {
+            match eval(&strict, input) {
+                Err(CalculatorError::EvalError(msg)) => {
+                    assert!(msg.starts_with(operation), "{}: {}", input, msg)
+                }
+                other => panic!("{} returned {:?}", input, other),
+            }
+        }
+
+        // Finite results are unaffected, and the default stays permissive
+        assert_eq!(eval(&strict, "return big * 2"), Ok(Value::Number(2000.0)));
+        let permissive = create_evaluator();
+        permissive
+            .variable_cache
+            .set("big".to_string(), Value::Number(1000.0));
+        assert_eq!(
+            eval(&permissive, "return exp(big)"),
+            Ok(Value::Number(f64::INFINITY))
+        );
+    }
 }
@@ -24,7 +24,14 @@ pub enum Token {
     Rnd,
     Ceil,
     Floor,
+    MRound,
+    FloorTo,
+    CeilTo,
     Exp,
+    Log,
+    Pow,
+    Square,
+    Cube,
     Year,
     Month,
     Day,
@@ -32,9 +39,48 @@ pub enum Token {
     Error,
     AddDays,
     GetDiffDays,
+    GetDiffHours,
+    GetDiffMinutes,
     PaddedString,
+    FormatNumber,
     GetDiffMonths,
     GetOutputFrom,
+    BusinessDaysBetween,
+    Iif,
+    Date,
+    Sum,
+    Avg,
+    Count,
+    Product,
+    Pmt,
+    ModE,
+    Div,
+    Gcd,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Lcm,
+    Factorial,
+    IsEven,
+    IsOdd,
+    Combinations,
+    Npv,
+    Irr,
+    Split,
+    Fv,
+    Cagr,
+    ApproxEqual,
+    FormatFixed,
+    PctChange,
+    Lookup,
+    PadNumber,
+    Join,
+    Median,
+    Percentile,
+    Variance,
+    VarianceP,
+    Stddev,
+    StddevP,
 
     // Operators
     Plus,
@@ -49,6 +95,7 @@ pub enum Token {
     GreaterThanOrEqual,
     LessThanOrEqual,
     Not,
+    Pipe,
 
     // Delimiters
     LeftParen,
@@ -59,24 +106,53 @@ pub enum Token {
     Eof,
 }
 
+/// A token with the byte range it was read from, as returned by
+/// [`Lexer::tokenize_with_positions`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenSpan {
+    pub token: Token,
+    /// Byte offset of the token's first character in the input
+    pub start: usize,
+    /// Byte offset just past the token's last character
+    pub end: usize,
+}
+
 pub struct Lexer {
     input: Vec<char>,
+    /// Byte offset of each character in `input`, followed by the input's length
+    byte_offsets: Vec<usize>,
     position: usize,
+    /// Position where the token being read began
+    token_start: usize,
 }
 
 impl Lexer {
     pub fn new(input: &str) -> Self {
+        let mut byte_offsets: Vec<usize> = input.char_indices().map(|(i, _)| i).collect();
+        byte_offsets.push(input.len());
         Self {
             input: input.chars().collect(),
+            byte_offsets,
             position: 0,
+            token_start: 0,
         }
     }
 
     pub fn tokenize(&mut self) -> Result<Vec<Token>> {
+        self.tokenize_with_positions()
+            .map(|spans| spans.into_iter().map(|span| span.token).collect())
+    }
+
+    /// Tokenizes the input like [`Lexer::tokenize`], pairing each token with its
+    /// start and end byte offsets, e.g. for editor highlighting.
+    ///
+    /// Comments and whitespace produce no tokens. The final [`Token::Eof`] has an
+    /// empty span at the end of the input.
+    pub fn tokenize_with_positions(&mut self) -> Result<Vec<TokenSpan>> {
         let mut tokens = Vec::new();
 
         while self.position < self.input.len() {
-            self.skip_whitespace_and_comments();
+            self.skip_whitespace_and_comments()?;
 
             if self.position >= self.input.len() {
                 break;
@@ -84,20 +160,30 @@ impl Lexer {
 
             let token = self.next_token()?;
             if token != Token::Eof {
-                tokens.push(token);
+                tokens.push(TokenSpan {
+                    token,
+                    start: self.byte_offsets[self.token_start],
+                    end: self.byte_offsets[self.position],
+                });
             }
         }
 
-        tokens.push(Token::Eof);
+        let end = self.byte_offsets[self.input.len()];
+        tokens.push(TokenSpan {
+            token: Token::Eof,
+            start: end,
+            end,
+        });
         Ok(tokens)
     }
 
     fn next_token(&mut self) -> Result<Token> {
+        self.token_start = self.position;
         let ch = self.current_char();
 
         match ch {
             '0'..='9' => self.read_number(),
-            '\'' => self.read_string(),
+            '\'' | '"' => self.read_string_with_delimiter(ch),
             'a'..='z' | 'A'..='Z' | '_' => self.read_identifier_or_keyword(),
             '+' => {
                 self.advance();
@@ -160,6 +246,11 @@ impl Lexer {
                 self.advance();
                 Ok(Token::Comma)
             }
+            '|' if self.peek() == Some('>') => {
+                self.advance();
+                self.advance();
+                Ok(Token::Pipe)
+            }
             _ => Err(CalculatorError::ParseError(format!(
                 "Unexpected character: {}",
                 ch
@@ -168,20 +259,15 @@ impl Lexer {
     }
 
     fn read_number(&mut self) -> Result<Token> {
-        let start = self.position;
-
-        while self.position < self.input.len() && self.current_char().is_ascii_digit() {
-            self.advance();
-        }
+        let mut num_str = String::new();
+        self.read_digits(&mut num_str)?;
 
         if self.position < self.input.len() && self.current_char() == '.' {
+            num_str.push('.');
             self.advance();
-            while self.position < self.input.len() && self.current_char().is_ascii_digit() {
-                self.advance();
-            }
+            self.read_digits(&mut num_str)?;
         }
 
-        let num_str: String = self.input[start..self.position].iter().collect();
         let num = num_str
             .parse::<f64>()
             .map_err(|e| CalculatorError::ParseError(format!("Invalid number: {}", e)))?;
@@ -189,11 +275,39 @@ impl Lexer {
         Ok(Token::Number(num))
     }
 
-    fn read_string(&mut self) -> Result<Token> {
-        self.advance(); // skip opening '
+    /// Appends a run of digits to `digits`, skipping `_` separators such as `1_000`.
+    ///
+    /// A separator must sit between two digits, so `123_`, `1_.5`, and `1._5` are errors.
+    fn read_digits(&mut self, digits: &mut String) -> Result<()> {
+        while self.position < self.input.len() {
+            let ch = self.current_char();
+            if ch.is_ascii_digit() {
+                digits.push(ch);
+            } else if ch == '_' {
+                let after_digit = digits.ends_with(|c: char| c.is_ascii_digit());
+                let before_digit = self.peek().is_some_and(|c| c.is_ascii_digit());
+                if !after_digit || !before_digit {
+                    return Err(CalculatorError::ParseError(format!(
+                        "Misplaced '_' in number literal '{}_'",
+                        digits
+                    )));
+                }
+            } else {
+                break;
+            }
+            self.advance();
+        }
+        Ok(())
+    }
+
+    /// Reads a string quoted with `delimiter`, either `'` or `"`.
+    ///
+    /// A backslash escapes the next character, so `'it\'s'` and `"say \"hi\""` work.
+    fn read_string_with_delimiter(&mut self, delimiter: char) -> Result<Token> {
+        self.advance(); // skip opening quote
         let mut result = String::new();
 
-        while self.position < self.input.len() && self.current_char() != '\'' {
+        while self.position < self.input.len() && self.current_char() != delimiter {
             let ch = self.current_char();
             if ch == '\\' {
                 self.advance();
@@ -213,7 +327,7 @@ impl Lexer {
             ));
         }
 
-        self.advance(); // skip closing '
+        self.advance(); // skip closing quote
         Ok(Token::String(result))
     }
 
@@ -230,41 +344,31 @@ impl Lexer {
         }
 
         let text: String = self.input[start..self.position].iter().collect();
-        let lower = text.to_lowercase();
-
-        let token = match lower.as_str() {
-            "if" => Token::If,
-            "then" => Token::Then,
-            "else" => Token::Else,
-            "end" => Token::End,
-            "return" => Token::Return,
-            "or" => Token::Or,
-            "and" => Token::And,
-            "mod" => Token::Mod,
-            "max" => Token::Max,
-            "min" => Token::Min,
-            "rnd" => Token::Rnd,
-            "ceil" => Token::Ceil,
-            "floor" => Token::Floor,
-            "exp" => Token::Exp,
-            "year" => Token::Year,
-            "month" => Token::Month,
-            "day" => Token::Day,
-            "substr" => Token::Substr,
-            "error" => Token::Error,
-            "add_days" => Token::AddDays,
-            "get_diff_days" => Token::GetDiffDays,
-            "padded_string" => Token::PaddedString,
-            "get_diff_months" => Token::GetDiffMonths,
-            "get_output_from" => Token::GetOutputFrom,
-            "true" | "false" => Token::Bool(lower == "true"),
-            _ => Token::Identifier(text),
-        };
-
-        Ok(token)
+
+        // `_123` reads as a number with a leading separator, not as a name
+        if text.starts_with('_')
+            && text.contains(|c: char| c.is_ascii_digit())
+            && text.chars().all(|c| c == '_' || c.is_ascii_digit())
+        {
+            return Err(CalculatorError::ParseError(format!(
+                "Misplaced '_' in number literal '{}'",
+                text
+            )));
+        }
+
+        // Namespaced formula names like `pricing.base` are only valid as
+        // `get_output_from` string arguments, never as bare identifiers
+        if self.position < self.input.len() && self.current_char() == '.' {
+            return Err(CalculatorError::ParseError(format!(
+                "Unexpected '.' after '{}': qualified names must be quoted, as in get_output_from('{}.name')",
+                text, text
+            )));
+        }
+
+        Ok(keyword(&text.to_lowercase()).unwrap_or(Token::Identifier(text)))
     }
 
-    fn skip_whitespace_and_comments(&mut self) {
+    fn skip_whitespace_and_comments(&mut self) -> Result<()> {
         while self.position < self.input.len() {
             let ch = self.current_char();
 
@@ -279,18 +383,26 @@ impl Lexer {
                 // Block comment
                 self.advance();
                 self.advance();
-                while self.position < self.input.len() - 1 {
+                let mut terminated = false;
+                while self.position < self.input.len() {
                     if self.current_char() == '*' && self.peek() == Some('/') {
                         self.advance();
                         self.advance();
+                        terminated = true;
                         break;
                     }
                     self.advance();
                 }
+                if !terminated {
+                    return Err(CalculatorError::ParseError(
+                        "Unterminated block comment".to_string(),
+                    ));
+                }
             } else {
                 break;
             }
         }
+        Ok(())
     }
 
     fn current_char(&self) -> char {
@@ -314,6 +426,91 @@ impl Lexer {
     }
 }
 
+/// Returns the token for a reserved word, given in lowercase.
+fn keyword(lower: &str) -> Option<Token> {
+    let token = match lower {
+        "if" => Token::If,
+        "then" => Token::Then,
+        "else" => Token::Else,
+        "end" => Token::End,
+        "return" => Token::Return,
+        "or" => Token::Or,
+        "and" => Token::And,
+        "mod" => Token::Mod,
+        "max" => Token::Max,
+        "min" => Token::Min,
+        "rnd" => Token::Rnd,
+        "ceil" => Token::Ceil,
+        "mround" => Token::MRound,
+        "floor_to" => Token::FloorTo,
+        "ceil_to" => Token::CeilTo,
+        "floor" => Token::Floor,
+        "exp" => Token::Exp,
+        "log" => Token::Log,
+        "pow" => Token::Pow,
+        "square" => Token::Square,
+        "cube" => Token::Cube,
+        "year" => Token::Year,
+        "month" => Token::Month,
+        "day" => Token::Day,
+        "substr" => Token::Substr,
+        "error" => Token::Error,
+        "add_days" => Token::AddDays,
+        "get_diff_days" => Token::GetDiffDays,
+        "get_diff_hours" => Token::GetDiffHours,
+        "get_diff_minutes" => Token::GetDiffMinutes,
+        "padded_string" => Token::PaddedString,
+        "format_number" => Token::FormatNumber,
+        // `difference_in_months` is the deprecated spelling, kept so existing formulas still parse
+        "get_diff_months" | "difference_in_months" => Token::GetDiffMonths,
+        "get_output_from" => Token::GetOutputFrom,
+        "business_days_between" => Token::BusinessDaysBetween,
+        "iif" => Token::Iif,
+        "date" => Token::Date,
+        "sum" => Token::Sum,
+        "avg" => Token::Avg,
+        "count" => Token::Count,
+        "product" => Token::Product,
+        "pmt" => Token::Pmt,
+        "mod_e" => Token::ModE,
+        "div" => Token::Div,
+        "gcd" => Token::Gcd,
+        "bit_and" => Token::BitAnd,
+        "bit_or" => Token::BitOr,
+        "bit_xor" => Token::BitXor,
+        "lcm" => Token::Lcm,
+        "factorial" => Token::Factorial,
+        "is_even" => Token::IsEven,
+        "is_odd" => Token::IsOdd,
+        "combinations" => Token::Combinations,
+        "npv" => Token::Npv,
+        "irr" => Token::Irr,
+        "split" => Token::Split,
+        "fv" => Token::Fv,
+        "cagr" => Token::Cagr,
+        "approx_equal" => Token::ApproxEqual,
+        "format_fixed" => Token::FormatFixed,
+        "pct_change" => Token::PctChange,
+        "lookup" => Token::Lookup,
+        "pad_number" => Token::PadNumber,
+        "join" => Token::Join,
+        "median" => Token::Median,
+        "percentile" => Token::Percentile,
+        "variance" => Token::Variance,
+        "variance_p" => Token::VarianceP,
+        "stddev" => Token::Stddev,
+        "stddev_p" => Token::StddevP,
+        "true" | "false" => Token::Bool(lower == "true"),
+        _ => return None,
+    };
+    Some(token)
+}
+
+/// Returns `true` if `word` is a keyword or built-in function name, in any case.
+pub(crate) fn is_keyword(word: &str) -> bool {
+    keyword(&word.to_lowercase()).is_some()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -326,6 +523,37 @@ mod tests {
         assert_eq!(tokens[1], Token::Number(3.15));
     }
 
+    #[test]
+    fn test_tokenize_numbers_with_separators() {
+        let tokens = Lexer::new("1_000_000 0.123_456 1_0.0_1")
+            .tokenize()
+            .unwrap();
+        assert_eq!(tokens[0], Token::Number(1000000.0));
+        assert_eq!(tokens[1], Token::Number(0.123456));
+        assert_eq!(tokens[2], Token::Number(10.01));
+
+        let tokens = Lexer::new("3.141_59").tokenize().unwrap();
+        assert_eq!(tokens[0], Token::Number("3.14159".parse().unwrap()));
+
+        for input in [
+            "_123", "_5", "123_", "1_.0", "1._0", "1__0", "1__000", "2.5_",
+        ] {
+            assert!(
+                matches!(
+                    Lexer::new(input).tokenize(),
+                    Err(CalculatorError::ParseError(_))
+                ),
+                "{} was accepted",
+                input
+            );
+        }
+
+        // Underscores in names are unaffected
+        let tokens = Lexer::new("_rate rate_1").tokenize().unwrap();
+        assert_eq!(tokens[0], Token::Identifier("_rate".to_string()));
+        assert_eq!(tokens[1], Token::Identifier("rate_1".to_string()));
+    }
+
     #[test]
     fn test_tokenize_string() {
         let mut lexer = Lexer::new("'hello world'");
@@ -333,6 +561,32 @@ mod tests {
         assert_eq!(tokens[0], Token::String("hello world".to_string()));
     }
 
+    #[test]
+    fn test_tokenize_double_quoted_string() {
+        let tokens = Lexer::new(r#""hello world" 'it''s' "it's" "say \"hi\"""#)
+            .tokenize()
+            .unwrap();
+        assert_eq!(
+            tokens[0],
+            Lexer::new("'hello world'").tokenize().unwrap()[0]
+        );
+        assert_eq!(tokens[1], Token::String("it".to_string()));
+        assert_eq!(tokens[2], Token::String("s".to_string()));
+        assert_eq!(tokens[3], Token::String("it's".to_string()));
+        assert_eq!(tokens[4], Token::String("say \"hi\"".to_string()));
+
+        for input in [r#""foo'"#, r#"'foo""#] {
+            assert_eq!(
+                Lexer::new(input).tokenize(),
+                Err(CalculatorError::ParseError(
+                    "Unterminated string".to_string()
+                )),
+                "{}",
+                input
+            );
+        }
+    }
+
     #[test]
     fn test_tokenize_keywords() {
         let mut lexer = Lexer::new("if then else end return");
@@ -344,9 +598,40 @@ mod tests {
         assert_eq!(tokens[4], Token::Return);
     }
 
+    #[test]
+    fn test_keywords_ignore_case() {
+        let mut lexer = Lexer::new("true True TRUE false FALSE If RETURN");
+        let tokens = lexer.tokenize().unwrap();
+        assert_eq!(tokens[0], Token::Bool(true));
+        assert_eq!(tokens[1], Token::Bool(true));
+        assert_eq!(tokens[2], Token::Bool(true));
+        assert_eq!(tokens[3], Token::Bool(false));
+        assert_eq!(tokens[4], Token::Bool(false));
+        assert_eq!(tokens[5], Token::If);
+        assert_eq!(tokens[6], Token::Return);
+        assert!(is_keyword("True"));
+    }
+
+    #[test]
+    fn test_tokenize_with_positions() {
+        let input = "return 'é' + max(x1, 2.5) // done";
+        let spans = Lexer::new(input).tokenize_with_positions().unwrap();
+
+        let text: Vec<&str> = spans.iter().map(|s| &input[s.start..s.end]).collect();
+        assert_eq!(
+            text,
+            ["return", "'é'", "+", "max", "(", "x1", ",", "2.5", ")", ""]
+        );
+        assert_eq!(spans[1].token, Token::String("é".to_string()));
+        // Byte offsets, not character indexes, follow the two-byte character
+        assert_eq!((spans[2].start, spans[2].end), (12, 13));
+        assert_eq!(spans.last().unwrap().token, Token::Eof);
+        assert_eq!(spans.last().unwrap().start, input.len());
+    }
+
     #[test]
     fn test_tokenize_operators() {
-        let mut lexer = Lexer::new("+ - * / ^ = <> < > <= >=");
+        let mut lexer = Lexer::new("+ - * / ^ = <> < > <= >= |>");
         let tokens = lexer.tokenize().unwrap();
         assert_eq!(tokens[0], Token::Plus);
         assert_eq!(tokens[1], Token::Minus);
@@ -359,6 +644,7 @@ mod tests {
         assert_eq!(tokens[8], Token::GreaterThan);
         assert_eq!(tokens[9], Token::LessThanOrEqual);
         assert_eq!(tokens[10], Token::GreaterThanOrEqual);
+        assert_eq!(tokens[11], Token::Pipe);
     }
 
     #[test]
@@ -370,4 +656,58 @@ mod tests {
         assert_eq!(tokens[2], Token::Plus);
         assert_eq!(tokens[3], Token::Number(2.0));
     }
+
+    #[test]
+    fn test_tokenize_get_diff_months_spellings() {
+        for keyword in ["get_diff_months", "difference_in_months", "GET_DIFF_MONTHS"] {
+            assert_eq!(
+                Lexer::new(keyword).tokenize().unwrap()[0],
+                Token::GetDiffMonths,
+                "{}",
+                keyword
+            );
+        }
+    }
+
+    #[test]
+    fn test_tokenize_comments_inside_argument_lists() {
+        let expected = Lexer::new("max(1, 2)").tokenize().unwrap();
+        for input in [
+            "max(1, /* fallback */ 2)",
+            "max(1, 2/* last */)",
+            "max(/**/1,/* a */ /* b */2 )",
+            "max(1, // trailing\n 2)",
+        ] {
+            assert_eq!(Lexer::new(input).tokenize().unwrap(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_tokenize_unterminated_block_comment() {
+        for input in [
+            "return 1 /* never closed",
+            "return 1 /*",
+            "return 1 /* almost *",
+        ] {
+            assert!(
+                matches!(
+                    Lexer::new(input).tokenize(),
+                    Err(CalculatorError::ParseError(_))
+                ),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_tokenize_rejects_qualified_identifier() {
+        assert!(matches!(
+            Lexer::new("return pricing.base").tokenize(),
+            Err(CalculatorError::ParseError(_))
+        ));
+        assert!(Lexer::new("return get_output_from('pricing.base')")
+            .tokenize()
+            .is_ok());
+    }
 }
@@ -1,10 +1,14 @@
 pub mod ast;
 pub mod evaluator;
 pub mod lexer;
+pub mod optimizer;
 #[allow(clippy::module_inception)]
 pub mod parser;
+pub mod references;
 
 pub use ast::{Expr, Program, Statement};
 pub use evaluator::Evaluator;
-pub use lexer::Lexer;
+pub use lexer::{Lexer, TokenSpan};
+pub use optimizer::{ConstantFolder, ExprVisitor};
 pub use parser::Parser;
+pub use references::References;
@@ -0,0 +1,356 @@
+use super::ast::{Expr, Program, Statement};
+use super::evaluator::Evaluator;
+use crate::cache::{FormulaResultCache, FunctionCache, FunctionResultCache, VariableCache};
+use crate::error::Result;
+use crate::value::Value;
+
+/// A rewriting pass over the expression tree of a [`Program`].
+///
+/// The tree is walked bottom-up: `visit_expr` receives each expression after all
+/// of its children have already been visited and rewritten.
+pub trait ExprVisitor {
+    /// Rewrites a single expression whose children have already been visited.
+    fn visit_expr(&mut self, expr: Expr) -> Result<Expr>;
+
+    /// Applies the pass to every expression in the program.
+    fn visit_program(&mut self, program: Program) -> Result<Program> {
+        Ok(Program {
+            statement: walk_statement(self, program.statement)?,
+        })
+    }
+}
+
+/// Replaces operators whose operands are all literals with the computed literal.
+///
+/// Only arithmetic, comparison, and logical operators are folded; built-in and
+/// custom function calls are left for runtime. Errors raised while folding (for
+/// example [`crate::CalculatorError::DivisionByZero`]) are reported at parse time,
+/// while operations producing `NaN` or infinity are left unfolded.
+pub struct ConstantFolder {
+    evaluator: Evaluator,
+}
+
+impl ConstantFolder {
+    pub fn new() -> Self {
+        Self {
+            evaluator: Evaluator::new(
+                VariableCache::new(),
+                FormulaResultCache::new(),
+                FunctionCache::new(),
+                FunctionResultCache::new(),
+            ),
+        }
+    }
+}
+
+impl Default for ConstantFolder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExprVisitor for ConstantFolder {
+    fn visit_expr(&mut self, expr: Expr) -> Result<Expr> {
+        if !is_foldable(&expr) {
+            return Ok(expr);
+        }
+
+        match self.evaluator.evaluate_expr(&expr)? {
+            Value::Number(n) if n.is_finite() => Ok(Expr::Number(n)),
+            Value::String(s) => Ok(Expr::String(s)),
+            Value::Bool(b) => Ok(Expr::Bool(b)),
+            _ => Ok(expr),
+        }
+    }
+}
+
+fn is_literal(expr: &Expr) -> bool {
+    matches!(expr, Expr::Number(_) | Expr::String(_) | Expr::Bool(_))
+}
+
+fn is_foldable(expr: &Expr) -> bool {
+    match expr {
+        Expr::Add(l, r)
+        | Expr::Subtract(l, r)
+        | Expr::Multiply(l, r)
+        | Expr::Divide(l, r)
+        | Expr::Power(l, r)
+        | Expr::Modulo(l, r)
+        | Expr::Equal(l, r)
+        | Expr::NotEqual(l, r)
+        | Expr::LessThan(l, r)
+        | Expr::GreaterThan(l, r)
+        | Expr::LessThanOrEqual(l, r)
+        | Expr::GreaterThanOrEqual(l, r)
+        | Expr::And(l, r)
+        | Expr::Or(l, r) => is_literal(l) && is_literal(r),
+        Expr::Not(e) | Expr::UnaryMinus(e) => is_literal(e),
+        _ => false,
+    }
+}
+
+fn walk_statement<V: ExprVisitor + ?Sized>(visitor: &mut V, stmt: Statement) -> Result<Statement> {
+    Ok(match stmt {
+        Statement::Return(expr) => Statement::Return(walk_expr(visitor, expr)?),
+        Statement::If {
+            condition,
+            then_block,
+            else_ifs,
+            else_block,
+        } => Statement::If {
+            condition: walk_expr(visitor, condition)?,
+            then_block: Box::new(walk_statement(visitor, *then_block)?),
+            else_ifs: else_ifs
+                .into_iter()
+                .map(|(cond, block)| {
+                    Ok((walk_expr(visitor, cond)?, walk_statement(visitor, block)?))
+                })
+                .collect::<Result<_>>()?,
+            else_block: match else_block {
+                Some(block) => Some(Box::new(walk_statement(visitor, *block)?)),
+                None => None,
+            },
+        },
+        Statement::Error(expr) => Statement::Error(walk_expr(visitor, expr)?),
+    })
+}
+
+fn walk_box<V: ExprVisitor + ?Sized>(visitor: &mut V, mut expr: Box<Expr>) -> Result<Box<Expr>> {
+    // Reuse the existing allocation for the rewritten child
+    let inner = std::mem::replace(&mut *expr, Expr::Bool(false));
+    *expr = walk_expr(visitor, inner)?;
+    Ok(expr)
+}
+
+fn walk_option<V: ExprVisitor + ?Sized>(
+    visitor: &mut V,
+    expr: Option<Box<Expr>>,
+) -> Result<Option<Box<Expr>>> {
+    expr.map(|e| walk_box(visitor, e)).transpose()
+}
+
+fn walk_vec<V: ExprVisitor + ?Sized>(visitor: &mut V, exprs: Vec<Expr>) -> Result<Vec<Expr>> {
+    exprs
+        .into_iter()
+        .map(|expr| walk_expr(visitor, expr))
+        .collect()
+}
+
+fn walk_expr<V: ExprVisitor + ?Sized>(visitor: &mut V, expr: Expr) -> Result<Expr> {
+    let expr = match expr {
+        Expr::Number(_) | Expr::String(_) | Expr::Bool(_) | Expr::Identifier(_) => expr,
+
+        Expr::Add(l, r) => Expr::Add(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::Subtract(l, r) => Expr::Subtract(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::Multiply(l, r) => Expr::Multiply(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::Divide(l, r) => Expr::Divide(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::Power(l, r) => Expr::Power(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::Modulo(l, r) => Expr::Modulo(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::Div(l, r) => Expr::Div(walk_box(visitor, l)?, walk_box(visitor, r)?),
+
+        Expr::Equal(l, r) => Expr::Equal(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::NotEqual(l, r) => Expr::NotEqual(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::LessThan(l, r) => Expr::LessThan(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::GreaterThan(l, r) => Expr::GreaterThan(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::LessThanOrEqual(l, r) => {
+            Expr::LessThanOrEqual(walk_box(visitor, l)?, walk_box(visitor, r)?)
+        }
+        Expr::GreaterThanOrEqual(l, r) => {
+            Expr::GreaterThanOrEqual(walk_box(visitor, l)?, walk_box(visitor, r)?)
+        }
+
+        Expr::And(l, r) => Expr::And(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::Or(l, r) => Expr::Or(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::Not(e) => Expr::Not(walk_box(visitor, e)?),
+        Expr::UnaryMinus(e) => Expr::UnaryMinus(walk_box(visitor, e)?),
+
+        expr => walk_call(visitor, expr)?,
+    };
+
+    visitor.visit_expr(expr)
+}
+
+/// Walks the arguments of a built-in or custom function call.
+///
+/// Kept out of [`walk_expr`], which operator chains recurse through once per
+/// operator, so that its stack frame stays small.
+fn walk_call<V: ExprVisitor + ?Sized>(visitor: &mut V, expr: Expr) -> Result<Expr> {
+    Ok(match expr {
+        Expr::FunctionCall { name, args } => Expr::FunctionCall {
+            name,
+            args: walk_vec(visitor, args)?,
+        },
+
+        Expr::Max(l, r) => Expr::Max(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::Min(l, r) => Expr::Min(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::Rnd(l, r) => Expr::Rnd(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::Ceil(e) => Expr::Ceil(walk_box(visitor, e)?),
+        Expr::MRound(l, r) => Expr::MRound(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::FloorTo(l, r) => Expr::FloorTo(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::CeilTo(l, r) => Expr::CeilTo(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::Floor(e) => Expr::Floor(walk_box(visitor, e)?),
+        Expr::Exp(e) => Expr::Exp(walk_box(visitor, e)?),
+        Expr::Log(l, r) => Expr::Log(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::Year(e) => Expr::Year(walk_box(visitor, e)?),
+        Expr::Month(e) => Expr::Month(walk_box(visitor, e)?),
+        Expr::Day(e) => Expr::Day(walk_box(visitor, e)?),
+        Expr::Substr(s, start, len) => Expr::Substr(
+            walk_box(visitor, s)?,
+            walk_box(visitor, start)?,
+            walk_option(visitor, len)?,
+        ),
+        Expr::AddDays(l, r) => Expr::AddDays(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::GetDiffDays(l, r) => Expr::GetDiffDays(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::GetDiffHours(l, r) => {
+            Expr::GetDiffHours(walk_box(visitor, l)?, walk_box(visitor, r)?)
+        }
+        Expr::GetDiffMinutes(l, r) => {
+            Expr::GetDiffMinutes(walk_box(visitor, l)?, walk_box(visitor, r)?)
+        }
+        Expr::PaddedString(l, r) => {
+            Expr::PaddedString(walk_box(visitor, l)?, walk_box(visitor, r)?)
+        }
+        Expr::FormatNumber(n, decimals, separator) => Expr::FormatNumber(
+            walk_box(visitor, n)?,
+            walk_box(visitor, decimals)?,
+            walk_option(visitor, separator)?,
+        ),
+        Expr::GetDiffMonths(l, r) => {
+            Expr::GetDiffMonths(walk_box(visitor, l)?, walk_box(visitor, r)?)
+        }
+        Expr::GetOutputFrom(e) => Expr::GetOutputFrom(walk_box(visitor, e)?),
+        Expr::BusinessDaysBetween(start, end, holidays) => Expr::BusinessDaysBetween(
+            walk_box(visitor, start)?,
+            walk_box(visitor, end)?,
+            walk_option(visitor, holidays)?,
+        ),
+        Expr::Iif(cond, then_expr, else_expr) => Expr::Iif(
+            walk_box(visitor, cond)?,
+            walk_box(visitor, then_expr)?,
+            walk_box(visitor, else_expr)?,
+        ),
+        Expr::Date(e) => Expr::Date(walk_box(visitor, e)?),
+        Expr::Sum(args) => Expr::Sum(walk_vec(visitor, args)?),
+        Expr::Avg(args) => Expr::Avg(walk_vec(visitor, args)?),
+        Expr::Count(args) => Expr::Count(walk_vec(visitor, args)?),
+        Expr::Product(args) => Expr::Product(walk_vec(visitor, args)?),
+        Expr::Median(args) => Expr::Median(walk_vec(visitor, args)?),
+        Expr::Percentile(values, p) => {
+            Expr::Percentile(walk_box(visitor, values)?, walk_box(visitor, p)?)
+        }
+        Expr::Variance(args) => Expr::Variance(walk_vec(visitor, args)?),
+        Expr::VarianceP(args) => Expr::VarianceP(walk_vec(visitor, args)?),
+        Expr::Stddev(args) => Expr::Stddev(walk_vec(visitor, args)?),
+        Expr::StddevP(args) => Expr::StddevP(walk_vec(visitor, args)?),
+        Expr::Pmt(args) => Expr::Pmt(walk_vec(visitor, args)?),
+        Expr::ModE(l, r) => Expr::ModE(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::Gcd(l, r) => Expr::Gcd(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::BitAnd(l, r) => Expr::BitAnd(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::BitOr(l, r) => Expr::BitOr(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::BitXor(l, r) => Expr::BitXor(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::Lcm(l, r) => Expr::Lcm(walk_box(visitor, l)?, walk_box(visitor, r)?),
+        Expr::Factorial(e) => Expr::Factorial(walk_box(visitor, e)?),
+        Expr::IsEven(e) => Expr::IsEven(walk_box(visitor, e)?),
+        Expr::IsOdd(e) => Expr::IsOdd(walk_box(visitor, e)?),
+        Expr::Combinations(l, r) => {
+            Expr::Combinations(walk_box(visitor, l)?, walk_box(visitor, r)?)
+        }
+        Expr::Npv(rate, flows) => Expr::Npv(walk_box(visitor, rate)?, walk_vec(visitor, flows)?),
+        Expr::Irr(flows) => Expr::Irr(walk_vec(visitor, flows)?),
+        Expr::Split(s, d) => Expr::Split(walk_box(visitor, s)?, walk_box(visitor, d)?),
+        Expr::Join(a, s) => Expr::Join(walk_box(visitor, a)?, walk_box(visitor, s)?),
+        Expr::Fv(rate, nper, pv) => Expr::Fv(
+            walk_box(visitor, rate)?,
+            walk_box(visitor, nper)?,
+            walk_box(visitor, pv)?,
+        ),
+        Expr::Cagr(begin, end, years) => Expr::Cagr(
+            walk_box(visitor, begin)?,
+            walk_box(visitor, end)?,
+            walk_box(visitor, years)?,
+        ),
+        Expr::ApproxEqual(a, b, epsilon) => Expr::ApproxEqual(
+            walk_box(visitor, a)?,
+            walk_box(visitor, b)?,
+            walk_box(visitor, epsilon)?,
+        ),
+        Expr::FormatFixed(n, width, decimals) => Expr::FormatFixed(
+            walk_box(visitor, n)?,
+            walk_box(visitor, width)?,
+            walk_box(visitor, decimals)?,
+        ),
+        Expr::PctChange(old, new) => {
+            Expr::PctChange(walk_box(visitor, old)?, walk_box(visitor, new)?)
+        }
+        Expr::Lookup(prefix, key) => {
+            Expr::Lookup(walk_box(visitor, prefix)?, walk_box(visitor, key)?)
+        }
+        Expr::PadNumber(value, width) => {
+            Expr::PadNumber(walk_box(visitor, value)?, walk_box(visitor, width)?)
+        }
+        _ => unreachable!("literals and operators are walked by walk_expr"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::CalculatorError;
+    use crate::parser::parser::Parser;
+
+    fn fold(input: &str) -> Result<Statement> {
+        let program = Parser::new(input)?.parse()?;
+        Ok(ConstantFolder::new().visit_program(program)?.statement)
+    }
+
+    #[test]
+    fn test_fold_arithmetic() {
+        assert_eq!(
+            fold("return 2 * 3 + 1").unwrap(),
+            Statement::Return(Expr::Number(7.0))
+        );
+        assert_eq!(
+            fold("return -(2 + 3)").unwrap(),
+            Statement::Return(Expr::Number(-5.0))
+        );
+    }
+
+    #[test]
+    fn test_fold_keeps_non_constant_operands() {
+        assert_eq!(
+            fold("return price * (2 + 3)").unwrap(),
+            Statement::Return(Expr::Multiply(
+                Box::new(Expr::Identifier("price".to_string())),
+                Box::new(Expr::Number(5.0)),
+            ))
+        );
+        assert_eq!(
+            fold("return max(1 + 1, 3)").unwrap(),
+            Statement::Return(Expr::Max(
+                Box::new(Expr::Number(2.0)),
+                Box::new(Expr::Number(3.0)),
+            ))
+        );
+    }
+
+    #[test]
+    fn test_fold_comparison_and_logical() {
+        assert_eq!(
+            fold("if (1 < 2 and !false) then return 'a' + 'b' else return 0 end").unwrap(),
+            Statement::If {
+                condition: Expr::Bool(true),
+                then_block: Box::new(Statement::Return(Expr::String("ab".to_string()))),
+                else_ifs: vec![],
+                else_block: Some(Box::new(Statement::Return(Expr::Number(0.0)))),
+            }
+        );
+    }
+
+    #[test]
+    fn test_fold_division_by_zero() {
+        assert_eq!(
+            fold("return x + 1 / 0").unwrap_err(),
+            CalculatorError::DivisionByZero
+        );
+    }
+}
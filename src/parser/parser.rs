@@ -1,10 +1,22 @@
 use super::ast::{Expr, Program, Statement};
 use super::lexer::{Lexer, Token};
 use crate::error::{CalculatorError, Result};
+use std::ops::RangeInclusive;
+
+/// Deepest nesting of parentheses, function arguments, unary operators, `^`, and
+/// `if` blocks, each of which the parser recurses into.
+const MAX_NESTING: usize = 64;
+
+/// Deepest expression tree the parser builds. Folding and evaluation recurse once
+/// per level, so a chain like `x + x + ... + x` could otherwise overflow the stack;
+/// this leaves ample room on a 2 MiB worker thread in optimized builds.
+const MAX_DEPTH: usize = 256;
 
 pub struct Parser {
     tokens: Vec<Token>,
     position: usize,
+    /// Levels of [`Parser::nested`] currently entered
+    nesting: usize,
 }
 
 impl Parser {
@@ -14,6 +26,7 @@ impl Parser {
         Ok(Self {
             tokens,
             position: 0,
+            nesting: 0,
         })
     }
 
@@ -23,17 +36,25 @@ impl Parser {
         Ok(Program { statement })
     }
 
+    /// Parses the whole input as a single expression, such as `2 + 3 * price`,
+    /// without the `return` a program needs.
+    pub fn parse_expression(&mut self) -> Result<Expr> {
+        let expr = self.parse_expr()?;
+        self.expect_token(Token::Eof)?;
+        Ok(expr)
+    }
+
     fn parse_block(&mut self) -> Result<Statement> {
         if self.check_token(&Token::If) {
             self.parse_if_statement()
         } else if self.check_token(&Token::Return) {
             self.advance();
-            let expr = self.parse_expression()?;
+            let expr = self.parse_expr()?;
             Ok(Statement::Return(expr))
         } else if self.check_token(&Token::Error) {
             self.advance();
             self.expect_token(Token::LeftParen)?;
-            let expr = self.parse_expression()?;
+            let expr = self.parse_expr()?;
             self.expect_token(Token::RightParen)?;
             Ok(Statement::Error(expr))
         } else {
@@ -46,10 +67,10 @@ impl Parser {
     fn parse_if_statement(&mut self) -> Result<Statement> {
         self.expect_token(Token::If)?;
         self.expect_token(Token::LeftParen)?;
-        let condition = self.parse_expression()?;
+        let condition = self.parse_expr()?;
         self.expect_token(Token::RightParen)?;
         self.expect_token(Token::Then)?;
-        let then_block = Box::new(self.parse_block()?);
+        let then_block = Box::new(self.nested(Self::parse_block)?);
 
         let mut else_ifs = Vec::new();
         while self.check_token(&Token::Else) {
@@ -59,10 +80,10 @@ impl Parser {
                     self.advance(); // consume Else
                     self.advance(); // consume If
                     self.expect_token(Token::LeftParen)?;
-                    let else_if_condition = self.parse_expression()?;
+                    let else_if_condition = self.parse_expr()?;
                     self.expect_token(Token::RightParen)?;
                     self.expect_token(Token::Then)?;
-                    let else_if_block = self.parse_block()?;
+                    let else_if_block = self.nested(Self::parse_block)?;
                     else_ifs.push((else_if_condition, else_if_block));
                 } else {
                     break;
@@ -74,7 +95,7 @@ impl Parser {
 
         let else_block = if self.check_token(&Token::Else) {
             self.advance();
-            Some(Box::new(self.parse_block()?))
+            Some(Box::new(self.nested(Self::parse_block)?))
         } else {
             None
         };
@@ -89,17 +110,105 @@ impl Parser {
         })
     }
 
-    fn parse_expression(&mut self) -> Result<Expr> {
-        self.parse_or()
+    fn parse_expr(&mut self) -> Result<Expr> {
+        self.nested(Self::parse_pipe)
+    }
+
+    /// Runs `parse` one level deeper, failing past [`MAX_NESTING`] levels.
+    fn nested<T>(&mut self, parse: fn(&mut Self) -> Result<T>) -> Result<T> {
+        if self.nesting == MAX_NESTING {
+            return Err(CalculatorError::LimitExceeded(format!(
+                "expression nests deeper than {} levels",
+                MAX_NESTING
+            )));
+        }
+        self.nesting += 1;
+        let result = parse(self);
+        self.nesting -= 1;
+        result
+    }
+
+    /// Builds the operator `constructor(left, right)` as the next link of a chain.
+    ///
+    /// `depth` carries the depth of the chain so far, so each operand is measured once.
+    fn link(
+        depth: &mut Option<usize>,
+        constructor: fn(Box<Expr>, Box<Expr>) -> Expr,
+        left: Expr,
+        right: Expr,
+    ) -> Result<Expr> {
+        let left_depth = depth.unwrap_or_else(|| left.depth());
+        *depth = Some(check_depth(1 + left_depth.max(right.depth()))?);
+        Ok(constructor(Box::new(left), Box::new(right)))
+    }
+
+    /// Parses `value |> function`, the lowest-precedence operator, as `function(value)`.
+    fn parse_pipe(&mut self) -> Result<Expr> {
+        let mut left = self.parse_or()?;
+        let mut depth = None;
+
+        while self.check_token(&Token::Pipe) {
+            self.advance();
+            let function = self.current_token().clone();
+            self.advance();
+            // The piped value becomes the only argument of the call
+            depth = Some(check_depth(1 + depth.unwrap_or_else(|| left.depth()))?);
+            left = Self::pipe_into(function, left)?;
+        }
+
+        Ok(left)
+    }
+
+    /// Builds the call `function(value)` for the function token after a `|>`.
+    ///
+    /// Custom functions and the built-ins that accept a single argument are valid targets.
+    fn pipe_into(function: Token, value: Expr) -> Result<Expr> {
+        let arg = Box::new(value);
+        Ok(match function {
+            Token::Identifier(name) => Expr::FunctionCall {
+                name,
+                args: vec![*arg],
+            },
+            Token::Ceil => Expr::Ceil(arg),
+            Token::Floor => Expr::Floor(arg),
+            Token::Exp => Expr::Exp(arg),
+            Token::Square => Expr::Power(arg, Box::new(Expr::Number(2.0))),
+            Token::Cube => Expr::Power(arg, Box::new(Expr::Number(3.0))),
+            Token::Year => Expr::Year(arg),
+            Token::Month => Expr::Month(arg),
+            Token::Day => Expr::Day(arg),
+            Token::GetOutputFrom => Expr::GetOutputFrom(arg),
+            Token::Date => Expr::Date(arg),
+            Token::Factorial => Expr::Factorial(arg),
+            Token::IsEven => Expr::IsEven(arg),
+            Token::IsOdd => Expr::IsOdd(arg),
+            Token::Sum => Expr::Sum(vec![*arg]),
+            Token::Avg => Expr::Avg(vec![*arg]),
+            Token::Count => Expr::Count(vec![*arg]),
+            Token::Product => Expr::Product(vec![*arg]),
+            Token::Median => Expr::Median(vec![*arg]),
+            Token::Variance => Expr::Variance(vec![*arg]),
+            Token::VarianceP => Expr::VarianceP(vec![*arg]),
+            Token::Stddev => Expr::Stddev(vec![*arg]),
+            Token::StddevP => Expr::StddevP(vec![*arg]),
+            Token::Irr => Expr::Irr(vec![*arg]),
+            function => {
+                return Err(CalculatorError::ParseError(format!(
+                    "Expected a function taking one argument after |>, found {:?}",
+                    function
+                )))
+            }
+        })
     }
 
     fn parse_or(&mut self) -> Result<Expr> {
         let mut left = self.parse_and()?;
+        let mut depth = None;
 
         while self.check_token(&Token::Or) {
             self.advance();
             let right = self.parse_and()?;
-            left = Expr::Or(Box::new(left), Box::new(right));
+            left = Self::link(&mut depth, Expr::Or, left, right)?;
         }
 
         Ok(left)
@@ -107,11 +216,12 @@ impl Parser {
 
     fn parse_and(&mut self) -> Result<Expr> {
         let mut left = self.parse_equality()?;
+        let mut depth = None;
 
         while self.check_token(&Token::And) {
             self.advance();
             let right = self.parse_equality()?;
-            left = Expr::And(Box::new(left), Box::new(right));
+            left = Self::link(&mut depth, Expr::And, left, right)?;
         }
 
         Ok(left)
@@ -119,16 +229,17 @@ impl Parser {
 
     fn parse_equality(&mut self) -> Result<Expr> {
         let mut left = self.parse_comparison()?;
+        let mut depth = None;
 
         loop {
             if self.check_token(&Token::Equal) {
                 self.advance();
                 let right = self.parse_comparison()?;
-                left = Expr::Equal(Box::new(left), Box::new(right));
+                left = Self::link(&mut depth, Expr::Equal, left, right)?;
             } else if self.check_token(&Token::NotEqual) {
                 self.advance();
                 let right = self.parse_comparison()?;
-                left = Expr::NotEqual(Box::new(left), Box::new(right));
+                left = Self::link(&mut depth, Expr::NotEqual, left, right)?;
             } else {
                 break;
             }
@@ -139,24 +250,25 @@ impl Parser {
 
     fn parse_comparison(&mut self) -> Result<Expr> {
         let mut left = self.parse_additive()?;
+        let mut depth = None;
 
         loop {
             if self.check_token(&Token::LessThan) {
                 self.advance();
                 let right = self.parse_additive()?;
-                left = Expr::LessThan(Box::new(left), Box::new(right));
+                left = Self::link(&mut depth, Expr::LessThan, left, right)?;
             } else if self.check_token(&Token::GreaterThan) {
                 self.advance();
                 let right = self.parse_additive()?;
-                left = Expr::GreaterThan(Box::new(left), Box::new(right));
+                left = Self::link(&mut depth, Expr::GreaterThan, left, right)?;
             } else if self.check_token(&Token::LessThanOrEqual) {
                 self.advance();
                 let right = self.parse_additive()?;
-                left = Expr::LessThanOrEqual(Box::new(left), Box::new(right));
+                left = Self::link(&mut depth, Expr::LessThanOrEqual, left, right)?;
             } else if self.check_token(&Token::GreaterThanOrEqual) {
                 self.advance();
                 let right = self.parse_additive()?;
-                left = Expr::GreaterThanOrEqual(Box::new(left), Box::new(right));
+                left = Self::link(&mut depth, Expr::GreaterThanOrEqual, left, right)?;
             } else {
                 break;
             }
@@ -167,16 +279,17 @@ impl Parser {
 
     fn parse_additive(&mut self) -> Result<Expr> {
         let mut left = self.parse_multiplicative()?;
+        let mut depth = None;
 
         loop {
             if self.check_token(&Token::Plus) {
                 self.advance();
                 let right = self.parse_multiplicative()?;
-                left = Expr::Add(Box::new(left), Box::new(right));
+                left = Self::link(&mut depth, Expr::Add, left, right)?;
             } else if self.check_token(&Token::Minus) {
                 self.advance();
                 let right = self.parse_multiplicative()?;
-                left = Expr::Subtract(Box::new(left), Box::new(right));
+                left = Self::link(&mut depth, Expr::Subtract, left, right)?;
             } else {
                 break;
             }
@@ -187,16 +300,22 @@ impl Parser {
 
     fn parse_multiplicative(&mut self) -> Result<Expr> {
         let mut left = self.parse_modulo()?;
+        let mut depth = None;
 
         loop {
             if self.check_token(&Token::Multiply) {
                 self.advance();
                 let right = self.parse_modulo()?;
-                left = Expr::Multiply(Box::new(left), Box::new(right));
+                left = Self::link(&mut depth, Expr::Multiply, left, right)?;
             } else if self.check_token(&Token::Divide) {
                 self.advance();
                 let right = self.parse_modulo()?;
-                left = Expr::Divide(Box::new(left), Box::new(right));
+                left = Self::link(&mut depth, Expr::Divide, left, right)?;
+            } else if self.check_token(&Token::Div) {
+                // Infix `a div b`; `div(a, b)` in operand position is the function form
+                self.advance();
+                let right = self.parse_modulo()?;
+                left = Self::link(&mut depth, Expr::Div, left, right)?;
             } else {
                 break;
             }
@@ -207,11 +326,12 @@ impl Parser {
 
     fn parse_modulo(&mut self) -> Result<Expr> {
         let mut left = self.parse_power()?;
+        let mut depth = None;
 
         while self.check_token(&Token::Mod) {
             self.advance();
             let right = self.parse_power()?;
-            left = Expr::Modulo(Box::new(left), Box::new(right));
+            left = Self::link(&mut depth, Expr::Modulo, left, right)?;
         }
 
         Ok(left)
@@ -222,8 +342,8 @@ impl Parser {
 
         if self.check_token(&Token::Power) {
             self.advance();
-            let right = self.parse_power()?; // Right associative
-            left = Expr::Power(Box::new(left), Box::new(right));
+            let right = self.nested(Self::parse_power)?; // Right associative
+            left = Self::link(&mut None, Expr::Power, left, right)?;
         }
 
         Ok(left)
@@ -232,11 +352,11 @@ impl Parser {
     fn parse_unary(&mut self) -> Result<Expr> {
         if self.check_token(&Token::Minus) {
             self.advance();
-            let expr = self.parse_unary()?;
+            let expr = self.nested(Self::parse_unary)?;
             Ok(Expr::UnaryMinus(Box::new(expr)))
         } else if self.check_token(&Token::Not) {
             self.advance();
-            let expr = self.parse_unary()?;
+            let expr = self.nested(Self::parse_unary)?;
             Ok(Expr::Not(Box::new(expr)))
         } else {
             self.parse_primary()
@@ -264,7 +384,7 @@ impl Parser {
             }
             Token::LeftParen => {
                 self.advance();
-                let expr = self.parse_expression()?;
+                let expr = self.parse_expr()?;
                 self.expect_token(Token::RightParen)?;
                 Ok(expr)
             }
@@ -286,17 +406,73 @@ impl Parser {
             Token::Min => self.parse_binary_function(Expr::Min),
             Token::Rnd => self.parse_binary_function(Expr::Rnd),
             Token::Ceil => self.parse_unary_function(Expr::Ceil),
+            Token::MRound => self.parse_binary_function(Expr::MRound),
+            Token::FloorTo => self.parse_binary_function(Expr::FloorTo),
+            Token::CeilTo => self.parse_binary_function(Expr::CeilTo),
             Token::Floor => self.parse_unary_function(Expr::Floor),
             Token::Exp => self.parse_unary_function(Expr::Exp),
+            Token::Log => self.parse_binary_function(Expr::Log),
+            // `pow(a, b)` is the function form of `a ^ b`
+            Token::Pow => self.parse_binary_function(Expr::Power),
+            Token::Square => {
+                self.parse_unary_function(|x| Expr::Power(x, Box::new(Expr::Number(2.0))))
+            }
+            Token::Cube => {
+                self.parse_unary_function(|x| Expr::Power(x, Box::new(Expr::Number(3.0))))
+            }
             Token::Year => self.parse_unary_function(Expr::Year),
             Token::Month => self.parse_unary_function(Expr::Month),
             Token::Day => self.parse_unary_function(Expr::Day),
-            Token::Substr => self.parse_ternary_function(Expr::Substr),
+            Token::Substr => self.parse_binary_function_with_optional_arg(Expr::Substr),
             Token::AddDays => self.parse_binary_function(Expr::AddDays),
             Token::GetDiffDays => self.parse_binary_function(Expr::GetDiffDays),
+            Token::GetDiffHours => self.parse_binary_function(Expr::GetDiffHours),
+            Token::GetDiffMinutes => self.parse_binary_function(Expr::GetDiffMinutes),
             Token::PaddedString => self.parse_binary_function(Expr::PaddedString),
+            Token::FormatNumber => self.parse_binary_function_with_optional_arg(Expr::FormatNumber),
             Token::GetDiffMonths => self.parse_binary_function(Expr::GetDiffMonths),
             Token::GetOutputFrom => self.parse_unary_function(Expr::GetOutputFrom),
+            Token::BusinessDaysBetween => {
+                self.parse_binary_function_with_optional_arg(Expr::BusinessDaysBetween)
+            }
+            Token::Iif => self.parse_ternary_function(Expr::Iif),
+            Token::Date => self.parse_unary_function(Expr::Date),
+            Token::Sum => self.parse_variadic_function(Expr::Sum),
+            Token::Avg => self.parse_variadic_function(Expr::Avg),
+            Token::Count => self.parse_variadic_function(Expr::Count),
+            Token::Product => self.parse_variadic_function(Expr::Product),
+            Token::Median => self.parse_variadic_function(Expr::Median),
+            Token::Percentile => self.parse_binary_function(Expr::Percentile),
+            Token::Variance => self.parse_variadic_function(Expr::Variance),
+            Token::VarianceP => self.parse_variadic_function(Expr::VarianceP),
+            Token::Stddev => self.parse_variadic_function(Expr::Stddev),
+            Token::StddevP => self.parse_variadic_function(Expr::StddevP),
+            Token::Pmt => self.parse_function_with_arity(3..=5, Expr::Pmt),
+            Token::ModE => self.parse_binary_function(Expr::ModE),
+            Token::Div => self.parse_binary_function(Expr::Div),
+            Token::Gcd => self.parse_binary_function(Expr::Gcd),
+            Token::BitAnd => self.parse_binary_function(Expr::BitAnd),
+            Token::BitOr => self.parse_binary_function(Expr::BitOr),
+            Token::BitXor => self.parse_binary_function(Expr::BitXor),
+            Token::Lcm => self.parse_binary_function(Expr::Lcm),
+            Token::Factorial => self.parse_unary_function(Expr::Factorial),
+            Token::IsEven => self.parse_unary_function(Expr::IsEven),
+            Token::IsOdd => self.parse_unary_function(Expr::IsOdd),
+            Token::Combinations => self.parse_binary_function(Expr::Combinations),
+            Token::Npv => self.parse_function_with_arity(2..=usize::MAX, |mut args| {
+                let rate = args.remove(0);
+                Expr::Npv(Box::new(rate), args)
+            }),
+            Token::Irr => self.parse_variadic_function(Expr::Irr),
+            Token::Split => self.parse_binary_function(Expr::Split),
+            Token::Join => self.parse_binary_function(Expr::Join),
+            Token::Fv => self.parse_ternary_function(Expr::Fv),
+            Token::Cagr => self.parse_ternary_function(Expr::Cagr),
+            Token::ApproxEqual => self.parse_ternary_function(Expr::ApproxEqual),
+            Token::FormatFixed => self.parse_ternary_function(Expr::FormatFixed),
+            Token::PctChange => self.parse_binary_function(Expr::PctChange),
+            Token::Lookup => self.parse_binary_function(Expr::Lookup),
+            Token::PadNumber => self.parse_binary_function(Expr::PadNumber),
             _ => Err(CalculatorError::ParseError(format!(
                 "Unexpected token: {:?}",
                 current
@@ -310,7 +486,7 @@ impl Parser {
     {
         self.advance();
         self.expect_token(Token::LeftParen)?;
-        let arg = self.parse_expression()?;
+        let arg = self.parse_expr()?;
         self.expect_token(Token::RightParen)?;
         Ok(constructor(Box::new(arg)))
     }
@@ -321,28 +497,84 @@ impl Parser {
     {
         self.advance();
         self.expect_token(Token::LeftParen)?;
-        let arg1 = self.parse_expression()?;
+        let arg1 = self.parse_expr()?;
         self.expect_token(Token::Comma)?;
-        let arg2 = self.parse_expression()?;
+        let arg2 = self.parse_expr()?;
         self.expect_token(Token::RightParen)?;
         Ok(constructor(Box::new(arg1), Box::new(arg2)))
     }
 
+    fn parse_binary_function_with_optional_arg<F>(&mut self, constructor: F) -> Result<Expr>
+    where
+        F: FnOnce(Box<Expr>, Box<Expr>, Option<Box<Expr>>) -> Expr,
+    {
+        self.advance();
+        self.expect_token(Token::LeftParen)?;
+        let arg1 = self.parse_expr()?;
+        self.expect_token(Token::Comma)?;
+        let arg2 = self.parse_expr()?;
+        let arg3 = if self.check_token(&Token::Comma) {
+            self.advance();
+            Some(Box::new(self.parse_expr()?))
+        } else {
+            None
+        };
+        self.expect_token(Token::RightParen)?;
+        Ok(constructor(Box::new(arg1), Box::new(arg2), arg3))
+    }
+
     fn parse_ternary_function<F>(&mut self, constructor: F) -> Result<Expr>
     where
         F: FnOnce(Box<Expr>, Box<Expr>, Box<Expr>) -> Expr,
     {
         self.advance();
         self.expect_token(Token::LeftParen)?;
-        let arg1 = self.parse_expression()?;
+        let arg1 = self.parse_expr()?;
         self.expect_token(Token::Comma)?;
-        let arg2 = self.parse_expression()?;
+        let arg2 = self.parse_expr()?;
         self.expect_token(Token::Comma)?;
-        let arg3 = self.parse_expression()?;
+        let arg3 = self.parse_expr()?;
         self.expect_token(Token::RightParen)?;
         Ok(constructor(Box::new(arg1), Box::new(arg2), Box::new(arg3)))
     }
 
+    fn parse_variadic_function<F>(&mut self, constructor: F) -> Result<Expr>
+    where
+        F: FnOnce(Vec<Expr>) -> Expr,
+    {
+        self.parse_function_with_arity(1..=usize::MAX, constructor)
+    }
+
+    fn parse_function_with_arity<F>(
+        &mut self,
+        arity: RangeInclusive<usize>,
+        constructor: F,
+    ) -> Result<Expr>
+    where
+        F: FnOnce(Vec<Expr>) -> Expr,
+    {
+        let function = self.current_token().clone();
+        self.advance();
+        self.expect_token(Token::LeftParen)?;
+        let mut args = vec![self.parse_expr()?];
+        while self.check_token(&Token::Comma) {
+            self.advance();
+            args.push(self.parse_expr()?);
+        }
+        self.expect_token(Token::RightParen)?;
+
+        if !arity.contains(&args.len()) {
+            return Err(CalculatorError::ParseError(format!(
+                "{:?} expects {} to {} arguments, found {}",
+                function,
+                arity.start(),
+                arity.end(),
+                args.len()
+            )));
+        }
+        Ok(constructor(args))
+    }
+
     fn parse_argument_list(&mut self) -> Result<Vec<Expr>> {
         let mut args = Vec::new();
 
@@ -350,11 +582,11 @@ impl Parser {
             return Ok(args);
         }
 
-        args.push(self.parse_expression()?);
+        args.push(self.parse_expr()?);
 
         while self.check_token(&Token::Comma) {
             self.advance();
-            args.push(self.parse_expression()?);
+            args.push(self.parse_expr()?);
         }
 
         Ok(args)
@@ -391,6 +623,17 @@ impl Parser {
     }
 }
 
+/// Returns `depth` if it is within [`MAX_DEPTH`].
+fn check_depth(depth: usize) -> Result<usize> {
+    if depth > MAX_DEPTH {
+        return Err(CalculatorError::LimitExceeded(format!(
+            "expression is deeper than {} levels",
+            MAX_DEPTH
+        )));
+    }
+    Ok(depth)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -415,6 +658,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_expression_without_return() {
+        let expr = Parser::new("2 + 3 * price")
+            .unwrap()
+            .parse_expression()
+            .unwrap();
+        assert_eq!(
+            expr,
+            Expr::Add(
+                Box::new(Expr::Number(2.0)),
+                Box::new(Expr::Multiply(
+                    Box::new(Expr::Number(3.0)),
+                    Box::new(Expr::Identifier("price".to_string()))
+                ))
+            )
+        );
+
+        for input in ["return 1", "1 + 2 )", "1 2", ""] {
+            assert!(
+                Parser::new(input).unwrap().parse_expression().is_err(),
+                "{}",
+                input
+            );
+        }
+    }
+
     #[test]
     fn test_parse_operator_precedence_add_mul() {
         assert_eq!(
@@ -476,6 +745,71 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_display_parenthesizes_nested_operators() {
+        let program = Parser::new("return 2 + 3 * 4").unwrap().parse().unwrap();
+        assert_eq!(program.to_string(), "return 2 + (3 * 4)");
+        assert_eq!(
+            parse_return_expr("return -(a - b) ^ 2 > 1 and !done").to_string(),
+            "((-(a - b) ^ 2) > 1) and !done"
+        );
+    }
+
+    #[test]
+    fn test_display_round_trips_through_parser() {
+        for input in [
+            "return 1 - (2 - 3) / 4 mod 5 div 6",
+            "return 'it\\'s' + ' \\\\ ' + name",
+            "return substr(s, 1) + substr(s, 1, 2) + format_number(x, 2, ',')",
+            "return npv(0.1, flows) + sum(1, x) + pmt(r, n, pv, 0, 1) + bonus(x, 2)",
+            "return square(x) + cube(2) + pow(x, 0.5) + iif(a <> b, 1, 2)",
+            "return iif(approx_equal(a, b, 0.001), pct_change(a, b), 0) + format_fixed(x, 10, 2)",
+            "if (x > 1) then return 1 else if (x < 0) then error('negative') else if (true) then return 0 end",
+            "if (a) then if (b) then return 1 end else return 2 end",
+            "if (x >= 1 or x <= -1) then return get_output_from('base') end",
+        ] {
+            let program = Parser::new(input).unwrap().parse().unwrap();
+            let rendered = program.to_string();
+            let reparsed = Parser::new(&rendered)
+                .unwrap_or_else(|e| panic!("{} rendered as unparsable {}: {}", input, rendered, e))
+                .parse()
+                .unwrap_or_else(|e| panic!("{} rendered as unparsable {}: {}", input, rendered, e));
+            assert_eq!(reparsed, program, "{} rendered as {}", input, rendered);
+        }
+    }
+
+    #[test]
+    fn test_parse_div_operator() {
+        // Same precedence as `*` and `/`, left associative, and equivalent to div(a, b)
+        assert_eq!(
+            parse_return_expr("return 1 + 20 div 3 * 2"),
+            Expr::Add(
+                Box::new(Expr::Number(1.0)),
+                Box::new(Expr::Multiply(
+                    Box::new(Expr::Div(
+                        Box::new(Expr::Number(20.0)),
+                        Box::new(Expr::Number(3.0)),
+                    )),
+                    Box::new(Expr::Number(2.0)),
+                )),
+            )
+        );
+        assert_eq!(
+            parse_return_expr("return 20 div 3"),
+            parse_return_expr("return div(20, 3)")
+        );
+        assert_eq!(
+            parse_return_expr("return div(20, 3) div 2"),
+            Expr::Div(
+                Box::new(Expr::Div(
+                    Box::new(Expr::Number(20.0)),
+                    Box::new(Expr::Number(3.0)),
+                )),
+                Box::new(Expr::Number(2.0)),
+            )
+        );
+    }
+
     #[test]
     fn test_parse_identifier_and_function_call_arguments() {
         assert_eq!(
@@ -513,6 +847,42 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_pipe_desugars_to_calls() {
+        let call = |name: &str, arg: Expr| Expr::FunctionCall {
+            name: name.to_string(),
+            args: vec![arg],
+        };
+        assert_eq!(
+            parse_return_expr("return x |> trim |> upper"),
+            call("upper", call("trim", Expr::Identifier("x".to_string())))
+        );
+        assert_eq!(
+            parse_return_expr("return x |> trim |> upper"),
+            parse_return_expr("return upper(trim(x))")
+        );
+        // Lowest precedence, so the whole left side is piped
+        assert_eq!(
+            parse_return_expr("return a + 1.5 |> ceil |> square"),
+            parse_return_expr("return square(ceil(a + 1.5))")
+        );
+        assert_eq!(
+            parse_return_expr("return split(s, ',') |> count |> is_even"),
+            parse_return_expr("return is_even(count(split(s, ',')))")
+        );
+
+        for input in ["return x |> 1", "return x |> max", "return x |>"] {
+            assert!(
+                matches!(
+                    Parser::new(input).unwrap().parse(),
+                    Err(CalculatorError::ParseError(_))
+                ),
+                "{}",
+                input
+            );
+        }
+    }
+
     #[test]
     fn test_parse_built_in_binary_functions() {
         assert_eq!(
@@ -523,6 +893,10 @@ mod tests {
             parse_return_expr("return add_days(10, 5)"),
             Expr::AddDays(Box::new(Expr::Number(10.0)), Box::new(Expr::Number(5.0)))
         );
+        assert_eq!(
+            parse_return_expr("return pow(-2, 3)"),
+            parse_return_expr("return (-2) ^ 3")
+        );
     }
 
     #[test]
@@ -532,7 +906,15 @@ mod tests {
             Expr::Substr(
                 Box::new(Expr::String("abcdef".to_string())),
                 Box::new(Expr::Number(2.0)),
-                Box::new(Expr::Number(3.0)),
+                Some(Box::new(Expr::Number(3.0))),
+            )
+        );
+        assert_eq!(
+            parse_return_expr("return substr('abcdef', 2)"),
+            Expr::Substr(
+                Box::new(Expr::String("abcdef".to_string())),
+                Box::new(Expr::Number(2.0)),
+                None,
             )
         );
     }
@@ -567,6 +949,14 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_comments_inside_expressions() {
+        assert_eq!(
+            parse_statement("if (x /* threshold */ > 10 /* inclusive? */) then return max(1, /* fallback */ 2 /* end */) else return 0 end"),
+            parse_statement("if (x > 10) then return max(1, 2) else return 0 end")
+        );
+    }
+
     #[test]
     fn test_parse_error_statement() {
         assert_eq!(
@@ -592,4 +982,35 @@ mod tests {
             matches!(error, CalculatorError::ParseError(message) if message.contains("Expected Comma"))
         );
     }
+
+    #[test]
+    fn test_parse_limits_expression_depth() {
+        let parse = |input: String| Parser::new(&input).unwrap().parse();
+        let chain = |terms: usize| format!("return {}", vec!["x"; terms].join(" + "));
+        let parens =
+            |levels: usize| format!("return {}x{}", "(".repeat(levels), ")".repeat(levels));
+
+        // Rejected while parsing, before folding or evaluation could overflow the stack
+        for input in [
+            chain(50_000),
+            parens(50_000),
+            format!("return {}x{}", "abs(".repeat(50_000), ")".repeat(50_000)),
+            format!("return {}x", "-".repeat(50_000)),
+            format!("return {}", vec!["2"; 50_000].join(" ^ ")),
+            format!(
+                "return (({}) * 2) + 1",
+                chain(50_000).replace("return ", "")
+            ),
+        ] {
+            assert!(
+                matches!(parse(input), Err(CalculatorError::LimitExceeded(_))),
+                "deep expression was accepted"
+            );
+        }
+
+        assert!(parse(chain(MAX_DEPTH)).is_ok());
+        assert!(parse(chain(MAX_DEPTH + 1)).is_err());
+        assert!(parse(parens(MAX_NESTING - 1)).is_ok());
+        assert!(parse(parens(MAX_NESTING)).is_err());
+    }
 }
@@ -0,0 +1,74 @@
+use super::ast::{Expr, Program};
+use super::optimizer::ExprVisitor;
+use crate::error::Result;
+use std::collections::BTreeSet;
+
+/// The variables, formulas, and custom functions a [`Program`] refers to.
+///
+/// Formula references are only known for `get_output_from` calls whose argument
+/// is a string literal. Functions are recorded as `(name, argument count)` pairs.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct References {
+    pub variables: BTreeSet<String>,
+    pub formulas: BTreeSet<String>,
+    pub functions: BTreeSet<(String, usize)>,
+}
+
+impl References {
+    /// Collects every reference made anywhere in `program`, including untaken branches.
+    pub fn collect(program: Program) -> Result<Self> {
+        let mut references = References::default();
+        references.visit_program(program)?;
+        Ok(references)
+    }
+}
+
+impl ExprVisitor for References {
+    fn visit_expr(&mut self, expr: Expr) -> Result<Expr> {
+        match &expr {
+            Expr::Identifier(name) => {
+                self.variables.insert(name.clone());
+            }
+            Expr::GetOutputFrom(name) => {
+                if let Expr::String(name) = name.as_ref() {
+                    self.formulas.insert(name.clone());
+                }
+            }
+            Expr::FunctionCall { name, args } => {
+                self.functions.insert((name.clone(), args.len()));
+            }
+            _ => {}
+        }
+        Ok(expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_collect_references() {
+        let program = Parser::new(
+            "if (rate > 0) then return get_output_from('base') * rate else return bonus(rate, 2) end",
+        )
+        .unwrap()
+        .parse()
+        .unwrap();
+
+        let references = References::collect(program).unwrap();
+        assert_eq!(
+            references.variables.into_iter().collect::<Vec<_>>(),
+            vec!["rate".to_string()]
+        );
+        assert_eq!(
+            references.formulas.into_iter().collect::<Vec<_>>(),
+            vec!["base".to_string()]
+        );
+        assert_eq!(
+            references.functions.into_iter().collect::<Vec<_>>(),
+            vec![("bonus".to_string(), 2)]
+        );
+    }
+}
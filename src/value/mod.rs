@@ -1,7 +1,11 @@
+use chrono::{NaiveDate, NaiveDateTime};
 use std::cmp::Ordering;
 use std::fmt;
 
-/// Represents a value that can be a string, number, or boolean.
+/// Canonical format used to render [`Value::Date`] values.
+pub(crate) const DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+/// Represents a value that can be a string, number, boolean, date, array, or null.
 ///
 /// This is the primary data type for all values in the formula engine,
 /// including variables, function parameters, and formula results.
@@ -20,13 +24,28 @@ use std::fmt;
 /// assert_eq!(flag.as_bool(), Some(true));
 /// ```
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Value {
     /// A string value
     String(String),
     /// A numeric value (f64)
+    ///
+    /// With the `serde` feature, `NaN` and the infinities serialize as the strings
+    /// `"NaN"`, `"inf"`, and `"-inf"`, since formats like JSON have no such numbers.
+    #[cfg_attr(feature = "serde", serde(with = "number_serde"))]
     Number(f64),
     /// A boolean value
     Bool(bool),
+    /// A date and time value, rendered as `YYYY-MM-DDTHH:MM:SS`
+    Date(NaiveDateTime),
+    /// An ordered list of values, rendered as `[a, b, c]`
+    Array(Vec<Value>),
+    /// An absent value, rendered as `null`.
+    ///
+    /// Null equals only null and is unordered against every other value. Formulas
+    /// can pass it along and test it with `=`, but arithmetic, concatenation, and
+    /// built-ins that expect a specific type reject it with a type error.
+    Null,
 }
 
 impl Value {
@@ -45,6 +64,21 @@ impl Value {
         matches!(self, Value::Bool(_))
     }
 
+    /// Returns `true` if the value is a date.
+    pub fn is_date(&self) -> bool {
+        matches!(self, Value::Date(_))
+    }
+
+    /// Returns `true` if the value is an array.
+    pub fn is_array(&self) -> bool {
+        matches!(self, Value::Array(_))
+    }
+
+    /// Returns `true` if the value is null.
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
     /// Returns the value as a string slice if it is a string, or `None` otherwise.
     pub fn as_string(&self) -> Option<&str> {
         match self {
@@ -69,12 +103,30 @@ impl Value {
         }
     }
 
+    /// Returns the value as a date if it is a date, or `None` otherwise.
+    pub fn as_date(&self) -> Option<NaiveDateTime> {
+        match self {
+            Value::Date(d) => Some(*d),
+            _ => None,
+        }
+    }
+
+    /// Returns the elements if the value is an array, or `None` otherwise.
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
     /// Get the underlying value as an object representation
     pub fn get(&self) -> String {
         match self {
             Value::String(s) => s.clone(),
             Value::Number(n) => n.to_string(),
             Value::Bool(b) => b.to_string(),
+            Value::Date(d) => d.format(DATE_FORMAT).to_string(),
+            Value::Array(_) | Value::Null => self.to_string(),
         }
     }
 }
@@ -85,6 +137,9 @@ impl PartialOrd for Value {
             (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
             (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
             (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            (Value::Date(a), Value::Date(b)) => Some(a.cmp(b)),
+            (Value::Array(a), Value::Array(b)) => a.partial_cmp(b),
+            (Value::Null, Value::Null) => Some(Ordering::Equal),
             _ => None,
         }
     }
@@ -96,6 +151,18 @@ impl fmt::Display for Value {
             Value::String(s) => write!(f, "{}", s),
             Value::Number(n) => write!(f, "{}", n),
             Value::Bool(b) => write!(f, "{}", b),
+            Value::Date(d) => write!(f, "{}", d.format(DATE_FORMAT)),
+            Value::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Null => write!(f, "null"),
         }
     }
 }
@@ -124,6 +191,76 @@ impl From<bool> for Value {
     }
 }
 
+impl From<NaiveDateTime> for Value {
+    fn from(d: NaiveDateTime) -> Self {
+        Value::Date(d)
+    }
+}
+
+impl From<NaiveDate> for Value {
+    fn from(d: NaiveDate) -> Self {
+        Value::Date(d.and_hms_opt(0, 0, 0).unwrap())
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Self {
+        Value::Array(items.into_iter().map(Into::into).collect())
+    }
+}
+
+/// Serializes numbers so that non-finite values survive a JSON round trip.
+#[cfg(feature = "serde")]
+mod number_serde {
+    use serde::de::{self, Visitor};
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+
+    pub fn serialize<S: Serializer>(n: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        if n.is_finite() {
+            serializer.serialize_f64(*n)
+        } else {
+            // `f64`'s Display already renders these as "NaN", "inf", and "-inf"
+            serializer.serialize_str(&n.to_string())
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        deserializer.deserialize_any(NumberVisitor)
+    }
+
+    struct NumberVisitor;
+
+    impl Visitor<'_> for NumberVisitor {
+        type Value = f64;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "a number or one of \"NaN\", \"inf\", \"-inf\"")
+        }
+
+        fn visit_f64<E: de::Error>(self, n: f64) -> Result<f64, E> {
+            Ok(n)
+        }
+
+        fn visit_i64<E: de::Error>(self, n: i64) -> Result<f64, E> {
+            Ok(n as f64)
+        }
+
+        fn visit_u64<E: de::Error>(self, n: u64) -> Result<f64, E> {
+            Ok(n as f64)
+        }
+
+        fn visit_str<E: de::Error>(self, s: &str) -> Result<f64, E> {
+            match s {
+                "NaN" => Ok(f64::NAN),
+                "inf" => Ok(f64::INFINITY),
+                "-inf" => Ok(f64::NEG_INFINITY),
+                _ => Err(E::invalid_value(de::Unexpected::Str(s), &self)),
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -160,4 +297,48 @@ mod tests {
         assert_eq!(Value::from("test").to_string(), "test");
         assert_eq!(Value::from(true).to_string(), "true");
     }
+
+    #[test]
+    fn test_value_date() {
+        let earlier = Value::from(NaiveDate::from_ymd_opt(2024, 1, 5).unwrap());
+        let later = Value::from(NaiveDate::from_ymd_opt(2024, 1, 10).unwrap());
+
+        assert!(earlier.is_date());
+        assert!(earlier.as_date().is_some());
+        assert_eq!(earlier.as_string(), None);
+        assert!(earlier < later);
+        assert_eq!(earlier.to_string(), "2024-01-05T00:00:00");
+        assert_eq!(earlier.get(), "2024-01-05T00:00:00");
+    }
+
+    #[test]
+    fn test_value_array() {
+        let array = Value::from(vec![1.0, 2.5]);
+        assert!(array.is_array());
+        assert_eq!(
+            array.as_array(),
+            Some(&[Value::Number(1.0), Value::Number(2.5)][..])
+        );
+        assert_eq!(array.to_string(), "[1, 2.5]");
+        assert_eq!(Value::Array(vec![]).get(), "[]");
+        assert!(Value::from(vec![1.0, 2.0]) < Value::from(vec![1.0, 3.0]));
+    }
+
+    #[test]
+    fn test_value_null() {
+        let null = Value::Null;
+        assert!(null.is_null());
+        assert!(!Value::from(0.0).is_null());
+        assert_eq!(null.as_number(), None);
+        assert_eq!(null.as_string(), None);
+        assert_eq!(null.as_bool(), None);
+
+        assert_eq!(null.to_string(), "null");
+        assert_eq!(null.get(), "null");
+        assert_eq!(Value::Array(vec![Value::Null]).to_string(), "[null]");
+
+        assert_eq!(null.partial_cmp(&Value::Null), Some(Ordering::Equal));
+        assert_eq!(null.partial_cmp(&Value::from(1.0)), None);
+        assert_ne!(null, Value::from(""));
+    }
 }
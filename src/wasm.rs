@@ -1,4 +1,5 @@
-use crate::{Engine as CoreEngine, Formula as CoreFormula, Value as CoreValue};
+use crate::parser::Parser;
+use crate::{Engine as CoreEngine, Value as CoreValue};
 use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
 
@@ -19,13 +20,14 @@ impl Engine {
     }
 
     /// Evaluate a simple expression with variables
-    /// Returns the result as a number
+    /// Returns numbers and booleans as themselves, strings as strings, dates as
+    /// `YYYY-MM-DDTHH:MM:SS` strings, and null as `null`
     #[wasm_bindgen(js_name = evaluateExpression)]
     pub fn evaluate_expression(
         &mut self,
         expression: &str,
         variables: JsValue,
-    ) -> Result<f64, JsValue> {
+    ) -> Result<JsValue, JsValue> {
         // Parse variables from JavaScript object
         let vars: HashMap<String, f64> = serde_wasm_bindgen::from_value(variables)
             .map_err(|e| JsValue::from_str(&format!("Invalid variables: {}", e)))?;
@@ -35,36 +37,26 @@ impl Engine {
             self.inner.set_variable(key, CoreValue::Number(value));
         }
 
-        // Create a temporary formula
-        let formula = CoreFormula::new("_temp", &format!("return {}", expression));
-
-        // Execute formula
-        self.inner
-            .execute(vec![formula])
-            .map_err(|e| JsValue::from_str(&format!("Execution error: {}", e)))?;
-
-        // Get result
         let result = self
             .inner
-            .get_result("_temp")
-            .ok_or_else(|| JsValue::from_str("No result found"))?;
+            .evaluate(expression)
+            .map_err(|e| JsValue::from_str(&format!("Execution error: {}", e)))?;
 
-        // Convert to number
         match result {
-            CoreValue::Number(n) => Ok(n),
-            _ => Err(JsValue::from_str("Result is not a number")),
+            CoreValue::Number(n) => Ok(JsValue::from_f64(n)),
+            CoreValue::Bool(b) => Ok(JsValue::from_bool(b)),
+            CoreValue::String(_) | CoreValue::Date(_) => Ok(JsValue::from_str(&result.get())),
+            CoreValue::Null => Ok(JsValue::NULL),
+            CoreValue::Array(_) => Err(JsValue::from_str("Array results are not supported")),
         }
     }
 
     /// Validate an expression syntax
     #[wasm_bindgen(js_name = validateExpression)]
     pub fn validate_expression(&self, expression: &str) -> bool {
-        // Try to create a formula - if it fails, syntax is invalid
-        let formula = CoreFormula::new("_test", &format!("return {}", expression));
-
-        // Create a temporary engine to test
-        let mut test_engine = CoreEngine::new();
-        test_engine.execute(vec![formula]).is_ok()
+        Parser::new(expression)
+            .and_then(|mut parser| parser.parse_expression())
+            .is_ok()
     }
 }
 
@@ -89,13 +81,8 @@ impl Formula {
     /// Parse and validate a formula expression
     #[wasm_bindgen(js_name = parse)]
     pub fn parse(expression: &str) -> Result<Formula, JsValue> {
-        // Try to create a formula to validate syntax
-        let formula = CoreFormula::new("_parse_test", &format!("return {}", expression));
-
-        // Test if it's valid
-        let mut test_engine = CoreEngine::new();
-        test_engine
-            .execute(vec![formula])
+        Parser::new(expression)
+            .and_then(|mut parser| parser.parse_expression())
             .map_err(|e| JsValue::from_str(&format!("Parse error: {}", e)))?;
 
         Ok(Formula {